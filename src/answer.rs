@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chatgpt::types::{ChatMessage, Role};
+use url::Url;
+
+use serenity::builder::{CreateMessage, EditMessage};
+use serenity::model::channel::AttachmentType;
+
+use crate::utils::{
+    config::config,
+    conversations::{is_conversation_channel, Conversation, ConversationSummary, Turn},
+    gpt::{get_gpt_history_from_messages, send_gpt_message_with_tools, tools::{tools, GenerateImage, SearchThread, Tool, ToolContext}},
+    guilds::GuildSettings,
+    knowledge::KnowledgeBase,
+    log::log_to_file,
+    memory::UserMemory,
+    moderation::{moderate, ContentKind, ModerationTarget, ModerationVerdict},
+    permissions::UserPermissions,
+    stats::Stats,
+};
+
+/// Who asks the model and where, the same for new messages, edited questions and the answer buttons
+pub struct AnswerRequest<'a> {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub user_id: u64,
+    pub model: &'a str,
+    pub permissions: &'a UserPermissions,
+}
+
+impl AnswerRequest<'_> {
+    pub fn target(&self) -> ModerationTarget {
+        ModerationTarget { guild_id: self.guild_id, user_id: self.user_id, channel_id: self.channel_id }
+    }
+}
+
+/// Outcome of `ask`
+pub enum Answer {
+    Text {
+        text: String,
+        /// URLs of the images generated by tools
+        images: Vec<String>,
+    },
+    /// The answer was stopped by the moderation policy
+    Blocked,
+    /// The request failed, the error is already logged
+    Failed,
+}
+
+impl Answer {
+    /// Text to post, the configured notice if there is no answer
+    pub fn text(&self) -> String {
+        match self {
+            Answer::Text { text, .. } => text.to_owned(),
+            Answer::Blocked => config().texts.moderation_answer_blocked.to_string(),
+            Answer::Failed => config().texts.chat_error.to_string(),
+        }
+    }
+
+    pub fn images(&self) -> &[String] {
+        match self {
+            Answer::Text { images, .. } => images,
+            _ => &[],
+        }
+    }
+}
+
+/// Tools the model may call in the guild, direct messages use the default set.
+/// Image generation is left out for members who may not generate images,
+/// the thread search for channels without a stored conversation.
+async fn enabled_tools(guild_id: u64, channel_id: u64, permissions: &UserPermissions) -> Vec<&'static dyn Tool> {
+    let mut enabled = match GuildSettings::load(guild_id).await {
+        Ok(settings) => tools().enabled(&settings.tools),
+        Err(_) => vec![]
+    };
+    if !permissions.images {
+        enabled.retain(|tool| tool.name() != GenerateImage.name());
+    }
+    if is_conversation_channel(channel_id) != Some(true) {
+        enabled.retain(|tool| tool.name() != SearchThread.name());
+    }
+    enabled
+}
+
+/// System message with the facts the user stored with /memory
+pub async fn memory_message(user_id: u64) -> Option<ChatMessage> {
+    match UserMemory::load(user_id).await {
+        Ok(memory) => memory.prompt().map(|content| ChatMessage { role: Role::System, content }),
+        Err(_) => None
+    }
+}
+
+/// History for answering `question` in a conversation: the memory facts of the user, the excerpts
+/// of the knowledge base if the thread uses it, then the summary and the newest of `turns`
+pub async fn conversation_history(
+    conversation: &Conversation, turns: &[Turn], summary: Option<&ConversationSummary>, user_id: u64, question: &str,
+    messages: &Arc<Mutex<Vec<String>>>
+) -> Vec<ChatMessage> {
+    let mut history = get_gpt_history_from_messages(turns, summary, config().limits.history_messages as usize);
+
+    if conversation.settings.knowledge {
+        match KnowledgeBase::prompt_for(conversation.guild_id, question).await {
+            Ok(Some(prompt)) => history.insert(0, ChatMessage { role: Role::System, content: prompt }),
+            Ok(None) => {},
+            Err(e) => {
+                log_to_file(&format!("[WARN] - Knowledge base lookup failed: {}", e), messages)
+                    .await.unwrap();
+            }
+        }
+    }
+    if let Some(memory) = memory_message(user_id).await {
+        history.insert(0, memory);
+    }
+
+    history
+}
+
+/// Asks the model with the tools enabled for the request and checks the answer with the moderation policy
+pub async fn ask(
+    request: &AnswerRequest<'_>, history: Vec<ChatMessage>, messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+) -> Answer {
+    let tool_context = ToolContext::new(
+        request.guild_id, request.channel_id, request.user_id, request.permissions, messages, stats
+    );
+    let enabled_tools = enabled_tools(request.guild_id, request.channel_id, request.permissions).await;
+
+    let started_at = Instant::now();
+    let reply = match send_gpt_message_with_tools(request.model, history, &enabled_tools, &tool_context).await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - GPT request failed: {:#?}", e), messages)
+                .await.unwrap();
+            stats.record_error();
+            return Answer::Failed
+        }
+    };
+
+    stats.record_llm_request(started_at.elapsed(), reply.total_tokens);
+
+    if moderate(&request.target(), ContentKind::Answer, &reply.text, messages).await == ModerationVerdict::Blocked {
+        return Answer::Blocked
+    }
+
+    Answer::Text { text: reply.text, images: tool_context.take_attachments() }
+}
+
+/// Attaches the images generated by tools to an answer
+pub fn add_images<'a, 'b>(message: &'b mut CreateMessage<'a>, images: &[String]) -> &'b mut CreateMessage<'a> {
+    for url in images {
+        if let Ok(url) = Url::parse(url) {
+            message.add_file(AttachmentType::Image(url));
+        }
+    }
+    message
+}
+
+/// Attaches the images generated by tools to a regenerated answer
+pub fn attach_images<'a, 'b>(message: &'b mut EditMessage<'a>, images: &[String]) -> &'b mut EditMessage<'a> {
+    for url in images {
+        if let Ok(url) = Url::parse(url) {
+            message.attachment(AttachmentType::Image(url));
+        }
+    }
+    message
+}
@@ -0,0 +1,521 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+
+use crate::utils::{
+    audit::{AuditLog, AuditRecord},
+    config::config,
+    conversations::Conversation,
+    datastorage::Users,
+    guilds::GuildSettings,
+    log::log_to_file,
+    permissions::{can_manage_guild, role_ids, UserPermissions, UserQuota},
+    usage::DailyUsage,
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::model::channel::ChannelType;
+use serenity::model::id::ChannelId;
+use serenity::model::permissions::Permissions;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{
+    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+};
+
+/// Discord limit for the content of a message
+static MESSAGE_LIMIT: usize = 2000;
+
+pub struct Admin;
+
+#[async_trait]
+impl SlashCommand for Admin {
+    fn name(&self) -> &'static str {
+        "admin"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Manage the users of the bot")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("user")
+                    .description("Look at or change what is stored about a user")
+                    .kind(CommandOptionType::SubCommandGroup)
+                    .create_sub_option(|option| {
+                        option
+                            .name("show")
+                            .description("Show the model, ban and usage of a user")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("set-model")
+                            .description("Change the model of a user on every server (bot owners only)")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                            .create_sub_option(|option| {
+                                option
+                                    .name("model")
+                                    .description("The new model")
+                                    .kind(CommandOptionType::String)
+                                    .required(true);
+
+                                for model in &config().models.available {
+                                    option.add_string_choice(&model.label, &model.name);
+                                }
+
+                                option
+                            })
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("reset")
+                            .description("Reset the model and the usage of today of a user (bot owners only)")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("ban")
+                            .description("Stop answering a user on every server (bot owners only)")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                            .create_sub_option(|option| {
+                                option
+                                    .name("reason")
+                                    .description("Kept in the audit trail")
+                                    .kind(CommandOptionType::String)
+                                    .max_length(200)
+                                    .required(false)
+                            })
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("unban")
+                            .description("Answer a banned user again (bot owners only)")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("quota")
+                            .description("Show or change the daily requests of a user")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| user_option(option))
+                            .create_sub_option(|option| {
+                                option
+                                    .name("daily_requests")
+                                    .description("Requests per day for this user, 0 for unlimited")
+                                    .kind(CommandOptionType::Integer)
+                                    .min_int_value(0)
+                                    .required(false)
+                            })
+                            .create_sub_option(|option| {
+                                option
+                                    .name("reset_usage")
+                                    .description("Forget the requests of today (bot owners only)")
+                                    .kind(CommandOptionType::Boolean)
+                                    .required(false)
+                            })
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("stats")
+                    .description("Show the counters of the bot (bot owners only)")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("broadcast")
+                    .description("Post an announcement to a channel or to every chat thread of this server")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("message")
+                            .description("The announcement")
+                            .kind(CommandOptionType::String)
+                            .max_length(MESSAGE_LIMIT as u16)
+                            .required(true)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("channel")
+                            .description("Channel to post to, every chat thread if not set")
+                            .kind(CommandOptionType::Channel)
+                            .channel_types(&[ChannelType::Text])
+                            .required(false)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("audit")
+                    .description("Show the latest actions taken with /admin")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("count")
+                            .description("Amount of actions")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(20)
+                            .required(false)
+                    })
+            })
+    }
+
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        let _command = context.command;
+
+        let guild_id = match _command.guild_id {
+            Some(v) => v.as_u64().to_owned(),
+            None => return CommandResponse::ephemeral("This command only works on a server.")
+        };
+
+        // Members without Manage Server need a permission rule that names the command.
+        if !can_manage_guild(_command.member.as_ref()) {
+            let permissions = UserPermissions::of_member(_command.guild_id, _command.user.id, _command.member.as_ref()).await;
+            if !permissions.commands.as_ref().is_some_and(|commands| commands.contains(self.name())) {
+                return CommandResponse::ephemeral("You are not allowed to use /admin here.")
+            }
+        }
+
+        let option = match _command.data.options.first() {
+            Some(v) => v,
+            None => return CommandResponse::ephemeral("Unknown subcommand.")
+        };
+
+        let reply = match option.name.as_str() {
+            "user" => match option.options.first() {
+                Some(subcommand) => run_user_subcommand(context.messages, _command, subcommand, guild_id).await,
+                None => "Unknown subcommand.".to_string()
+            },
+            "stats" if !is_bot_owner(_command.user.id.0) => "The counters cover every server, only the owners of the bot may see them.".to_string(),
+            "stats" => show_stats(context).await,
+            "broadcast" => broadcast(context, option, guild_id).await,
+            "audit" => {
+                let count = sub_option(option, "count").and_then(|v| v.as_u64()).unwrap_or(10);
+                match AuditLog::load(guild_id).await {
+                    Ok(log) => describe_records(&log, count as usize),
+                    Err(e) => {
+                        log_to_file(&format!("[ERROR] - Cannot load audit log: {}", e), context.messages)
+                            .await.unwrap();
+                        "Error in datastorage.".to_string()
+                    }
+                }
+            },
+            _ => "Unknown subcommand.".to_string(),
+        };
+
+        CommandResponse::ephemeral(reply)
+    }
+}
+
+fn user_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("user")
+        .description("The user")
+        .kind(CommandOptionType::User)
+        .required(true)
+}
+
+fn sub_option<'a>(subcommand: &'a CommandDataOption, name: &str) -> Option<&'a serenity::json::Value> {
+    subcommand.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+}
+
+/// Id and role ids of the user chosen in the `user` option
+fn target_of(subcommand: &CommandDataOption) -> Option<(u64, Vec<u64>)> {
+    let option = subcommand.options.iter().find(|option| option.name == "user")?;
+
+    match option.resolved.as_ref()? {
+        CommandDataOptionValue::User(user, member) => {
+            Some((user.id.0, member.as_ref().map(|m| role_ids(&m.roles)).unwrap_or_default()))
+        },
+        _ => None,
+    }
+}
+
+/// Applies `f` to the stored users and saves them
+async fn update_users<R>(f: impl FnOnce(&mut Users) -> R) -> Result<R, String> {
+    Users::update(f).await.map_err(|e| e.to_string())
+}
+
+/// Models and bans are shared by every server, so only the owners of the bot may change them
+fn is_bot_owner(user_id: u64) -> bool {
+    config().discord.owners.contains(&user_id)
+}
+
+async fn audit(guild_id: u64, record: AuditRecord, _messages: &Arc<Mutex<Vec<String>>>) {
+    log_to_file(
+        &format!("[INFO] - Admin {} used /admin {} on {:?}: {}", record.admin_id, record.action, record.target_id, record.details),
+        _messages
+    ).await.unwrap();
+
+    if let Err(e) = AuditLog::append(guild_id, record).await {
+        log_to_file(&format!("[ERROR] - Cannot save audit record: {}", e), _messages)
+            .await.unwrap();
+    }
+}
+
+async fn show_user(guild_id: u64, user_id: u64, roles: &[u64]) -> Result<String, String> {
+    let config = config();
+
+    let (model, banned) = match Users::default().await {
+        Ok(users) => users.find_user_by_id(user_id).map_or((config.models.default.to_owned(), false), |user| (user.model.to_owned(), user.banned)),
+        Err(e) => return Err(e.to_string())
+    };
+    let usage = DailyUsage::load(user_id).await.map_err(|e| e.to_string())?;
+    let permissions = UserPermissions::load(guild_id, user_id, roles).await;
+
+    let models = match &permissions.models {
+        None => "all".to_string(),
+        Some(models) => {
+            let mut labels: Vec<&str> = models.iter().map(|name| config.model_label(name)).collect();
+            labels.sort();
+            labels.join(", ")
+        }
+    };
+
+    Ok(format!(
+        "User: <@{}>\nModel: {}\nBanned: {}\nRequests today: {} of {}\nAllowed models: {}\nImages: {}",
+        user_id,
+        config.model_label(model.trim_matches('"')),
+        if banned { "yes" } else { "no" },
+        usage.requests,
+        permissions.daily_requests.map_or("unlimited".to_string(), |v| v.to_string()),
+        models,
+        if permissions.images { "allowed" } else { "not allowed" },
+    ))
+}
+
+async fn run_user_subcommand(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, subcommand: &CommandDataOption, guild_id: u64
+) -> String {
+    let admin_id = _command.user.id.as_u64().to_owned();
+
+    let (user_id, roles) = match target_of(subcommand) {
+        Some(v) => v,
+        None => return "Choose a user.".to_string()
+    };
+
+    let action = format!("user {}", subcommand.name);
+
+    let resets_usage = sub_option(subcommand, "reset_usage").and_then(|v| v.as_bool()).unwrap_or(false);
+    let changes_bot_data = matches!(subcommand.name.as_str(), "set-model" | "reset" | "ban" | "unban")
+        || (subcommand.name == "quota" && resets_usage);
+    if changes_bot_data && !is_bot_owner(admin_id) {
+        return "The model, the ban and the usage of a user apply on every server, only the owners of the bot may change them.".to_string();
+    }
+
+    let res: Result<(String, Option<String>), String> = match subcommand.name.as_str() {
+        "show" => show_user(guild_id, user_id, &roles).await.map(|reply| (reply, None)),
+        "set-model" => {
+            let model = sub_option(subcommand, "model").and_then(|v| v.as_str()).unwrap_or_default().to_owned();
+            if !config().models.available.iter().any(|m| m.name == model) {
+                return format!("Unknown model `{}`.", model);
+            }
+
+            update_users(|users| users.set_model(user_id, model.to_owned())).await.map(|_| {
+                (format!("<@{}> now uses {}.", user_id, config().model_label(&model)), Some(format!("model {}", model)))
+            })
+        },
+        "reset" => {
+            // A ban outlives the reset, only the model goes back to the default.
+            let default_model = config().models.default.to_owned();
+            let res = update_users(|users| {
+                match users.find_user_by_id(user_id).map(|user| user.banned) {
+                    Some(true) => {
+                        users.update_user(user_id, default_model);
+                    },
+                    Some(false) => {
+                        users.delete_user(user_id);
+                    },
+                    None => {},
+                }
+            }).await;
+
+            match res {
+                Ok(()) => DailyUsage::delete(user_id).await
+                    .map(|_| (format!("The model and the usage of <@{}> are reset.", user_id), Some(String::new())))
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            }
+        },
+        "ban" | "unban" => {
+            let banned = subcommand.name == "ban";
+            if banned && user_id == admin_id {
+                return "You can't ban yourself.".to_string();
+            }
+            let reason = sub_option(subcommand, "reason").and_then(|v| v.as_str()).unwrap_or_default().to_owned();
+
+            update_users(|users| users.set_banned(user_id, banned)).await.map(|_| {
+                let reply = if banned { format!("<@{}> is banned.", user_id) } else { format!("<@{}> is not banned anymore.", user_id) };
+                (reply, Some(reason))
+            })
+        },
+        "quota" => {
+            let daily_requests = sub_option(subcommand, "daily_requests").and_then(|v| v.as_u64());
+
+            let mut details = vec![];
+
+            // The quota replaces the daily requests of the rules, so it can also lower them.
+            if let Some(limit) = daily_requests {
+                let res = GuildSettings::update(guild_id, |settings| {
+                    let quota = UserQuota { user_id, daily_requests: (limit > 0).then_some(limit) };
+                    match settings.quotas.iter_mut().find(|quota| quota.user_id == user_id) {
+                        Some(v) => *v = quota,
+                        None => settings.quotas.push(quota),
+                    }
+                }).await;
+
+                if let Err(e) = res {
+                    log_to_file(&format!("[ERROR] - Cannot update guild settings: {}", e), _messages)
+                        .await.unwrap();
+                    return "Error in datastorage.".to_string();
+                }
+                details.push(format!("daily requests {}", if limit > 0 { limit.to_string() } else { "unlimited".to_string() }));
+            }
+            if resets_usage {
+                if let Err(e) = DailyUsage::delete(user_id).await {
+                    log_to_file(&format!("[ERROR] - Cannot reset usage: {}", e), _messages)
+                        .await.unwrap();
+                    return "Error in datastorage.".to_string();
+                }
+                details.push("usage reset".to_string());
+            }
+
+            show_user(guild_id, user_id, &roles).await.map(|reply| {
+                let changed = !details.is_empty();
+                (reply, changed.then(|| details.join(", ")))
+            })
+        },
+        _ => return "Unknown subcommand.".to_string(),
+    };
+
+    match res {
+        Ok((reply, details)) => {
+            if let Some(details) = details {
+                audit(guild_id, AuditRecord::new(admin_id, &action, Some(user_id), &details), _messages).await;
+            }
+            reply
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot run /admin {}: {}", action, e), _messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    }
+}
+
+async fn show_stats(context: &CommandContext<'_>) -> String {
+    let snapshot = context.stats.snapshot();
+
+    let (users, banned) = match Users::default().await {
+        Ok(users) => (users.users.len(), users.users.iter().filter(|user| user.banned).count()),
+        Err(_) => (0, 0)
+    };
+    let conversations = Conversation::list_ids().await.map_or(0, |ids| ids.len());
+
+    format!(
+        "Shard: {}{}\nServers: {}\nUsers: {} ({} banned)\nStored conversations: {}\nActive threads: {}\nMessages per minute: {}\n\
+         Chat requests: {} (average {})\nTokens today: {}\nImage requests: {}\nErrors: {}",
+        snapshot.shard_status,
+        snapshot.shard_latency.map_or(String::new(), |v| format!(" ({} ms)", v.as_millis())),
+        snapshot.guilds,
+        users,
+        banned,
+        conversations,
+        snapshot.active_threads,
+        snapshot.messages_per_minute,
+        snapshot.llm_requests,
+        snapshot.average_llm_latency.map_or("-".to_string(), |v| format!("{} ms", v.as_millis())),
+        snapshot.tokens_today,
+        snapshot.image_requests,
+        snapshot.errors,
+    )
+}
+
+async fn broadcast(context: &CommandContext<'_>, option: &CommandDataOption, guild_id: u64) -> String {
+    let text = sub_option(option, "message").and_then(|v| v.as_str()).unwrap_or_default().to_owned();
+    let channel = sub_option(option, "channel").and_then(|v| v.as_str()).and_then(|v| v.parse::<u64>().ok());
+
+    if text.trim().is_empty() {
+        return "The message is empty.".to_string();
+    }
+
+    let channels = match channel {
+        Some(v) => vec![v],
+        None => {
+            let ids = match Conversation::list_ids().await {
+                Ok(v) => v,
+                Err(e) => {
+                    log_to_file(&format!("[ERROR] - Cannot list conversations: {}", e), context.messages)
+                        .await.unwrap();
+                    return "Error in datastorage.".to_string()
+                }
+            };
+
+            let mut threads = vec![];
+            for id in ids {
+                if let Ok(Some(conversation)) = Conversation::load(id).await {
+                    if conversation.guild_id == guild_id {
+                        threads.push(id);
+                    }
+                }
+            }
+            threads
+        }
+    };
+
+    let mut sent = 0;
+    for channel_id in &channels {
+        match ChannelId(*channel_id).say(&context.ctx.http, &text).await {
+            Ok(_) => sent += 1,
+            Err(e) => log_to_file(&format!("[WARN] - Cannot broadcast to {}: {}", channel_id, e), context.messages)
+                .await.unwrap(),
+        }
+    }
+
+    let details = format!("{} of {} channels: {}", sent, channels.len(), text.chars().take(200).collect::<String>());
+    audit(guild_id, AuditRecord::new(context.command.user.id.0, "broadcast", None, &details), context.messages).await;
+
+    format!("The announcement was posted to {} of {} channels.", sent, channels.len())
+}
+
+fn describe_records(log: &AuditLog, count: usize) -> String {
+    if log.records.is_empty() {
+        return "No actions were taken yet.".to_string();
+    }
+
+    log.records
+        .iter()
+        .rev()
+        .take(count)
+        .map(|record| {
+            let time = Utc.timestamp_millis_opt(record.timestamp).single()
+                .map_or(String::new(), |v| v.format("%Y-%m-%d %H:%M").to_string());
+            let target = record.target_id.map_or(String::new(), |id| format!(" on <@{}>", id));
+            let details = if record.details.is_empty() { String::new() } else { format!(": {}", record.details) };
+
+            format!("`{}` <@{}> {}{}{}", time, record.admin_id, record.action, target, details)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .chars()
+        .take(MESSAGE_LIMIT)
+        .collect()
+}
@@ -1,81 +1,272 @@
 use std::sync::{Arc, Mutex};
 
+use crate::utils::{
+    config::config, conversations::Conversation, import::{parse_transcript, Transcript, MAX_TRANSCRIPT_BYTES},
+    log::{log_to_file, show_in_tui},
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
 use serenity::builder::CreateApplicationCommand;
 use serenity::json::{JsonMap, json};
-use serenity::model::channel::Message;
+use serenity::model::channel::{Attachment, ChannelType, Message};
+use serenity::model::id::ChannelId;
 use serenity::model::prelude::command::CommandOptionType;
-use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOptionValue};
 use serenity::prelude::Context;
 // use serenity::model::prelude::interaction::application_command::CommandDataOption;
 
-async fn create_new_thread(_ctx: &Context, _command: &ApplicationCommandInteraction, _message: Message, _title: String) {
+/// Discord limit for the name of a thread
+pub(crate) static THREAD_NAME_LIMIT: usize = 100;
+/// Name of threads created without a title, until the title is generated
+static PLACEHOLDER_TITLE: &str = "New chat";
+
+/// Starts a thread on `_message`, or a private thread in `_parent` inviting just the caller
+/// if there is no message, and records an empty conversation for it
+pub(crate) async fn create_new_thread(
+    _ctx: &Context, _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction,
+    _parent: ChannelId, _message: Option<Message>, _title: String
+) -> Option<Conversation> {
+    let _title: String = _title.chars().take(THREAD_NAME_LIMIT).collect();
+
     let mut options = JsonMap::new();
 
     options.insert("name".to_string(), json!(_title));
 
-    let _ = match _ctx.http.create_public_thread(
-        _message.channel_id.as_u64().to_owned(),
-        _message.id.as_u64().to_owned(),
-        &options
-    ).await {
+    let res = match &_message {
+        Some(message) => _ctx.http.create_public_thread(
+            message.channel_id.as_u64().to_owned(),
+            message.id.as_u64().to_owned(),
+            &options
+        ).await,
+        None => {
+            options.insert("type".to_string(), json!(ChannelType::PrivateThread as u8));
+            // Only the bot may add more members.
+            options.insert("invitable".to_string(), json!(false));
+
+            _ctx.http.create_private_thread(_parent.as_u64().to_owned(), &options).await
+        }
+    };
+
+    let thread = match res {
         Ok(v) => v,
         Err(e) => {
-            println!("Cannot create new thread: {:#?}", e);
-            return
+            log_to_file(&format!("[ERROR] - Cannot create new thread: {:#?}", e), _messages)
+                .await.unwrap();
+            return None
         }
     };
+
+    if _message.is_none() {
+        if let Err(e) = _ctx.http.add_thread_channel_member(
+            thread.id.as_u64().to_owned(), _command.user.id.as_u64().to_owned()
+        ).await {
+            log_to_file(&format!("[ERROR] - Cannot add the user to the private thread: {:#?}", e), _messages)
+                .await.unwrap();
+            return None
+        }
+    }
+
+    let mut conversation = Conversation::new(
+        thread.id.as_u64().to_owned(),
+        thread.guild_id.as_u64().to_owned(),
+        _command.user.id.as_u64().to_owned(),
+        &_title
+    );
+    conversation.private = _message.is_none();
+
+    if let Err(e) = conversation.save().await {
+        log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), _messages)
+            .await.unwrap();
+        return None
+    }
+
+    Some(conversation)
 }
 
-pub async fn run(_ctx: &Context, _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> String {
-    // println!("{:#?}", _command);
-    {
-        let mut messages_guard = _messages.lock().unwrap();
-        messages_guard.push(format!("[INFO] - Create new thread: {:#?}", _command).to_string());
+pub struct CreateChat;
+
+#[async_trait]
+impl SlashCommand for CreateChat {
+    fn name(&self) -> &'static str {
+        "create_chat"
+    }
+
+    // Posting the message and creating the thread are two requests, which may take a while.
+    fn deferred(&self) -> bool {
+        true
     }
 
-    let title = match &_command                            .data
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("This command creates a separate thread for chatting with ChatGPT")
+            .create_option(|option| {
+                option
+                    .name("title")
+                    .description("Name of the new chat room, generated after the first answer if omitted")
+                    .kind(CommandOptionType::String)
+                    .max_length(THREAD_NAME_LIMIT as u16)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("private")
+                    .description("Create a private thread only you and the bot can see")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("transcript")
+                    .description("Continue a conversation exported by /export (JSON) or conversations.json from ChatGPT")
+                    .kind(CommandOptionType::Attachment)
+                    .required(false)
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(create_chat(context.ctx, context.messages, context.command).await)
+    }
+}
+
+async fn create_chat(_ctx: &Context, _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> String {
+    // println!("{:#?}", _command);
+    show_in_tui(format!("[INFO] - Create new thread: {:#?}", _command), _messages);
+
+    let transcript = match _command.data
         .options
-        .first()
-        .unwrap()
-        .value {
-            Some(v) => v.to_string(),
-            _ => "Untitled".to_string()
+        .iter()
+        .find(|option| option.name == "transcript")
+        .and_then(|option| option.resolved.as_ref()) {
+            Some(CommandDataOptionValue::Attachment(attachment)) => match read_transcript(_messages, _command, attachment).await {
+                Ok(v) => Some(v),
+                Err(e) => return e
+            },
+            _ => None
         };
 
-    let message = match _command
-        .channel_id
-        .send_message(&_ctx.http, |message| {
-            message
-                .content(
-                    format!(
-                        "Создаю новую беседу с названием: {}",
-                        title
-                    )
-                )
-        })
-        .await {
-            Ok(v) => v,
-            Err(e) => {
-                println!("Cannot send message: {:#?}", e);
-                return "There was a server-side error. Please try again later.".to_string()
+    let given_title = _command.data
+        .options
+        .iter()
+        .find(|option| option.name == "title")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .or_else(|| transcript.as_ref().and_then(|transcript| transcript.title.to_owned()));
+
+    let (title, auto_title) = match given_title {
+        Some(v) => (v, false),
+        None => (PLACEHOLDER_TITLE.to_string(), true)
+    };
+
+    let private = _command.data
+        .options
+        .iter()
+        .find(|option| option.name == "private")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false);
+
+    // A private thread is not started from a message, which would be visible to everyone.
+    let message = if private {
+        None
+    } else {
+        match _command
+            .channel_id
+            .send_message(&_ctx.http, |message| {
+                if auto_title {
+                    message.content("Создаю новую беседу")
+                } else {
+                    message
+                        .content(
+                            format!(
+                                "Создаю новую беседу с названием: {}",
+                                title
+                            )
+                        )
+                }
+            })
+            .await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    log_to_file(&format!("[ERROR] - Cannot send message: {:#?}", e), _messages)
+                        .await.unwrap();
+                    return "There was a server-side error. Please try again later.".to_string()
+                }
             }
-        };
+    };
 
-    create_new_thread(_ctx, _command, message, title).await;
+    let mut conversation = match create_new_thread(_ctx, _messages, _command, _command.channel_id, message, title).await {
+        Some(v) => v,
+        None => return "There was a server-side error. Please try again later.".to_string()
+    };
 
-    "Created!".to_string()
-}
+    if private {
+        let intro = format!("<@{}>, это ваша личная беседа, её видите только вы и бот.", _command.user.id);
+        if let Err(e) = ChannelId(conversation.thread_id).say(&_ctx.http, intro).await {
+            log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), _messages)
+                .await.unwrap();
+        }
+    }
 
-pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command
-        .name("create_chat")
-        .description("This command creates a separate thread for chatting with ChatGPT")
-        .create_option(|option| {
-            option
-                .name("title")
-                .description("Name of the new chat room")
-                .kind(CommandOptionType::String)
-                .required(true)
-        })
+    if !auto_title && transcript.is_none() {
+        return format!("Created <#{}>!", conversation.thread_id)
+    }
+
+    conversation.auto_title = auto_title;
+
+    let imported = transcript.map(|transcript| {
+        let imported = transcript.turns.len();
+        conversation.turns = transcript.turns;
+        imported
+    });
+
+    if let Err(e) = conversation.save().await {
+        log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), _messages)
+            .await.unwrap();
+        return "Error in datastorage.".to_string()
+    }
+
+    let imported = match imported {
+        Some(v) => v,
+        None => return format!("Created <#{}>!", conversation.thread_id)
+    };
+
+    let intro = format!("Загружено сообщений из беседы «{}»: {}. Можно продолжать!", conversation.title, imported);
+
+    if let Err(e) = ChannelId(conversation.thread_id).say(&_ctx.http, intro).await {
+        log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), _messages)
+            .await.unwrap();
+    }
+
+    format!("Created <#{}>! Imported {} messages.", conversation.thread_id, imported)
 }
 
+/// Downloads and parses the transcript attached to /create_chat, the error is the reply to the user
+async fn read_transcript(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, attachment: &Attachment
+) -> Result<Transcript, String> {
+    if attachment.size > MAX_TRANSCRIPT_BYTES {
+        return Err(format!("The transcript is too large, at most {} MB are supported.", MAX_TRANSCRIPT_BYTES / 1024 / 1024));
+    }
+
+    let data = match attachment.download().await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot download transcript: {:#?}", e), _messages)
+                .await.unwrap();
+            return Err("Cannot download the transcript. Please try again later.".to_string())
+        }
+    };
+
+    let transcript = parse_transcript(&data, _command.user.id.as_u64().to_owned(), config().discord.bot_id)?;
+
+    log_to_file(
+        &format!("[INFO] - Read {} turns from transcript {}", transcript.turns.len(), attachment.filename), _messages
+    ).await.unwrap();
+
+    Ok(transcript)
+}
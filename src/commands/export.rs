@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{
+    conversations::Conversation,
+    export::{export_file_name, render_conversation, ExportFormat},
+    log::log_to_file,
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+pub struct Export;
+
+#[async_trait]
+impl SlashCommand for Export {
+    fn name(&self) -> &'static str {
+        "export"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Export the conversation in this thread as a file")
+            .create_option(|option| {
+                option
+                    .name("format")
+                    .description("File format, Markdown by default")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("Markdown", "markdown")
+                    .add_string_choice("JSON", "json")
+                    .add_string_choice("HTML", "html")
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        export_conversation(context.messages, context.command).await
+    }
+}
+
+async fn export_conversation(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> CommandResponse {
+    let format = match _command.data.options
+        .first()
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str()) {
+            Some(v) => match v.parse::<ExportFormat>() {
+                Ok(v) => v,
+                Err(e) => return CommandResponse::ephemeral(e)
+            },
+            None => ExportFormat::Markdown
+        };
+
+    let conversation = match Conversation::load(_command.channel_id.as_u64().to_owned()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return CommandResponse::ephemeral("This command only works inside a chat thread."),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot load conversation: {}", e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    log_to_file(
+        &format!("[INFO] - Export thread {} as {:?}", conversation.thread_id, format), _messages
+    ).await.unwrap();
+
+    CommandResponse::ephemeral(format!("Exported {} messages of **{}**.", conversation.turns.len(), conversation.title))
+        .file(
+            export_file_name(&conversation, format),
+            render_conversation(&conversation, format).into_bytes()
+        )
+}
@@ -0,0 +1,36 @@
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+
+use crate::components::forget_me_buttons;
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+pub struct ForgetMe;
+
+#[async_trait]
+impl SlashCommand for ForgetMe {
+    fn name(&self) -> &'static str {
+        "forget_me"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Delete your settings, conversations, memory and usage")
+    }
+
+    fn always_allowed(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        let user_id = context.command.user.id.as_u64().to_owned();
+
+        // Nothing is deleted before the user confirms with the button.
+        CommandResponse::ephemeral(
+            "This deletes your settings, all chat threads you created, your memory and your usage for good. \
+             Download a copy with `/my_data export` first if you want to keep it."
+        )
+            .components(|components| forget_me_buttons(components, user_id))
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{config::config, conversations::Conversation, log::log_to_file};
+
+use super::{create_chat::create_new_thread, CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::id::ChannelId;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+use serenity::prelude::Context;
+
+pub struct Fork;
+
+#[async_trait]
+impl SlashCommand for Fork {
+    fn name(&self) -> &'static str {
+        "fork"
+    }
+
+    // Posts a message, creates a thread and copies the conversation.
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Continue the conversation of this thread in a new thread")
+            .create_option(|option| {
+                option
+                    .name("title")
+                    .description("Name of the new chat room")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("model")
+                    .description("GPT model for the new thread")
+                    .kind(CommandOptionType::String)
+                    .required(false);
+
+                for model in &config().models.available {
+                    option.add_string_choice(&model.label, &model.name);
+                }
+
+                option
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(fork_chat(context.ctx, context.messages, context.command).await)
+    }
+}
+
+fn string_option<'a>(_command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a str> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+async fn fork_chat(_ctx: &Context, _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> String {
+    let source = match Conversation::load(_command.channel_id.as_u64().to_owned()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return "This command only works inside a chat thread.".to_string(),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot load conversation: {}", e), _messages)
+                .await.unwrap();
+            return "Error in datastorage.".to_string()
+        }
+    };
+
+    let parent_id = match _command.channel_id.to_channel(_ctx).await.map(|channel| channel.guild()) {
+        Ok(Some(channel)) => match channel.parent_id {
+            Some(v) => v,
+            None => return "This command only works inside a chat thread.".to_string()
+        },
+        _ => return "There was a server-side error. Please try again later.".to_string()
+    };
+
+    let title = match string_option(_command, "title") {
+        Some(v) => v.to_string(),
+        None => format!("{} (fork)", source.title)
+    };
+
+    let intro = format!("Продолжаю беседу <#{}> в новой ветке: {}", source.thread_id, title);
+
+    // Forks of a private thread stay private, so the intro goes into the new thread instead.
+    let message = if source.private {
+        None
+    } else {
+        match parent_id.say(&_ctx.http, &intro).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot send message: {:#?}", e), _messages)
+                    .await.unwrap();
+                return "There was a server-side error. Please try again later.".to_string()
+            }
+        }
+    };
+
+    let mut conversation = match create_new_thread(_ctx, _messages, _command, parent_id, message, title).await {
+        Some(v) => v,
+        None => return "There was a server-side error. Please try again later.".to_string()
+    };
+
+    conversation.settings = source.settings.to_owned();
+    if let Some(model) = string_option(_command, "model") {
+        conversation.settings.model = Some(model.to_string());
+    }
+    conversation.forked_from = Some(source.thread_id);
+    conversation.turns = source.context_turns().to_vec();
+
+    if let Err(e) = conversation.save().await {
+        log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), _messages)
+            .await.unwrap();
+        return "Error in datastorage.".to_string()
+    }
+
+    if conversation.private {
+        if let Err(e) = ChannelId(conversation.thread_id).say(&_ctx.http, &intro).await {
+            log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), _messages)
+                .await.unwrap();
+        }
+    }
+
+    format!("Forked into <#{}>!", conversation.thread_id)
+}
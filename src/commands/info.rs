@@ -1,60 +1,48 @@
-use std::sync::{Arc, Mutex};
+use crate::utils::{config::config, datastorage::{Users, User}};
 
-use crate::utils::datastorage::{Users, User};
+use super::{CommandContext, CommandResponse, SlashCommand};
 
-use serenity::model::prelude::command::CommandOptionType;
-use serenity::prelude::Context;
+use serenity::async_trait;
 use serenity::builder::CreateApplicationCommand;
 use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
 
-pub async fn run(_ctx: &Context, _command: &ApplicationCommandInteraction, _messages: &Arc<Mutex<Vec<String>>>) -> String {
-    let users = match Users::default().await {
-        Ok(v) => v,
-        Err(_) => {
-            // log_to_file(
-            //     &format!("[ERROR] - Cannot open users.bson: {}", e), _messages
-            // ).await.unwrap();
+pub struct Info;
 
-            return "Error in datastorage.".to_owned()
-        }
-    };
+#[async_trait]
+impl SlashCommand for Info {
+    fn name(&self) -> &'static str {
+        "info"
+    }
 
-    let mut model = "gpt-3.5-turbo";
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Gives information about the currently selected GPT model")
+    }
 
-    let current_user = users.find_user_by_id(_command.user.id.as_u64().to_owned());
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(current_model(context.command).await)
+    }
+}
 
-    if current_user.is_none() {
-        let mut users = match Users::default().await {
-            Ok(v) => v,
-            Err(_) => {
-                return "Error in datastorage.".to_owned()
-            }
-        };
-
-        users.add_user(
-            User {
-                user_id: _command.user.id.as_u64().to_owned(),
-                model: model.to_string()
-            }  
-        );
-
-        match users.write_users_datastorage().await {
-            Ok(_) => {},
-            Err(e) => {
-                let error = &format!("[ERROR] - Cannot update model for user: {}", e);
-                return error.to_owned()
+async fn current_model(_command: &ApplicationCommandInteraction) -> String {
+    let config = config();
+    let user_id = _command.user.id.as_u64().to_owned();
+
+    let res = Users::update(|users| {
+        match users.find_user_by_id(user_id) {
+            Some(user) => user.model.to_owned(),
+            None => {
+                users.add_user(User { user_id, model: config.models.default.to_owned(), banned: false });
+                config.models.default.to_owned()
             }
-        };
-    } else {
-        model = &current_user.unwrap().model;
-    };
+        }
+    }).await;
 
-    format!("The currently selected GPT model: {}", model).to_string()
-}
+    let model = match res {
+        Ok(v) => v,
+        Err(e) => return format!("[ERROR] - Cannot update model for user: {}", e)
+    };
 
-pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command
-        .name("info")
-        .description("Gives information about the currently selected GPT model")
+    format!("The currently selected GPT model: {}", config.model_label(model.trim_matches('"')))
 }
-
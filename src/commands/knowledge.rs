@@ -0,0 +1,238 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+
+use crate::utils::{
+    config::config, conversations::Conversation, knowledge::{extract_text, KnowledgeBase}, log::log_to_file,
+    permissions::can_manage_guild,
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::channel::Attachment;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{
+    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+};
+
+pub struct Knowledge;
+
+#[async_trait]
+impl SlashCommand for Knowledge {
+    fn name(&self) -> &'static str {
+        "knowledge"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Documents the bot can look things up in")
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("add")
+                    .description("Add a .txt, .md or .pdf file to the knowledge base of this server (needs Manage Server)")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("file")
+                            .description("The document, a file with the same name gets replaced")
+                            .kind(CommandOptionType::Attachment)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("Show the documents of the knowledge base")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Remove a document from the knowledge base (needs Manage Server)")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("name")
+                            .description("File name of the document")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("use")
+                    .description("Answer in this thread with the help of the knowledge base")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("enabled")
+                            .description("Whether excerpts of the knowledge base are sent to the model")
+                            .kind(CommandOptionType::Boolean)
+                            .required(true)
+                    })
+            })
+    }
+
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        let guild_id = match context.command.guild_id {
+            Some(v) => v.as_u64().to_owned(),
+            None => return CommandResponse::ephemeral("This command only works on a server.")
+        };
+
+        let subcommand = match context.command.data.options.first() {
+            Some(v) => v,
+            None => return CommandResponse::ephemeral("Unknown subcommand.")
+        };
+
+        match subcommand.name.as_str() {
+            "add" => add_document(context.messages, context.command, subcommand, guild_id).await,
+            "list" => list_documents(context.messages, guild_id).await,
+            "remove" => remove_document(context.messages, context.command, subcommand, guild_id).await,
+            "use" => use_knowledge(context.messages, context.command, subcommand).await,
+            _ => CommandResponse::ephemeral("Unknown subcommand.")
+        }
+    }
+}
+
+fn sub_option<'a>(subcommand: &'a CommandDataOption, name: &str) -> Option<&'a CommandDataOption> {
+    subcommand.options.iter().find(|option| option.name == name)
+}
+
+async fn add_document(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, subcommand: &CommandDataOption, guild_id: u64
+) -> CommandResponse {
+    if !can_manage_guild(_command.member.as_ref()) {
+        return CommandResponse::ephemeral("You need the Manage Server permission to change the knowledge base.");
+    }
+
+    let attachment = match sub_option(subcommand, "file").and_then(|option| option.resolved.as_ref()) {
+        Some(CommandDataOptionValue::Attachment(v)) => v,
+        _ => return CommandResponse::ephemeral("Attach the document to the command.")
+    };
+
+    let text = match read_document(_messages, attachment).await {
+        Ok(v) => v,
+        Err(reply) => return CommandResponse::ephemeral(reply)
+    };
+
+    match KnowledgeBase::add_document(guild_id, &attachment.filename, _command.user.id.as_u64().to_owned(), &text).await {
+        Ok(chunks) => {
+            log_to_file(
+                &format!("[INFO] - Added {} ({} chunks) to the knowledge base of guild {}", attachment.filename, chunks, guild_id),
+                _messages
+            ).await.unwrap();
+            CommandResponse::ephemeral(format!("Added **{}** ({} chunks).", attachment.filename, chunks))
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot add {} to the knowledge base: {}", attachment.filename, e), _messages)
+                .await.unwrap();
+            CommandResponse::ephemeral(format!("Cannot add the document: {}.", e))
+        }
+    }
+}
+
+/// Downloads the attachment and extracts its text, the error is the reply to the user
+async fn read_document(_messages: &Arc<Mutex<Vec<String>>>, attachment: &Attachment) -> Result<String, String> {
+    let max_bytes = config().knowledge.max_file_bytes;
+
+    if attachment.size > max_bytes {
+        return Err(format!("The file is too large, at most {} KB are supported.", max_bytes / 1024));
+    }
+
+    let data = match attachment.download().await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot download document: {:#?}", e), _messages)
+                .await.unwrap();
+            return Err("Cannot download the file. Please try again later.".to_string())
+        }
+    };
+
+    extract_text(&attachment.filename, &data)
+}
+
+async fn list_documents(_messages: &Arc<Mutex<Vec<String>>>, guild_id: u64) -> CommandResponse {
+    let knowledge = match KnowledgeBase::load(guild_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot load knowledge base: {}", e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    if knowledge.documents.is_empty() {
+        return CommandResponse::ephemeral("The knowledge base is empty, add documents with `/knowledge add`.");
+    }
+
+    let lines: Vec<String> = knowledge.documents
+        .iter()
+        .map(|document| {
+            let added_at = Utc.timestamp_millis_opt(document.added_at).single()
+                .map_or(String::new(), |v| v.format("%Y-%m-%d").to_string());
+
+            format!("**{}** · {} chunks · added by <@{}> on {}", document.name, document.chunks.len(), document.added_by, added_at)
+        })
+        .collect();
+
+    CommandResponse::ephemeral(lines.join("\n"))
+}
+
+async fn remove_document(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, subcommand: &CommandDataOption, guild_id: u64
+) -> CommandResponse {
+    if !can_manage_guild(_command.member.as_ref()) {
+        return CommandResponse::ephemeral("You need the Manage Server permission to change the knowledge base.");
+    }
+
+    let name = match sub_option(subcommand, "name").and_then(|option| option.value.as_ref()).and_then(|v| v.as_str()) {
+        Some(v) => v,
+        None => return CommandResponse::ephemeral("Name the document to remove.")
+    };
+
+    match KnowledgeBase::remove_document(guild_id, name).await {
+        Ok(true) => {
+            log_to_file(&format!("[INFO] - Removed {} from the knowledge base of guild {}", name, guild_id), _messages)
+                .await.unwrap();
+            CommandResponse::ephemeral(format!("Removed **{}**.", name))
+        },
+        Ok(false) => CommandResponse::ephemeral(format!("There is no document named **{}**.", name)),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot update knowledge base: {}", e), _messages)
+                .await.unwrap();
+            CommandResponse::ephemeral("Error in datastorage.")
+        }
+    }
+}
+
+async fn use_knowledge(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, subcommand: &CommandDataOption
+) -> CommandResponse {
+    let enabled = sub_option(subcommand, "enabled")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+    let res = Conversation::update(_command.channel_id.as_u64().to_owned(), |conversation| {
+        conversation.settings.knowledge = enabled;
+    }).await;
+
+    match res {
+        Ok(Some(_)) if enabled => CommandResponse::public("Answers in this thread now use the knowledge base of the server."),
+        Ok(Some(_)) => CommandResponse::public("Answers in this thread no longer use the knowledge base."),
+        Ok(None) => CommandResponse::ephemeral("This command only works inside a chat thread."),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), _messages)
+                .await.unwrap();
+            CommandResponse::ephemeral("Error in datastorage.")
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{
+    log::log_to_file,
+    memory::{UserMemory, MAX_FACTS, MAX_FACT_CHARS},
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+
+pub struct Memory;
+
+#[async_trait]
+impl SlashCommand for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Facts about you the bot keeps in mind in all your chats")
+            .create_option(|option| {
+                option
+                    .name("add")
+                    .description("Remember a fact about you")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("fact")
+                            .description("For example: I am a backend developer and mostly write Rust")
+                            .kind(CommandOptionType::String)
+                            .max_length(MAX_FACT_CHARS as u16)
+                            .required(true)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("list")
+                    .description("Show the remembered facts")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("forget")
+                    .description("Forget one fact or everything")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("number")
+                            .description("Number of the fact in /memory list")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(MAX_FACTS as u64)
+                            .required(false)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("everything")
+                            .description("Delete all facts, suggestions and settings of your memory")
+                            .kind(CommandOptionType::Boolean)
+                            .required(false)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("suggestions")
+                    .description("Let the bot suggest facts from your messages, nothing is saved without your confirmation")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("enabled")
+                            .description("Whether facts are suggested")
+                            .kind(CommandOptionType::Boolean)
+                            .required(true)
+                    })
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        let subcommand = match context.command.data.options.first() {
+            Some(v) => v,
+            None => return CommandResponse::ephemeral("Unknown subcommand.")
+        };
+
+        CommandResponse::ephemeral(run_subcommand(context.messages, context.command, subcommand).await)
+    }
+}
+
+fn sub_option<'a>(subcommand: &'a CommandDataOption, name: &str) -> Option<&'a serenity::json::Value> {
+    subcommand.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+}
+
+fn list_facts(memory: &UserMemory) -> String {
+    if memory.facts.is_empty() {
+        return "I don't remember anything about you. Add facts with `/memory add`.".to_string();
+    }
+
+    let facts: Vec<String> = memory.facts
+        .iter()
+        .enumerate()
+        .map(|(i, fact)| format!("**{}.** {}", i + 1, fact.text))
+        .collect();
+
+    format!(
+        "{}\n\nFact suggestions: {}",
+        facts.join("\n"),
+        if memory.suggestions { "on" } else { "off" }
+    )
+}
+
+async fn run_subcommand(
+    _messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction, subcommand: &CommandDataOption
+) -> String {
+    let user_id = _command.user.id.as_u64().to_owned();
+
+    let res = match subcommand.name.as_str() {
+        "add" => {
+            let fact = sub_option(subcommand, "fact").and_then(|v| v.as_str()).unwrap_or_default().to_owned();
+
+            UserMemory::update(user_id, |memory| match memory.add_fact(&fact) {
+                Ok(()) => format!("Remembered. I know {} of {} possible facts about you now.", memory.facts.len(), MAX_FACTS),
+                Err(reply) => reply,
+            }).await
+        },
+        "list" => UserMemory::load(user_id).await.map(|memory| list_facts(&memory)),
+        "forget" => {
+            let number = sub_option(subcommand, "number").and_then(|v| v.as_u64());
+            let everything = sub_option(subcommand, "everything").and_then(|v| v.as_bool()).unwrap_or(false);
+
+            match (number, everything) {
+                (_, true) => UserMemory::delete(user_id).await.map(|_| "Everything I remembered about you is deleted.".to_string()),
+                (Some(number), false) => UserMemory::update(user_id, |memory| {
+                    if number == 0 || number as usize > memory.facts.len() {
+                        return format!("There is no fact number {}.", number);
+                    }
+
+                    let fact = memory.facts.remove(number as usize - 1);
+                    format!("Forgot: {}", fact.text)
+                }).await,
+                (None, false) => Ok("Choose the number of the fact to forget, or set `everything`.".to_string()),
+            }
+        },
+        "suggestions" => {
+            let enabled = sub_option(subcommand, "enabled").and_then(|v| v.as_bool()).unwrap_or(false);
+
+            UserMemory::update(user_id, |memory| {
+                memory.suggestions = enabled;
+                if !enabled {
+                    memory.pending.clear();
+                }
+            }).await.map(|_| {
+                if enabled {
+                    "I will suggest facts from your messages, confirm the ones you want me to remember.".to_string()
+                } else {
+                    "Fact suggestions are off.".to_string()
+                }
+            })
+        },
+        _ => Ok("Unknown subcommand.".to_string()),
+    };
+
+    match res {
+        Ok(reply) => {
+            if subcommand.name != "list" {
+                log_to_file(&format!("[INFO] - User {} used /memory {}", user_id, subcommand.name), _messages)
+                    .await.unwrap();
+            }
+            reply
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot update memory: {}", e), _messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    }
+}
@@ -2,4 +2,384 @@ pub mod ping;
 pub mod info;
 pub mod model;
 pub mod create_chat;
+pub mod reset;
+pub mod fork;
+pub mod summarize;
+pub mod export;
+pub mod settings;
+pub mod knowledge;
+pub mod search;
+pub mod memory;
+pub mod moderation;
+pub mod permissions;
+pub mod admin;
+pub mod my_data;
+pub mod forget_me;
 
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommands, CreateComponents, CreateEmbed};
+use serenity::http::Http;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::channel::AttachmentType;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::id::GuildId;
+use serenity::model::permissions::Permissions;
+use serenity::prelude::Context;
+
+use crate::utils::{
+    config::config, log::log_to_file, permissions::{can_manage_guild, hidden_commands, UserPermissions}, stats::Stats,
+};
+
+/// Discord fails the interaction if it is not acknowledged within 3 seconds,
+/// so commands still running after this delay get deferred automatically.
+static AUTO_DEFER_AFTER: Duration = Duration::from_secs(2);
+
+/// Everything a command needs to handle one interaction
+pub struct CommandContext<'a> {
+    pub ctx: &'a Context,
+    pub command: &'a ApplicationCommandInteraction,
+    /// The commands registered on the server, shared by the whole bot
+    pub registry: &'a CommandRegistry,
+    pub messages: &'a Arc<Mutex<Vec<String>>>,
+    pub stats: &'a Arc<Stats>,
+    /// Set once the interaction got a deferred response, to whether it is ephemeral
+    deferred: tokio::sync::Mutex<Option<bool>>,
+}
+
+impl<'a> CommandContext<'a> {
+    pub fn new(
+        ctx: &'a Context,
+        command: &'a ApplicationCommandInteraction,
+        registry: &'a CommandRegistry,
+        messages: &'a Arc<Mutex<Vec<String>>>,
+        stats: &'a Arc<Stats>,
+    ) -> CommandContext<'a> {
+        CommandContext { ctx, command, registry, messages, stats, deferred: tokio::sync::Mutex::new(None) }
+    }
+
+    /// Acknowledges the interaction with `DeferredChannelMessageWithSource`, so the command can take
+    /// its time. The returned `CommandResponse` then replaces the "thinking" message. Does nothing
+    /// if the interaction is already deferred.
+    pub async fn defer(&self, ephemeral: bool) -> serenity::Result<()> {
+        let mut deferred = self.deferred.lock().await;
+        if deferred.is_some() {
+            return Ok(());
+        }
+
+        self.command
+            .create_interaction_response(&self.ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(ephemeral))
+            })
+            .await?;
+
+        *deferred = Some(ephemeral);
+        Ok(())
+    }
+
+    /// Whether the deferred response is ephemeral, `None` if the interaction is not deferred
+    pub async fn deferred_ephemeral(&self) -> Option<bool> {
+        *self.deferred.lock().await
+    }
+
+    /// Replaces the content of a deferred response while the command is still working
+    pub async fn edit_response<D: ToString>(&self, content: D) -> serenity::Result<()> {
+        self.command
+            .edit_original_interaction_response(&self.ctx.http, |message| message.content(content))
+            .await
+            .map(|_| ())
+    }
+
+    /// Sends a follow-up message right away, the interaction has to be deferred first
+    pub async fn followup(&self, followup: CommandResponse) -> serenity::Result<()> {
+        self.command
+            .create_followup_message(&self.ctx.http, |message| {
+                message
+                    .ephemeral(followup.ephemeral)
+                    .content(&followup.content)
+                    .set_embeds(followup.embeds)
+                    .add_files(followup.files.iter().map(CommandFile::attachment));
+                if let Some(components) = followup.components {
+                    message.set_components(components);
+                }
+                message
+            })
+            .await
+            .map(|_| ())
+    }
+}
+
+/// File uploaded together with a response
+pub struct CommandFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl CommandFile {
+    fn attachment(&self) -> AttachmentType<'_> {
+        AttachmentType::Bytes { data: Cow::Borrowed(&self.data), filename: self.filename.to_owned() }
+    }
+}
+
+/// What a command answers with
+#[derive(Default)]
+pub struct CommandResponse {
+    pub content: String,
+    pub embeds: Vec<CreateEmbed>,
+    pub files: Vec<CommandFile>,
+    /// Buttons under the main response
+    pub components: Option<CreateComponents>,
+    /// Only visible to the user who invoked the command
+    pub ephemeral: bool,
+    /// Messages sent after the main response
+    pub followups: Vec<CommandResponse>,
+}
+
+impl CommandResponse {
+    pub fn ephemeral<D: ToString>(content: D) -> CommandResponse {
+        CommandResponse { content: content.to_string(), ephemeral: true, ..Default::default() }
+    }
+
+    pub fn public<D: ToString>(content: D) -> CommandResponse {
+        CommandResponse { content: content.to_string(), ephemeral: false, ..Default::default() }
+    }
+
+    pub fn embed(mut self, embed: CreateEmbed) -> CommandResponse {
+        self.embeds.push(embed);
+        self
+    }
+
+    pub fn file<D: ToString>(mut self, filename: D, data: Vec<u8>) -> CommandResponse {
+        self.files.push(CommandFile { filename: filename.to_string(), data });
+        self
+    }
+
+    pub fn components<F>(mut self, f: F) -> CommandResponse
+    where
+        F: FnOnce(&mut CreateComponents) -> &mut CreateComponents,
+    {
+        let mut components = CreateComponents::default();
+        f(&mut components);
+        self.components = Some(components);
+        self
+    }
+
+    pub fn followup(mut self, followup: CommandResponse) -> CommandResponse {
+        self.followups.push(followup);
+        self
+    }
+}
+
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand;
+
+    /// Acknowledge the interaction before running, for commands that are known to be slow.
+    /// Other commands are deferred automatically if they run longer than `AUTO_DEFER_AFTER`.
+    fn deferred(&self) -> bool {
+        false
+    }
+
+    /// Commands about the own data of the user, they can't be restricted by bans or permission rules
+    fn always_allowed(&self) -> bool {
+        false
+    }
+
+    /// Whether the response is deferred as only visible to the invoking user. A response of
+    /// the other visibility replaces the deferred one by a follow-up.
+    fn ephemeral(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse;
+}
+
+/// Registers the slash command definitions and dispatches interactions to them
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn SlashCommand>>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> CommandRegistry {
+        CommandRegistry {
+            commands: vec![
+                Box::new(ping::Ping),
+                Box::new(info::Info),
+                Box::new(model::Model),
+                Box::new(create_chat::CreateChat),
+                Box::new(reset::Reset),
+                Box::new(fork::Fork),
+                Box::new(summarize::Summarize),
+                Box::new(export::Export),
+                Box::new(settings::Settings),
+                Box::new(knowledge::Knowledge),
+                Box::new(search::Search),
+                Box::new(memory::Memory),
+                Box::new(moderation::Moderation),
+                Box::new(permissions::Permissions),
+                Box::new(admin::Admin),
+                Box::new(my_data::MyData),
+                Box::new(forget_me::ForgetMe),
+            ],
+        }
+    }
+}
+
+impl CommandRegistry {
+    pub fn find(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands.iter().find(|c| c.name() == name).map(|c| c.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.commands.iter().map(|c| c.name()).collect()
+    }
+
+    /// Adds every command, the `hidden` ones are only shown to members with Manage Server by default
+    pub fn register_all<'a>(
+        &self, commands: &'a mut CreateApplicationCommands, hidden: &[&str]
+    ) -> &'a mut CreateApplicationCommands {
+        for slash_command in &self.commands {
+            commands.create_application_command(|command| {
+                slash_command.register(command);
+                if hidden.contains(&slash_command.name()) && !slash_command.always_allowed() {
+                    command.default_member_permissions(Permissions::MANAGE_GUILD);
+                }
+                command
+            });
+        }
+        commands
+    }
+
+    /// Runs the command matching the interaction and sends its response
+    pub async fn dispatch(&self, context: &CommandContext<'_>) -> serenity::Result<()> {
+        let slash_command = match self.find(&context.command.data.name) {
+            Some(v) => v,
+            None => return respond(context, CommandResponse::ephemeral("not implemented :(")).await,
+        };
+
+        // Members with Manage Server can always run commands, so they can't lock themselves out.
+        let command = context.command;
+        if !slash_command.always_allowed() {
+            let is_manager = can_manage_guild(command.member.as_ref());
+            let permissions = UserPermissions::of_member(command.guild_id, command.user.id, command.member.as_ref()).await;
+
+            if permissions.banned {
+                return respond(context, CommandResponse::ephemeral("You are banned from using the bot.")).await
+            }
+            if !is_manager && !permissions.allows_command(slash_command.name()) {
+                let reply = format!("You are not allowed to use /{} here.", slash_command.name());
+                return respond(context, CommandResponse::ephemeral(reply)).await
+            }
+        }
+
+        if slash_command.deferred() {
+            context.defer(slash_command.ephemeral()).await?;
+        }
+
+        let run = slash_command.run(context);
+        tokio::pin!(run);
+
+        let response = tokio::select! {
+            response = &mut run => response,
+            _ = tokio::time::sleep(AUTO_DEFER_AFTER) => {
+                if let Err(e) = context.defer(slash_command.ephemeral()).await {
+                    log_to_file(&format!("[WARN] - Cannot defer slash command: {}", e), context.messages)
+                        .await.unwrap();
+                }
+                run.await
+            }
+        };
+
+        respond(context, response).await
+    }
+}
+
+/// Sets the guild slash commands, the definitions depend on the current configuration and permission rules
+pub async fn register_commands(http: &Http, registry: &CommandRegistry, messages: &Arc<Mutex<Vec<String>>>) {
+    let guild_id = GuildId(config().discord.guild_id);
+    let hidden = hidden_commands(guild_id.0, &registry.names()).await;
+
+    let commands = GuildId::set_application_commands(&guild_id, http, |commands| {
+        registry.register_all(commands, &hidden)
+    })
+    .await;
+
+    // println!("I now have the following guild slash commands: {:#?}", commands);
+    log_to_file(&format!("[INFO] - I now have the following guild slash commands: {:#?}", commands), messages)
+        .await.unwrap();
+}
+
+/// Sends the main response (editing the deferred one if needed) and then the follow-ups
+pub async fn respond(context: &CommandContext<'_>, mut response: CommandResponse) -> serenity::Result<()> {
+    let http = &context.ctx.http;
+
+    let deferred_ephemeral = context.deferred_ephemeral().await;
+
+    if deferred_ephemeral.is_some_and(|ephemeral| ephemeral != response.ephemeral) {
+        // The visibility of a deferred response can't be changed, so it is sent as a follow-up instead.
+        if let Err(e) = context.command.delete_original_interaction_response(http).await {
+            log_to_file(&format!("[WARN] - Cannot delete deferred response: {}", e), context.messages)
+                .await.unwrap();
+        }
+
+        let mut replacement = std::mem::take(&mut response);
+        response.followups = std::mem::take(&mut replacement.followups);
+        response.followups.insert(0, replacement);
+    } else if deferred_ephemeral.is_some() {
+        context.command
+            .edit_original_interaction_response(http, |message| {
+                message
+                    .content(&response.content)
+                    .set_embeds(response.embeds.to_owned());
+                if let Some(components) = response.components.to_owned() {
+                    message.set_components(components);
+                }
+                message
+            })
+            .await?;
+
+        // Files can not be added by editing the response, they are sent as a follow-up instead.
+        if !response.files.is_empty() {
+            let files = CommandResponse {
+                files: std::mem::take(&mut response.files),
+                ephemeral: response.ephemeral,
+                ..Default::default()
+            };
+            response.followups.insert(0, files);
+        }
+    } else {
+        context.command
+            .create_interaction_response(http, |interaction_response| {
+                interaction_response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message
+                            .ephemeral(response.ephemeral)
+                            .content(&response.content)
+                            .set_embeds(response.embeds.to_owned())
+                            .add_files(response.files.iter().map(CommandFile::attachment));
+                        if let Some(components) = response.components.to_owned() {
+                            message.set_components(components);
+                        }
+                        message
+                    })
+            })
+            .await?;
+    }
+
+    for followup in response.followups {
+        if let Err(e) = context.followup(followup).await {
+            log_to_file(&format!("[WARN] - Cannot send follow-up message: {}", e), context.messages)
+                .await.unwrap();
+        }
+    }
+
+    Ok(())
+}
@@ -1,28 +1,49 @@
-use std::sync::{Arc, Mutex};
+use crate::utils::{config::config, datastorage::Users, permissions::UserPermissions};
 
-use crate::utils::datastorage::{Users, User};
+use super::{CommandContext, CommandResponse, SlashCommand};
 
+use serenity::async_trait;
 use serenity::model::prelude::command::CommandOptionType;
-use serenity::prelude::Context;
 use serenity::builder::CreateApplicationCommand;
 use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
 
-pub async fn run(_ctx: &Context, _command: &ApplicationCommandInteraction, _messages: &Arc<Mutex<Vec<String>>>) -> String {
-    let mut users = match Users::default().await {
-        Ok(v) => v,
-        Err(_) => {
-            // log_to_file(
-            //     &format!("[ERROR] - Cannot open users.bson: {}", e), _messages
-            // ).await.unwrap();
+pub struct Model;
 
-            return "Error in datastorage.".to_owned()
-        }
-    };
+#[async_trait]
+impl SlashCommand for Model {
+    fn name(&self) -> &'static str {
+        "model"
+    }
 
-    let new_model = match &_command.data.options
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Select a GPT model for your requests")
+            .create_option(|option| {
+                option
+                    .name("name")
+                    .description("Select the model name from the given options")
+                    .kind(CommandOptionType::String)
+                    .required(true);
+
+                for model in &config().models.available {
+                    option.add_string_choice(&model.label, &model.name);
+                }
+
+                option
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(update_model(context.command).await)
+    }
+}
+
+async fn update_model(_command: &ApplicationCommandInteraction) -> String {
+    let new_model = match _command.data.options
         .first()
-        .unwrap()
-        .value {
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str()) {
             Some(v) => v,
             _ => {
                 // log_to_file(
@@ -33,48 +54,20 @@ pub async fn run(_ctx: &Context, _command: &ApplicationCommandInteraction, _mess
             }
         };
 
-    let res = users.update_user(_command.user.id.as_u64().to_owned(), new_model.to_string());
-    
-    if !res {
-        users.add_user(
-            User {
-                user_id: _command.user.id.as_u64().to_owned(),
-                model: new_model.to_string()
-            }  
-        );
-    };
+    let permissions = UserPermissions::of_member(_command.guild_id, _command.user.id, _command.member.as_ref()).await;
+    if !permissions.allows_model(new_model) {
+        return format!("You are not allowed to use {}.", config().model_label(new_model));
+    }
 
-    match users.write_users_datastorage().await {
-        Ok(_) => {},
-        Err(e) => {
-            // log_to_file(
-            //     &format!("[ERROR] - Cannot update model for user: {}", e), _messages
-            // ).await.unwrap();
-            //
-            let error = &format!("[ERROR] - Cannot update model for user: {}", e);
-            return error.to_owned()
-        }
-    };
+    let user_id = _command.user.id.as_u64().to_owned();
 
-    "The GPT model update for you has been successfully completed!".to_string()
-}
+    if let Err(e) = Users::update(|users| users.set_model(user_id, new_model.to_string())).await {
+        // log_to_file(
+        //     &format!("[ERROR] - Cannot update model for user: {}", e), _messages
+        // ).await.unwrap();
+        //
+        return format!("[ERROR] - Cannot update model for user: {}", e)
+    }
 
-pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command
-        .name("model")
-        .description("Select a GPT model for your requests")
-        .create_option(|option| {
-            option
-                .name("name")
-                .description("Select the model name from the given options")
-                .kind(CommandOptionType::String)
-                .required(true)
-                .add_string_choice(
-                    "ChatGPT 3.5-turbo", "gpt-3.5-turbo"
-                )
-                .add_string_choice(
-                    "ChatGPT 4", "gpt-4"
-                )
-        })
+    "The GPT model update for you has been successfully completed!".to_string()
 }
-
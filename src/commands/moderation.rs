@@ -0,0 +1,303 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use regex::Regex;
+
+use crate::utils::{
+    guilds::GuildSettings,
+    log::log_to_file,
+    moderation::{ModerationAction, ModerationLog, ModerationPolicy},
+    permissions::can_manage_guild,
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::channel::ChannelType;
+use serenity::model::permissions::Permissions;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+/// Words and patterns a policy may hold
+static MAX_RULES: usize = 100;
+/// Discord limit for the content of a message
+static MESSAGE_LIMIT: usize = 2000;
+
+pub struct Moderation;
+
+#[async_trait]
+impl SlashCommand for Moderation {
+    fn name(&self) -> &'static str {
+        "moderation"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Show or change how prompts and answers are screened on this server")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("enabled")
+                    .description("Screen prompts, image prompts and answers")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("endpoint")
+                    .description("Also ask the moderation endpoint of the provider")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("action")
+                    .description("What happens to flagged content")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("Block it", "block")
+                    .add_string_choice("Warn the user", "warn")
+                    .add_string_choice("Only log it", "log")
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("check_answers")
+                    .description("Screen the answers of the model too")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("mod_channel")
+                    .description("Channel notified about flagged content")
+                    .kind(CommandOptionType::Channel)
+                    .channel_types(&[ChannelType::Text])
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("remove_mod_channel")
+                    .description("Stop notifying the mod channel")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("block_word")
+                    .description("Flag messages containing this word")
+                    .kind(CommandOptionType::String)
+                    .max_length(100)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("unblock_word")
+                    .description("Remove a word added with block_word")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("block_pattern")
+                    .description("Flag messages matching this regular expression")
+                    .kind(CommandOptionType::String)
+                    .max_length(200)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("unblock_pattern")
+                    .description("Remove a pattern added with block_pattern")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("recent")
+                    .description("Show the latest flagged messages")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(1)
+                    .max_int_value(10)
+                    .required(false)
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(update_policy(context.messages, context.command).await)
+    }
+}
+
+fn option_value<'a>(_command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a serenity::json::Value> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn describe(policy: &ModerationPolicy) -> String {
+    let list = |values: &[String]| {
+        if values.is_empty() {
+            "none".to_string()
+        } else {
+            values.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(", ")
+        }
+    };
+
+    format!(
+        "Moderation: {}\nEndpoint check: {}\nAction: {}\nAnswers checked: {}\nMod channel: {}\nBlocked words: {}\nBlocked patterns: {}",
+        on_off(policy.enabled),
+        on_off(policy.endpoint),
+        policy.action.name(),
+        on_off(policy.check_answers),
+        policy.mod_channel.map_or("none".to_string(), |id| format!("<#{}>", id)),
+        list(&policy.blocked_words),
+        list(&policy.blocked_patterns),
+    )
+}
+
+fn describe_records(log: &ModerationLog, count: usize) -> String {
+    if log.records.is_empty() {
+        return "Nothing was flagged yet.".to_string();
+    }
+
+    log.records
+        .iter()
+        .rev()
+        .take(count)
+        .map(|record| {
+            let time = Utc.timestamp_millis_opt(record.timestamp).single()
+                .map_or(String::new(), |v| v.format("%Y-%m-%d %H:%M").to_string());
+            let excerpt: String = record.excerpt.chars().take(100).collect();
+
+            format!(
+                "`{}` {} of <@{}> in <#{}> ({}): {}\n> {}",
+                time, record.kind, record.user_id, record.channel_id, record.action.name(), record.reason, excerpt.replace('\n', " ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .chars()
+        .take(MESSAGE_LIMIT)
+        .collect()
+}
+
+async fn update_policy(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> String {
+    let guild_id = match _command.guild_id {
+        Some(v) => v.as_u64().to_owned(),
+        None => return "This command only works on a server.".to_string()
+    };
+
+    // Discord lets servers open the command to other roles, the policy and the log stay with the managers.
+    if !can_manage_guild(_command.member.as_ref()) {
+        return "You need the Manage Server permission to use /moderation.".to_string();
+    }
+
+    if let Some(count) = option_value(_command, "recent").and_then(|v| v.as_u64()) {
+        return match ModerationLog::load(guild_id).await {
+            Ok(log) => describe_records(&log, count as usize),
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot load moderation log: {}", e), _messages)
+                    .await.unwrap();
+                "Error in datastorage.".to_string()
+            }
+        }
+    }
+
+    let enabled = option_value(_command, "enabled").and_then(|v| v.as_bool());
+    let endpoint = option_value(_command, "endpoint").and_then(|v| v.as_bool());
+    let action = option_value(_command, "action").and_then(|v| v.as_str()).and_then(ModerationAction::from_name);
+    let check_answers = option_value(_command, "check_answers").and_then(|v| v.as_bool());
+    let mod_channel = option_value(_command, "mod_channel").and_then(|v| v.as_str()).and_then(|v| v.parse::<u64>().ok());
+    let remove_mod_channel = option_value(_command, "remove_mod_channel").and_then(|v| v.as_bool()).unwrap_or(false);
+    let block_word = option_value(_command, "block_word").and_then(|v| v.as_str()).map(|v| v.trim().to_lowercase());
+    let unblock_word = option_value(_command, "unblock_word").and_then(|v| v.as_str()).map(|v| v.trim().to_lowercase());
+    let block_pattern = option_value(_command, "block_pattern").and_then(|v| v.as_str());
+    let unblock_pattern = option_value(_command, "unblock_pattern").and_then(|v| v.as_str());
+
+    if let Some(pattern) = block_pattern {
+        if let Err(e) = Regex::new(pattern) {
+            return format!("`{}` is not a valid regular expression: {}", pattern, e);
+        }
+    }
+    if block_word.as_ref().is_some_and(|word| word.is_empty()) {
+        return "The word is empty.".to_string();
+    }
+
+    let changed = enabled.is_some() || endpoint.is_some() || action.is_some() || check_answers.is_some()
+        || mod_channel.is_some() || remove_mod_channel || block_word.is_some() || unblock_word.is_some()
+        || block_pattern.is_some() || unblock_pattern.is_some();
+
+    let res = if changed {
+        GuildSettings::update(guild_id, |settings| {
+            let policy = &mut settings.moderation;
+
+            let adds_rule = block_word.is_some() || block_pattern.is_some();
+            if adds_rule && policy.blocked_words.len() + policy.blocked_patterns.len() >= MAX_RULES {
+                return Err(format!("A policy can hold at most {} words and patterns.", MAX_RULES));
+            }
+
+            if let Some(v) = enabled {
+                policy.enabled = v;
+            }
+            if let Some(v) = endpoint {
+                policy.endpoint = v;
+            }
+            if let Some(v) = action {
+                policy.action = v;
+            }
+            if let Some(v) = check_answers {
+                policy.check_answers = v;
+            }
+            if let Some(v) = mod_channel {
+                policy.mod_channel = Some(v);
+            }
+            if remove_mod_channel {
+                policy.mod_channel = None;
+            }
+            if let Some(v) = unblock_word {
+                policy.blocked_words.retain(|word| *word != v);
+            }
+            if let Some(v) = unblock_pattern {
+                policy.blocked_patterns.retain(|pattern| pattern != v);
+            }
+
+            if let Some(v) = block_word {
+                if !policy.blocked_words.contains(&v) {
+                    policy.blocked_words.push(v);
+                }
+            }
+            if let Some(v) = block_pattern {
+                if !policy.blocked_patterns.iter().any(|pattern| pattern == v) {
+                    policy.blocked_patterns.push(v.to_string());
+                }
+            }
+
+            Ok(policy.to_owned())
+        }).await
+    } else {
+        GuildSettings::load(guild_id).await.map(|settings| Ok(settings.moderation))
+    };
+
+    match res {
+        Ok(Ok(policy)) => {
+            if changed {
+                log_to_file(&format!("[INFO] - Guild {} moderation policy changed: {:?}", guild_id, policy), _messages)
+                    .await.unwrap();
+            }
+            describe(&policy)
+        },
+        Ok(Err(reply)) => reply,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot update guild settings: {}", e), _messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{log::log_to_file, privacy::collect_user_data};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+/// Discord rejects larger attachments on servers without boosts
+static ATTACHMENT_LIMIT_BYTES: usize = 25 * 1024 * 1024;
+
+pub struct MyData;
+
+#[async_trait]
+impl SlashCommand for MyData {
+    fn name(&self) -> &'static str {
+        "my_data"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("See what the bot stores about you")
+            .create_option(|option| {
+                option
+                    .name("export")
+                    .description("Download everything stored about you as a JSON file")
+                    .kind(CommandOptionType::SubCommand)
+            })
+    }
+
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    fn always_allowed(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        export_user_data(context.messages, context.command).await
+    }
+}
+
+async fn export_user_data(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> CommandResponse {
+    let user_id = _command.user.id.as_u64().to_owned();
+
+    let data = match collect_user_data(user_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot collect data of user {}: {}", user_id, e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    let json = match serde_json::to_string_pretty(&data) {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot serialize data of user {}: {}", user_id, e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    if json.len() > ATTACHMENT_LIMIT_BYTES {
+        log_to_file(&format!("[WARN] - Data of user {} is too large to export: {} bytes", user_id, json.len()), _messages)
+            .await.unwrap();
+        return CommandResponse::ephemeral(format!(
+            "Your data is larger than the {} MB Discord allows for a file. Ask the owners of the bot for a copy.",
+            ATTACHMENT_LIMIT_BYTES / 1024 / 1024
+        ))
+    }
+
+    log_to_file(&format!("[INFO] - User {} exported their data", user_id), _messages)
+        .await.unwrap();
+
+    CommandResponse::ephemeral(format!(
+        "Here is everything I store about you: {} conversations, messages in {} other threads and {} remembered facts. \
+         Use /forget_me to delete it.",
+        data.conversations.len(), data.other_messages.len(), data.memory.facts.len()
+    ))
+        .file(format!("my_data_{}.json", user_id), json.into_bytes())
+}
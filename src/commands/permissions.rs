@@ -0,0 +1,312 @@
+use crate::utils::{
+    config::config,
+    guilds::GuildSettings,
+    log::log_to_file,
+    permissions::{can_manage_guild, PermissionRule},
+};
+
+use super::{register_commands, CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::permissions::Permissions as DiscordPermissions;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::{CommandDataOption, CommandDataOptionValue};
+
+/// Rules a server may hold
+static MAX_RULES: usize = 50;
+/// Discord limit for the content of a message
+static MESSAGE_LIMIT: usize = 2000;
+
+pub struct Permissions;
+
+#[async_trait]
+impl SlashCommand for Permissions {
+    fn name(&self) -> &'static str {
+        "permissions"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Choose which roles and users may use commands, models and image generation")
+            .default_member_permissions(DiscordPermissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("show")
+                    .description("Show the rules of the configuration and of this server")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("set")
+                    .description("Change the rule of a role or user, @everyone replaces the default rule")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("target")
+                            .description("Role or user the rule applies to")
+                            .kind(CommandOptionType::Mentionable)
+                            .required(true)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("commands")
+                            .description("Comma-separated command names without the slash, or `all`")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("models")
+                            .description("Comma-separated model names, or `all`")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("images")
+                            .description("Whether images may be generated")
+                            .kind(CommandOptionType::Boolean)
+                            .required(false)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("daily_requests")
+                            .description("Requests to the chat model per user and day, 0 removes the limit of this rule")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .required(false)
+                    })
+            })
+            .create_option(|option| {
+                option
+                    .name("clear")
+                    .description("Remove the rule of a role or user")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("target")
+                            .description("Role or user of the rule")
+                            .kind(CommandOptionType::Mentionable)
+                            .required(true)
+                    })
+            })
+    }
+
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        let subcommand = match context.command.data.options.first() {
+            Some(v) => v,
+            None => return CommandResponse::ephemeral("Unknown subcommand.")
+        };
+
+        let (reply, changed) = run_subcommand(context, subcommand).await;
+
+        // The commands nobody may use anymore are hidden, the others shown again.
+        if changed {
+            register_commands(&context.ctx.http, context.registry, context.messages).await;
+        }
+
+        CommandResponse::ephemeral(reply)
+    }
+}
+
+fn sub_option<'a>(subcommand: &'a CommandDataOption, name: &str) -> Option<&'a CommandDataOption> {
+    subcommand.options.iter().find(|option| option.name == name)
+}
+
+/// Whether the target is a role, and its id
+fn target_of(subcommand: &CommandDataOption) -> Option<(bool, u64)> {
+    match sub_option(subcommand, "target").and_then(|option| option.resolved.as_ref()) {
+        Some(CommandDataOptionValue::Role(role)) => Some((true, role.id.0)),
+        Some(CommandDataOptionValue::User(user, _)) => Some((false, user.id.0)),
+        _ => None,
+    }
+}
+
+/// Parses a comma-separated list, `all` means no restriction on the base rule and every known name on the others
+fn parse_list(value: &str, known: &[String], kind: &str, is_base: bool) -> Result<Option<Vec<String>>, String> {
+    if value.trim().eq_ignore_ascii_case("all") {
+        return Ok(if is_base { None } else { Some(known.to_vec()) });
+    }
+
+    let mut names: Vec<String> = vec![];
+    for name in value.split(',').map(|v| v.trim().trim_start_matches('/')).filter(|v| !v.is_empty()) {
+        if !known.iter().any(|v| v == name) {
+            return Err(format!("Unknown {} `{}`, choose from: {}.", kind, name, known.join(", ")));
+        }
+        if !names.iter().any(|v| v == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    Ok(Some(names))
+}
+
+/// Unset fields allow everything on the base rule and keep the base on the others
+fn describe_rule(rule: &PermissionRule, guild_id: u64, is_base: bool) -> String {
+    let unset = if is_base { "all" } else { "as the base" };
+    let list = |values: &Option<Vec<String>>| match values {
+        None => unset.to_string(),
+        Some(values) if values.is_empty() => "none".to_string(),
+        Some(values) => values.join(", "),
+    };
+
+    let targets: Vec<String> = rule.roles
+        .iter()
+        .map(|id| if *id == guild_id { "@everyone".to_string() } else { format!("<@&{}>", id) })
+        .chain(rule.users.iter().map(|id| format!("<@{}>", id)))
+        .collect();
+
+    format!(
+        "{}: commands {}; models {}; images {}; daily requests {}",
+        if targets.is_empty() { "Everyone".to_string() } else { targets.join(", ") },
+        list(&rule.commands),
+        list(&rule.models),
+        match rule.images {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => if is_base { "on" } else { unset },
+        },
+        rule.daily_requests.map_or(if is_base { "unlimited".to_string() } else { unset.to_string() }, |v| v.to_string()),
+    )
+}
+
+fn describe(guild_rules: &[PermissionRule], guild_id: u64) -> String {
+    let config = config();
+
+    let mut lines = vec!["**Configuration**".to_string()];
+    if guild_rules.iter().any(|rule| rule.is_everyone(guild_id)) {
+        lines.push(format!("~~{}~~ (replaced by @everyone)", describe_rule(&config.permissions.default, guild_id, true)));
+    } else {
+        lines.push(describe_rule(&config.permissions.default, guild_id, true));
+    }
+    lines.extend(config.permissions.rules.iter().map(|rule| describe_rule(rule, guild_id, false)));
+
+    lines.push("**This server**".to_string());
+    if guild_rules.is_empty() {
+        lines.push("No rules, add them with `/permissions set`.".to_string());
+    }
+    lines.extend(guild_rules.iter().map(|rule| describe_rule(rule, guild_id, rule.is_everyone(guild_id))));
+
+    lines.join("\n").chars().take(MESSAGE_LIMIT).collect()
+}
+
+/// Returns the reply and whether the rules were changed
+async fn run_subcommand(context: &CommandContext<'_>, subcommand: &CommandDataOption) -> (String, bool) {
+    let guild_id = match context.command.guild_id {
+        Some(v) => v.as_u64().to_owned(),
+        None => return ("This command only works on a server.".to_string(), false)
+    };
+
+    // A rule can grant any command, /admin included, so Discord's default permission is not enough.
+    if !can_manage_guild(context.command.member.as_ref()) {
+        return ("You need the Manage Server permission to use /permissions.".to_string(), false);
+    }
+
+    let res = match subcommand.name.as_str() {
+        "show" => GuildSettings::load(guild_id).await.map(|settings| Ok(describe(&settings.permissions, guild_id))),
+        "set" | "clear" => {
+            let (is_role, target_id) = match target_of(subcommand) {
+                Some(v) => v,
+                None => return ("Choose a role or a user.".to_string(), false)
+            };
+            let matches = move |rule: &PermissionRule| {
+                if is_role { rule.users.is_empty() && rule.roles == [target_id] } else { rule.roles.is_empty() && rule.users == [target_id] }
+            };
+
+            if subcommand.name == "clear" {
+                GuildSettings::update(guild_id, |settings| {
+                    let count = settings.permissions.len();
+                    settings.permissions.retain(|rule| !matches(rule));
+
+                    if settings.permissions.len() == count {
+                        Err("There is no rule for this target.".to_string())
+                    } else {
+                        Ok(describe(&settings.permissions, guild_id))
+                    }
+                }).await
+            } else {
+                let config = config();
+                // @everyone replaces the default rule, the others only add to it.
+                let is_base = is_role && target_id == guild_id;
+                let command_names: Vec<String> = context.registry.names().iter().map(|v| v.to_string()).collect();
+                let model_names: Vec<String> = config.models.available.iter().map(|m| m.name.to_owned()).collect();
+
+                let commands = match sub_option(subcommand, "commands").and_then(|o| o.value.as_ref()).and_then(|v| v.as_str()) {
+                    Some(v) => match parse_list(v, &command_names, "command", is_base) {
+                        Ok(v) => Some(v),
+                        Err(reply) => return (reply, false)
+                    },
+                    None => None
+                };
+                let models = match sub_option(subcommand, "models").and_then(|o| o.value.as_ref()).and_then(|v| v.as_str()) {
+                    Some(v) => match parse_list(v, &model_names, "model", is_base) {
+                        Ok(v) => Some(v),
+                        Err(reply) => return (reply, false)
+                    },
+                    None => None
+                };
+                let images = sub_option(subcommand, "images").and_then(|o| o.value.as_ref()).and_then(|v| v.as_bool());
+                let daily_requests = sub_option(subcommand, "daily_requests").and_then(|o| o.value.as_ref()).and_then(|v| v.as_u64());
+
+                GuildSettings::update(guild_id, |settings| {
+                    let index = match settings.permissions.iter().position(&matches) {
+                        Some(v) => v,
+                        None => {
+                            if settings.permissions.len() >= MAX_RULES {
+                                return Err(format!("A server can hold at most {} rules.", MAX_RULES));
+                            }
+
+                            let mut rule = PermissionRule::default();
+                            if is_role { rule.roles.push(target_id) } else { rule.users.push(target_id) }
+                            settings.permissions.push(rule);
+                            settings.permissions.len() - 1
+                        }
+                    };
+
+                    let rule = &mut settings.permissions[index];
+                    if let Some(v) = commands {
+                        rule.commands = v;
+                    }
+                    if let Some(v) = models {
+                        rule.models = v;
+                    }
+                    if let Some(v) = images {
+                        rule.images = Some(v);
+                    }
+                    if let Some(v) = daily_requests {
+                        rule.daily_requests = (v > 0).then_some(v);
+                    }
+
+                    Ok(describe(&settings.permissions, guild_id))
+                }).await
+            }
+        },
+        _ => Ok(Err("Unknown subcommand.".to_string())),
+    };
+
+    match res {
+        Ok(Ok(reply)) => {
+            let changed = subcommand.name != "show";
+            if changed {
+                log_to_file(
+                    &format!("[INFO] - User {} used /permissions {} in guild {}", context.command.user.id, subcommand.name, guild_id),
+                    context.messages
+                ).await.unwrap();
+            }
+            (reply, changed)
+        },
+        Ok(Err(reply)) => (reply, false),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot update guild settings: {}", e), context.messages)
+                .await.unwrap();
+            ("Error in datastorage.".to_string(), false)
+        }
+    }
+}
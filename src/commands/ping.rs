@@ -1,11 +1,21 @@
+use serenity::async_trait;
 use serenity::builder::CreateApplicationCommand;
-use serenity::model::prelude::interaction::application_command::CommandDataOption;
 
-pub fn run(_options: &[CommandDataOption]) -> String {
-    "Hey, I'm alive!".to_string()
-}
+use super::{CommandContext, CommandResponse, SlashCommand};
 
-pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
-    command.name("ping").description("A ping command")
-}
+pub struct Ping;
+
+#[async_trait]
+impl SlashCommand for Ping {
+    fn name(&self) -> &'static str {
+        "ping"
+    }
 
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command.name(self.name()).description("A ping command")
+    }
+
+    async fn run(&self, _context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral("Hey, I'm alive!")
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{conversations::{now_millis, Conversation}, log::log_to_file, permissions::can_manage_guild};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+pub struct Reset;
+
+#[async_trait]
+impl SlashCommand for Reset {
+    fn name(&self) -> &'static str {
+        "reset"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Start the conversation in this thread from scratch, earlier messages are ignored")
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        reset_context(context.messages, context.command).await
+    }
+}
+
+async fn reset_context(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> CommandResponse {
+    let thread_id = _command.channel_id.as_u64().to_owned();
+    let user_id = _command.user.id.as_u64().to_owned();
+    let is_manager = can_manage_guild(_command.member.as_ref());
+
+    // The context is shared by everyone in the thread, so only its owner or a manager may drop it.
+    let res = Conversation::update(thread_id, |conversation| {
+        if conversation.owner_id != user_id && !is_manager {
+            return false
+        }
+        conversation.context_start = Some(now_millis());
+        conversation.summary = None;
+        true
+    }).await;
+
+    match res {
+        Ok(Some(true)) => CommandResponse::public(
+            "The context has been reset. Messages above this one are no longer sent to the model."
+        ),
+        Ok(Some(false)) => CommandResponse::ephemeral("Only the owner of this conversation can reset it."),
+        Ok(None) => CommandResponse::ephemeral("This command only works inside a chat thread."),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot reset conversation: {}", e), _messages)
+                .await.unwrap();
+            CommandResponse::ephemeral("Error in datastorage.")
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+
+use crate::utils::{
+    conversations::TurnRole,
+    log::log_to_file,
+    permissions::can_manage_guild,
+    search::{search_conversations, SearchScope},
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateEmbed};
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+/// Discord limit for the description of an embed
+static EMBED_DESCRIPTION_LIMIT: usize = 4096;
+/// Amount of messages listed in the results
+static SEARCH_RESULTS: usize = 10;
+
+pub struct Search;
+
+#[async_trait]
+impl SlashCommand for Search {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    // The first search embeds every message that has not been searched before.
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Find messages in your chats")
+            .create_option(|option| {
+                option
+                    .name("query")
+                    .description("Words or a description of what was discussed")
+                    .kind(CommandOptionType::String)
+                    .max_length(200)
+                    .required(true)
+            })
+            .create_option(|option| {
+                option
+                    .name("scope")
+                    .description("Whose chats to search, the whole server needs the Manage Server permission")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("My chats", "mine")
+                    .add_string_choice("This server", "server")
+                    .required(false)
+            })
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        search(context.messages, context.command).await
+    }
+}
+
+fn string_option<'a>(_command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a str> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+async fn search(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> CommandResponse {
+    let user_id = _command.user.id.as_u64().to_owned();
+
+    let query = match string_option(_command, "query") {
+        Some(v) if !v.trim().is_empty() => v,
+        _ => return CommandResponse::ephemeral("Enter what to search for.")
+    };
+
+    let scope = match (string_option(_command, "scope"), _command.guild_id) {
+        (Some("server"), Some(guild_id)) => {
+            if !can_manage_guild(_command.member.as_ref()) {
+                return CommandResponse::ephemeral("You need the Manage Server permission to search all chats of the server.");
+            }
+
+            SearchScope::Guild { guild_id: guild_id.as_u64().to_owned(), user_id }
+        },
+        (Some("server"), None) => return CommandResponse::ephemeral("The server scope only works on a server."),
+        _ => SearchScope::Owner(user_id),
+    };
+
+    let results = match search_conversations(query, &scope, SEARCH_RESULTS).await {
+        Ok(v) => v,
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Search failed: {}", e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    if let Some(e) = &results.similarity_error {
+        log_to_file(&format!("[WARN] - Search falls back to full-text matches: {}", e), _messages)
+            .await.unwrap();
+    }
+
+    if results.hits.is_empty() {
+        return CommandResponse::ephemeral("Nothing found.");
+    }
+
+    let mut description = String::new();
+
+    for (i, hit) in results.hits.iter().enumerate() {
+        let date = Utc.timestamp_millis_opt(hit.timestamp).single()
+            .map_or(String::new(), |v| v.format("%Y-%m-%d").to_string());
+        let author = if hit.role == TurnRole::User { "You" } else { "Bot" };
+        let excerpt = hit.excerpt.replace('\n', " ");
+
+        let entry = format!("**{}.** [{}]({}) · {}\n> {}: {}\n", i + 1, hit.title, hit.link(), date, author, excerpt);
+
+        if description.chars().count() + entry.chars().count() > EMBED_DESCRIPTION_LIMIT {
+            break;
+        }
+        description.push_str(&entry);
+    }
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("Results for \"{}\"", query.chars().take(200).collect::<String>()))
+        .description(description);
+
+    if results.similarity_error.is_some() {
+        embed.footer(|footer| footer.text("Similarity search is unavailable, only exact word matches are shown."));
+    }
+
+    CommandResponse::ephemeral("").embed(embed)
+}
@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+
+use crate::utils::{gpt::tools::tools, guilds::GuildSettings, log::log_to_file, permissions::can_manage_guild};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::model::channel::ChannelType;
+use serenity::model::permissions::Permissions;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+pub struct Settings;
+
+#[async_trait]
+impl SlashCommand for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Show or change the bot settings of this server")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .dm_permission(false)
+            .create_option(|option| {
+                option
+                    .name("auto_titles")
+                    .description("Name chats created without a title after their first answer")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("mentions")
+                    .description("Answer mentions of the bot and replies to it outside of the chat threads")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("allow_channel")
+                    .description("Answer mentions in this channel (mentions are answered everywhere if no channel is allowed)")
+                    .kind(CommandOptionType::Channel)
+                    .channel_types(&[ChannelType::Text])
+                    .required(false)
+            })
+            .create_option(|option| {
+                option
+                    .name("disallow_channel")
+                    .description("Remove the channel from the ones allowed with allow_channel")
+                    .kind(CommandOptionType::Channel)
+                    .channel_types(&[ChannelType::Text])
+                    .required(false)
+            })
+            .create_option(|option| tool_option(option, "enable_tool", "Let the model use this tool"))
+            .create_option(|option| tool_option(option, "disable_tool", "Stop the model from using this tool"))
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        CommandResponse::ephemeral(update_settings(context.messages, context.command).await)
+    }
+}
+
+fn tool_option<'a>(option: &'a mut CreateApplicationCommandOption, name: &str, description: &str) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(CommandOptionType::String)
+        .required(false);
+
+    for tool in tools().names() {
+        option.add_string_choice(tool, tool);
+    }
+
+    option
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn describe(settings: &GuildSettings) -> String {
+    let channels = if settings.mention_channels.is_empty() {
+        "all".to_string()
+    } else {
+        settings.mention_channels.iter().map(|id| format!("<#{}>", id)).collect::<Vec<_>>().join(", ")
+    };
+
+    let enabled_tools = if settings.tools.is_empty() { "none".to_string() } else { settings.tools.join(", ") };
+
+    format!(
+        "Automatic chat titles: {}\nAnswers to mentions and replies: {} (channels: {})\nTools: {}",
+        on_off(settings.auto_titles), on_off(settings.mentions), channels, enabled_tools
+    )
+}
+
+fn bool_option(_command: &ApplicationCommandInteraction, name: &str) -> Option<bool> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+}
+
+fn string_option<'a>(_command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a str> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+fn channel_option(_command: &ApplicationCommandInteraction, name: &str) -> Option<u64> {
+    _command.data.options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse().ok())
+}
+
+async fn update_settings(_messages: &Arc<Mutex<Vec<String>>>, _command: &ApplicationCommandInteraction) -> String {
+    let guild_id = match _command.guild_id {
+        Some(v) => v.as_u64().to_owned(),
+        None => return "This command only works on a server.".to_string()
+    };
+
+    // Discord lets servers open the command to other roles, the settings stay with the managers.
+    if !can_manage_guild(_command.member.as_ref()) {
+        return "You need the Manage Server permission to use /settings.".to_string();
+    }
+
+    let auto_titles = bool_option(_command, "auto_titles");
+    let mentions = bool_option(_command, "mentions");
+    let allow_channel = channel_option(_command, "allow_channel");
+    let disallow_channel = channel_option(_command, "disallow_channel");
+
+    let enable_tool = string_option(_command, "enable_tool");
+    let disable_tool = string_option(_command, "disable_tool");
+
+    let changed = auto_titles.is_some() || mentions.is_some() || allow_channel.is_some() || disallow_channel.is_some()
+        || enable_tool.is_some() || disable_tool.is_some();
+
+    let res = if changed {
+        GuildSettings::update(guild_id, |settings| {
+            if let Some(v) = auto_titles {
+                settings.auto_titles = v;
+            }
+            if let Some(v) = mentions {
+                settings.mentions = v;
+            }
+            if let Some(v) = allow_channel {
+                if !settings.mention_channels.contains(&v) {
+                    settings.mention_channels.push(v);
+                }
+            }
+            if let Some(v) = disallow_channel {
+                settings.mention_channels.retain(|id| *id != v);
+            }
+            if let Some(v) = enable_tool {
+                if !settings.tools.iter().any(|tool| tool == v) {
+                    settings.tools.push(v.to_string());
+                }
+            }
+            if let Some(v) = disable_tool {
+                settings.tools.retain(|tool| tool != v);
+            }
+            settings.to_owned()
+        }).await
+    } else {
+        GuildSettings::load(guild_id).await
+    };
+
+    match res {
+        Ok(settings) => {
+            if changed {
+                log_to_file(&format!("[INFO] - Guild {} settings changed: {:?}", guild_id, settings), _messages)
+                    .await.unwrap();
+            }
+            describe(&settings)
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot update guild settings: {}", e), _messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::utils::{
+    config::config, conversations::Conversation, datastorage::model_of_user, gpt::summarize_turns,
+    log::log_to_file, permissions::UserPermissions, stats::Stats,
+};
+
+use super::{CommandContext, CommandResponse, SlashCommand};
+
+use serenity::async_trait;
+use serenity::builder::{CreateApplicationCommand, CreateEmbed};
+use serenity::model::prelude::interaction::application_command::ApplicationCommandInteraction;
+
+/// Discord limit for the description of an embed
+static EMBED_DESCRIPTION_LIMIT: usize = 4096;
+
+pub struct Summarize;
+
+#[async_trait]
+impl SlashCommand for Summarize {
+    fn name(&self) -> &'static str {
+        "summarize"
+    }
+
+    // Summarizing a long thread easily takes longer than 3 seconds.
+    fn deferred(&self) -> bool {
+        true
+    }
+
+    fn ephemeral(&self) -> bool {
+        false
+    }
+
+    fn register<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        command
+            .name(self.name())
+            .description("Summarize the conversation in this thread")
+    }
+
+    async fn run(&self, context: &CommandContext<'_>) -> CommandResponse {
+        summarize(context.messages, context.stats, context.command).await
+    }
+}
+
+async fn summarize(
+    _messages: &Arc<Mutex<Vec<String>>>, _stats: &Arc<Stats>, _command: &ApplicationCommandInteraction
+) -> CommandResponse {
+    let conversation = match Conversation::load(_command.channel_id.as_u64().to_owned()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return CommandResponse::ephemeral("This command only works inside a chat thread."),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot load conversation: {}", e), _messages)
+                .await.unwrap();
+            return CommandResponse::ephemeral("Error in datastorage.")
+        }
+    };
+
+    let turns = conversation.unsummarized_turns();
+    let previous = conversation.summary.as_ref().map(|summary| summary.text.as_str());
+
+    if turns.is_empty() && previous.is_none() {
+        return CommandResponse::ephemeral("There is nothing to summarize yet.")
+    }
+
+    let model = model_of_user(_command.user.id.as_u64().to_owned()).await;
+
+    let permissions = UserPermissions::of_member(_command.guild_id, _command.user.id, _command.member.as_ref()).await;
+    if let Err(reply) = permissions.authorize_request(_command.user.id.as_u64().to_owned(), &model).await {
+        return CommandResponse::ephemeral(reply)
+    }
+
+    let started_at = Instant::now();
+    let text = match summarize_turns(&model, previous, turns).await {
+        Ok(reply) => {
+            _stats.record_llm_request(started_at.elapsed(), reply.total_tokens);
+            reply.text
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot summarize conversation: {:#?}", e), _messages)
+                .await.unwrap();
+            _stats.record_error();
+            return CommandResponse::ephemeral(&config().texts.chat_error)
+        }
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(&conversation.title)
+        .description(text.chars().take(EMBED_DESCRIPTION_LIMIT).collect::<String>());
+
+    CommandResponse::public("").embed(embed)
+}
@@ -0,0 +1,437 @@
+use std::sync::{Arc, Mutex};
+
+use chatgpt::types::{ChatMessage, Role};
+
+use serenity::builder::CreateComponents;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::prelude::Context;
+
+use crate::answer::{add_images, ask, attach_images, conversation_history, Answer, AnswerRequest};
+use crate::utils::{
+    config::config,
+    conversations::{Conversation, Rating, Turn, TurnRole},
+    datastorage::model_of_user,
+    log::log_to_file,
+    memory::UserMemory,
+    permissions::{can_manage_guild, UserPermissions},
+    privacy::forget_user,
+    stats::Stats,
+};
+
+static REGENERATE_ID: &str = "answer:regenerate";
+static CONTINUE_ID: &str = "answer:continue";
+static RATE_UP_ID: &str = "answer:up";
+static RATE_DOWN_ID: &str = "answer:down";
+/// Followed by `:<user id>` of the user the suggested fact is about
+static MEMORY_SAVE_PREFIX: &str = "memory:save";
+static MEMORY_DISMISS_PREFIX: &str = "memory:dismiss";
+/// Followed by `:<user id>` of the user who asked to be forgotten
+static FORGET_CONFIRM_PREFIX: &str = "forget_me:confirm";
+static FORGET_CANCEL_PREFIX: &str = "forget_me:cancel";
+
+/// Buttons shown under every answer in a chat thread
+pub fn answer_buttons(components: &mut CreateComponents) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row
+            .create_button(|button| button.custom_id(REGENERATE_ID).label("Regenerate").style(ButtonStyle::Secondary).emoji('🔄'))
+            .create_button(|button| button.custom_id(CONTINUE_ID).label("Continue").style(ButtonStyle::Secondary).emoji('⏩'))
+            .create_button(|button| button.custom_id(RATE_UP_ID).style(ButtonStyle::Secondary).emoji('👍'))
+            .create_button(|button| button.custom_id(RATE_DOWN_ID).style(ButtonStyle::Secondary).emoji('👎'))
+    })
+}
+
+/// Buttons under a fact suggested for the memory of `user_id`
+pub fn memory_buttons(components: &mut CreateComponents, user_id: u64) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row
+            .create_button(|button| {
+                button.custom_id(format!("{}:{}", MEMORY_SAVE_PREFIX, user_id)).label("Remember").style(ButtonStyle::Success)
+            })
+            .create_button(|button| {
+                button.custom_id(format!("{}:{}", MEMORY_DISMISS_PREFIX, user_id)).label("Dismiss").style(ButtonStyle::Secondary)
+            })
+    })
+}
+
+/// Buttons confirming /forget_me of `user_id`
+pub fn forget_me_buttons(components: &mut CreateComponents, user_id: u64) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row
+            .create_button(|button| {
+                button.custom_id(format!("{}:{}", FORGET_CONFIRM_PREFIX, user_id)).label("Delete my data").style(ButtonStyle::Danger)
+            })
+            .create_button(|button| {
+                button.custom_id(format!("{}:{}", FORGET_CANCEL_PREFIX, user_id)).label("Cancel").style(ButtonStyle::Secondary)
+            })
+    })
+}
+
+/// Handles a click on one of the `answer_buttons`, `memory_buttons` or `forget_me_buttons`
+pub async fn handle_component(
+    ctx: &Context, component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+) -> serenity::Result<()> {
+    let custom_id = component.data.custom_id.as_str();
+
+    if let Some((action, user_id)) = custom_id.rsplit_once(':') {
+        if action == MEMORY_SAVE_PREFIX || action == MEMORY_DISMISS_PREFIX {
+            return confirm_memory(ctx, component, messages, action == MEMORY_SAVE_PREFIX, user_id.parse().unwrap_or(0)).await
+        }
+        if action == FORGET_CONFIRM_PREFIX || action == FORGET_CANCEL_PREFIX {
+            return confirm_forget(ctx, component, messages, action == FORGET_CONFIRM_PREFIX, user_id.parse().unwrap_or(0)).await
+        }
+    }
+
+    if custom_id == RATE_UP_ID || custom_id == RATE_DOWN_ID {
+        let reply = rate(component, messages, custom_id == RATE_UP_ID).await;
+        return component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true).content(reply))
+            })
+            .await
+    }
+
+    if custom_id != REGENERATE_ID && custom_id != CONTINUE_ID {
+        return Ok(())
+    }
+
+    // The model may take longer than the 3 seconds Discord waits for an answer.
+    component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
+
+    let res = if custom_id == REGENERATE_ID {
+        regenerate(ctx, component, messages, stats).await
+    } else {
+        continue_answer(ctx, component, messages, stats).await
+    };
+
+    if let Err(reply) = res {
+        component
+            .create_followup_message(&ctx.http, |message| message.ephemeral(true).content(reply))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Conversation of the clicked message together with the answer turn
+async fn load_answer(
+    component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>
+) -> Result<(Conversation, Turn), String> {
+    let conversation = match Conversation::load(component.channel_id.as_u64().to_owned()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return Err("This conversation is not stored anymore.".to_string()),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot load conversation: {}", e), messages)
+                .await.unwrap();
+            return Err("Error in datastorage.".to_string())
+        }
+    };
+
+    let message_id = component.message.id.as_u64().to_owned();
+    let turn = match conversation.turns.iter().find(|t| t.message_id == message_id && t.role == TurnRole::Assistant) {
+        Some(v) => v.to_owned(),
+        None => return Err("This answer is not part of the conversation anymore.".to_string())
+    };
+
+    Ok((conversation, turn))
+}
+
+/// Model of the answer, the current model of the thread for answers stored without one
+async fn model_of_answer(conversation: &Conversation, turn: &Turn, user_id: u64) -> String {
+    match turn.model.as_ref().or(conversation.settings.model.as_ref()) {
+        Some(v) => v.to_owned(),
+        None => model_of_user(user_id).await
+    }
+}
+
+/// Permissions of the member who clicked, after checking that they may ask `model`.
+/// The request is counted against their quota.
+async fn authorize(component: &MessageComponentInteraction, model: &str) -> Result<UserPermissions, String> {
+    let permissions = UserPermissions::of_member(component.guild_id, component.user.id, component.member.as_ref()).await;
+    permissions.authorize_request(component.user.id.as_u64().to_owned(), model).await?;
+    Ok(permissions)
+}
+
+/// Asks the model for the member who clicked, errors and blocked answers are only shown to them
+async fn ask_model(
+    component: &MessageComponentInteraction, conversation: &Conversation, model: &str, permissions: &UserPermissions,
+    history: Vec<ChatMessage>, messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+) -> Result<(String, Vec<String>), String> {
+    let request = AnswerRequest {
+        guild_id: conversation.guild_id,
+        channel_id: conversation.thread_id,
+        user_id: component.user.id.as_u64().to_owned(),
+        model,
+        permissions,
+    };
+
+    match ask(&request, history, messages, stats).await {
+        Answer::Text { text, images } => Ok((text, images)),
+        answer => Err(answer.text()),
+    }
+}
+
+/// Only the owner of the conversation and managers may change its answers, `action` names the change in the error
+fn check_owner(component: &MessageComponentInteraction, conversation: &Conversation, action: &str) -> Result<(), String> {
+    if conversation.owner_id != component.user.id.0 && !can_manage_guild(component.member.as_ref()) {
+        return Err(format!("Only the owner of this conversation can {} its answers.", action))
+    }
+    Ok(())
+}
+
+/// Asks the model again with the context the answer was given in, and replaces the message
+async fn regenerate(
+    ctx: &Context, component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+) -> Result<(), String> {
+    let (conversation, turn) = load_answer(component, messages).await?;
+
+    // Regenerating replaces the answer and its ratings for everyone.
+    check_owner(component, &conversation, "regenerate")?;
+
+    let model = model_of_answer(&conversation, &turn, component.user.id.as_u64().to_owned()).await;
+    let permissions = authorize(component, &model).await?;
+
+    let turns = conversation.context_turns();
+    let before = &turns[..turns.partition_point(|t| t.timestamp < turn.timestamp)];
+    // The summary is only valid if the answer itself is not part of it.
+    let summary = conversation.summary.as_ref().filter(|summary| summary.until < turn.timestamp);
+    let question = before.iter().rev().find(|t| t.role == TurnRole::User);
+
+    let history = conversation_history(
+        &conversation, before, summary, question.map_or(conversation.owner_id, |t| t.author_id),
+        question.map_or("", |t| t.content.as_str()), messages
+    ).await;
+    let (text, images) = ask_model(component, &conversation, &model, &permissions, history, messages, stats).await?;
+
+    if let Err(e) = component.channel_id
+        .edit_message(&ctx.http, component.message.id, |message| {
+            message.content(&text);
+            attach_images(message, &images)
+        })
+        .await {
+            log_to_file(&format!("[WARN] - Can`t edit message: {:#?}", e), messages)
+                .await.unwrap();
+            return Err("There was a server-side error. Please try again later.".to_string())
+        }
+
+    let res = Conversation::update(conversation.thread_id, |conversation| {
+        if let Some(stored) = conversation.find_turn_mut(turn.message_id) {
+            stored.content = text;
+            stored.model = Some(model.trim_matches('"').to_owned());
+            stored.ratings.clear();
+        }
+    }).await;
+
+    if let Err(e) = res {
+        log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), messages)
+            .await.unwrap();
+    }
+
+    Ok(())
+}
+
+/// Asks the model to go on with the answer and posts the rest as a new message
+async fn continue_answer(
+    ctx: &Context, component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+) -> Result<(), String> {
+    let (conversation, turn) = load_answer(component, messages).await?;
+
+    // The continuation becomes a part of the history of the owner.
+    check_owner(component, &conversation, "continue")?;
+
+    let model = model_of_answer(&conversation, &turn, component.user.id.as_u64().to_owned()).await;
+    let permissions = authorize(component, &model).await?;
+
+    let turns = conversation.context_turns();
+    let until = &turns[..turns.partition_point(|t| t.timestamp <= turn.timestamp)];
+    let question = until.iter().rev().find(|t| t.role == TurnRole::User);
+
+    let mut history = conversation_history(
+        &conversation, until, conversation.summary.as_ref(), question.map_or(conversation.owner_id, |t| t.author_id),
+        question.map_or("", |t| t.content.as_str()), messages
+    ).await;
+    history.push(ChatMessage {
+        role: Role::User,
+        content: "Continue your previous answer exactly where it stopped, without repeating it.".to_string(),
+    });
+
+    let (text, images) = ask_model(component, &conversation, &model, &permissions, history, messages, stats).await?;
+
+    let sent = match component.channel_id
+        .send_message(&ctx.http, |message| {
+            message
+                .content(&text)
+                .reference_message(&component.message)
+                .components(answer_buttons);
+            add_images(message, &images)
+        })
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), messages)
+                    .await.unwrap();
+                return Err("There was a server-side error. Please try again later.".to_string())
+            }
+        };
+
+    let mut continued = Turn::from_message(&sent, config().discord.bot_id);
+    continued.model = Some(model.trim_matches('"').to_owned());
+
+    if let Err(e) = Conversation::update(conversation.thread_id, |conversation| conversation.push_turn(continued)).await {
+        log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), messages)
+            .await.unwrap();
+    }
+
+    Ok(())
+}
+
+/// Stores the rating of the user, a second click replaces the previous one
+async fn rate(component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, positive: bool) -> String {
+    let message_id = component.message.id.as_u64().to_owned();
+    let user_id = component.user.id.as_u64().to_owned();
+
+    let res = Conversation::update(component.channel_id.as_u64().to_owned(), |conversation| {
+        match conversation.find_turn_mut(message_id) {
+            Some(turn) if turn.role == TurnRole::Assistant => {
+                turn.ratings.retain(|rating| rating.user_id != user_id);
+                turn.ratings.push(Rating { user_id, positive });
+                true
+            },
+            _ => false
+        }
+    }).await;
+
+    match res {
+        Ok(Some(true)) => {
+            log_to_file(
+                &format!("[INFO] - Answer {} rated {} by {}", message_id, if positive { "up" } else { "down" }, user_id),
+                messages
+            ).await.unwrap();
+            "Thanks for the feedback!".to_string()
+        },
+        Ok(_) => "This answer is not part of the conversation anymore.".to_string(),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    }
+}
+
+/// Saves or drops the fact suggested in the clicked message, only the user it is about may decide
+async fn confirm_memory(
+    ctx: &Context, component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, save: bool, user_id: u64
+) -> serenity::Result<()> {
+    if *component.user.id.as_u64() != user_id {
+        return component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true).content("Only the user this fact is about can answer."))
+            })
+            .await
+    }
+
+    let message_id = component.message.id.as_u64().to_owned();
+
+    let res = UserMemory::update(user_id, |memory| {
+        let pending = memory.take_pending(message_id)?;
+
+        Some(if save { memory.add_fact(&pending.text).map(|_| pending.text) } else { Ok(pending.text) })
+    }).await;
+
+    let content = match res {
+        Ok(Some(Ok(fact))) if save => {
+            log_to_file(&format!("[INFO] - User {} confirmed a suggested memory fact", user_id), messages)
+                .await.unwrap();
+            format!("Remembered: {}", fact)
+        },
+        Ok(Some(Ok(_))) => "Okay, I won't remember that.".to_string(),
+        Ok(Some(Err(reply))) => reply,
+        Ok(None) => "This suggestion has expired.".to_string(),
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot save memory: {}", e), messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    };
+
+    component
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| message.content(content).components(|components| components))
+        })
+        .await
+}
+
+/// Deletes the data of the user after the confirmation of /forget_me
+async fn confirm_forget(
+    ctx: &Context, component: &MessageComponentInteraction, messages: &Arc<Mutex<Vec<String>>>, confirmed: bool, user_id: u64
+) -> serenity::Result<()> {
+    if *component.user.id.as_u64() != user_id {
+        return component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| message.ephemeral(true).content("Only the user who asked can answer."))
+            })
+            .await
+    }
+
+    if !confirmed {
+        return component
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|message| message.content("Nothing was deleted.").components(|components| components))
+            })
+            .await
+    }
+
+    // Going through all stored conversations may take longer than Discord waits for an answer.
+    component
+        .create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
+
+    let content = match forget_user(user_id).await {
+        Ok(report) => {
+            log_to_file(
+                &format!(
+                    "[INFO] - User {} was forgotten, {} conversations deleted, messages removed from {} others",
+                    user_id, report.conversations, report.other_threads
+                ),
+                messages
+            ).await.unwrap();
+
+            let mut content = format!(
+                "Your settings, memory, usage and {} conversations are deleted, your messages are removed \
+                 from {} other conversations. The messages stay in Discord, delete the threads there if you want them gone too.",
+                report.conversations, report.other_threads
+            );
+            if report.banned {
+                content.push_str(" Your ban is kept.");
+            }
+            content
+        },
+        Err(e) => {
+            log_to_file(&format!("[ERROR] - Cannot forget user {}: {}", user_id, e), messages)
+                .await.unwrap();
+            "Error in datastorage.".to_string()
+        }
+    };
+
+    component
+        .edit_original_interaction_response(&ctx.http, |message| message.content(content).components(|components| components))
+        .await
+        .map(|_| ())
+}
@@ -1,12 +1,27 @@
 pub mod ui;
 pub mod utils;
 pub mod commands;
+pub mod components;
+pub mod answer;
 
 use url::Url;
 
-use std::{env, sync::{Arc, Mutex}};
+use crate::answer::{add_images, ask, attach_images, conversation_history, memory_message, Answer, AnswerRequest};
+use crate::commands::{create_chat::THREAD_NAME_LIMIT, register_commands, CommandContext, CommandRegistry, CommandResponse};
 
-use crate::utils::{log::log_to_file, datastorage::{Users, User}};
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::utils::{
+    log::{log_to_file, show_in_tui}, datastorage::{Users, User, flush_datastorage, model_of_user}, stats::Stats,
+    config::{config, subscribe_config}, shutdown::Shutdown,
+    conversations::{
+        is_conversation_channel, load_conversation_channels, remember_channel, snowflake_millis, Conversation, ConversationSummary, Tombstone,
+        Turn, TurnRole,
+    },
+    guilds::GuildSettings, memory::{UserMemory, MAX_FACTS, MAX_FACT_CHARS},
+    moderation::{moderate, set_moderation_http, ContentKind, ModerationTarget, ModerationVerdict},
+    permissions::{role_ids, UserPermissions}, privacy::purge_expired_conversations,
+};
 
 // use std::io::Write;
 // use chrono::Local;
@@ -15,73 +30,645 @@ use crate::utils::{log::log_to_file, datastorage::{Users, User}};
 
 use serenity::async_trait;
 // use serenity::model::application::command::Command;
-use serenity::model::application::interaction::{Interaction, InteractionResponseType};
-use serenity::model::channel::{Message, AttachmentType};
+use serenity::model::application::interaction::Interaction;
+use serenity::model::channel::{Channel, ChannelType, Message, AttachmentType};
 use serenity::model::gateway::Ready;
-use serenity::model::id::GuildId;
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, GuildId, MessageId};
 use serenity::prelude::*;
 
+
+/// How often conversations past `storage.retention_days` are looked for
+static RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 struct Handler {
-    messages: Arc<Mutex<Vec<String>>>
+    messages: Arc<Mutex<Vec<String>>>,
+    stats: Arc<Stats>,
+    shutdown: Arc<Shutdown>,
+    commands: Arc<CommandRegistry>,
 }
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn message(&self, _ctx: Context, _new_message: Message) {
-        let bot_id = env::var("BOT_ID")
-            .unwrap_or("0".to_owned())
-            .parse()
-            .unwrap_or(0);
+/// Permissions of the author of the message
+async fn permissions_of_message(message: &Message, guild_id: u64) -> UserPermissions {
+    let roles = message.member.as_ref().map(|member| role_ids(&member.roles)).unwrap_or_default();
+    UserPermissions::load(guild_id, message.author.id.as_u64().to_owned(), &roles).await
+}
 
-        if _new_message.author.id == bot_id {
-            return
+/// The message mentions the bot or replies to one of its messages
+fn is_addressed_to(message: &Message, bot_id: u64) -> bool {
+    message.mentions.iter().any(|user| user.id == bot_id)
+        || message.referenced_message.as_ref().is_some_and(|referenced| referenced.author.id == bot_id)
+}
+
+/// Turn of a message with the mention of the bot removed
+fn turn_without_mention(message: &Message, bot_id: u64) -> Turn {
+    let mut turn = Turn::from_message(message, bot_id);
+    turn.content = turn.content
+        .replace(&format!("<@{}>", bot_id), "")
+        .replace(&format!("<@!{}>", bot_id), "")
+        .trim()
+        .to_string();
+    turn
+}
+
+impl Handler {
+    /// Answers a mention or reply outside of the chat threads. The context is the reply chain
+    /// of the message, not the whole channel.
+    async fn answer_mention(&self, ctx: &Context, message: &Message, guild_id: u64) {
+        let config = config();
+        let bot_id = config.discord.bot_id;
+
+        match GuildSettings::load(guild_id).await {
+            Ok(settings) if settings.mentions_allowed_in(message.channel_id.as_u64().to_owned()) => {},
+            Ok(_) => return,
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot load guild settings: {}", e), &self.messages)
+                    .await.unwrap();
+                return
+            }
+        }
+
+        let _request = match self.shutdown.begin_request(message.channel_id.as_u64().to_owned()) {
+            Some(v) => v,
+            None => return
         };
 
-        let mut members = match _new_message
-            .channel_id
-            .get_thread_members(
-                &_ctx.http
-            )
-            .await {
-                Ok(v) => v,
-                Err(_) => return
+        self.stats.record_message(message.channel_id.as_u64().to_owned());
+
+        log_to_file(&format!("[INFO] - Get new mention: {:#?}", message), &self.messages)
+            .await.unwrap();
+
+        if !self.moderate_prompt(ctx, message, guild_id).await {
+            return
+        }
+
+        let typing = ctx.http
+            .start_typing(message.channel_id.as_u64().to_owned())
+            .expect("Error typing");
+
+        // Discord only includes the first referenced message, the older ones are fetched one by one.
+        let mut turns = vec![turn_without_mention(message, bot_id)];
+        let mut referenced = message.referenced_message.as_ref().map(|v| *v.to_owned());
+
+        while let Some(current) = referenced {
+            if turns.len() as u64 >= config.limits.history_messages {
+                break
+            }
+
+            turns.push(turn_without_mention(&current, bot_id));
+
+            referenced = match current.message_reference.and_then(|reference| reference.message_id) {
+                Some(id) => match message.channel_id.message(&ctx.http, id).await {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        log_to_file(&format!("[WARN] - Cannot fetch replied message: {:#?}", e), &self.messages)
+                            .await.unwrap();
+                        None
+                    }
+                },
+                None => None
             };
+        }
+
+        turns.reverse();
+
+        let model = model_of_user(message.author.id.as_u64().to_owned()).await;
+
+        let permissions = permissions_of_message(message, guild_id).await;
+        if !self.authorize_request(ctx, message, &permissions, Some(&model)).await {
+            typing.stop();
+            return
+        }
+
+        let mut history = utils::gpt::get_gpt_history_from_messages(&turns, None, turns.len());
+
+        if let Some(memory) = memory_message(message.author.id.as_u64().to_owned()).await {
+            history.insert(0, memory);
+        }
+
+        let request = AnswerRequest {
+            guild_id,
+            channel_id: message.channel_id.as_u64().to_owned(),
+            user_id: message.author.id.as_u64().to_owned(),
+            model: &model,
+            permissions: &permissions,
+        };
+        let answer = ask(&request, history, &self.messages, &self.stats).await;
+
+        typing.stop();
+
+        // Replying keeps the chain going, so the next reply gets this answer as context.
+        if let Err(e) = message.channel_id.send_message(&ctx.http, |m| {
+            m.content(answer.text()).reference_message(message);
+            add_images(m, answer.images())
+        }).await {
+            log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), &self.messages)
+                .await.unwrap();
+            self.stats.record_error();
+        }
+    }
+
+    /// Threads created by /create_chat are known from the conversation store, older ones
+    /// are recognized by their owner once and remembered.
+    async fn is_bot_thread(&self, ctx: &Context, channel_id: ChannelId) -> bool {
+        if let Some(v) = is_conversation_channel(channel_id.as_u64().to_owned()) {
+            return v
+        }
+
+        let is_bot_thread = match channel_id.to_channel(ctx).await {
+            Ok(Channel::Guild(channel)) => {
+                matches!(channel.kind, ChannelType::PublicThread | ChannelType::PrivateThread)
+                    && channel.owner_id.is_some_and(|id| id == config().discord.bot_id)
+            },
+            Ok(_) => false,
+            Err(e) => {
+                log_to_file(&format!("[WARN] - Cannot fetch channel {}: {:#?}", channel_id, e), &self.messages)
+                    .await.unwrap();
+                return false
+            }
+        };
+
+        remember_channel(channel_id.as_u64().to_owned(), is_bot_thread);
+        is_bot_thread
+    }
+
+    /// Loads the stored conversation of a bot thread. Threads created before the conversation
+    /// store existed get a record built from their recent Discord messages.
+    async fn load_conversation(&self, ctx: &Context, message: &Message) -> Option<Conversation> {
+        let thread_id = message.channel_id.as_u64().to_owned();
+
+        match Conversation::load(thread_id).await {
+            Ok(Some(v)) => return Some(v),
+            Ok(None) => {},
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot load conversation: {}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                return None
+            }
+        }
+
+        let config = config();
+
+        let history = match message.channel_id.messages(
+            &ctx.http, |builder| {
+                builder.before(message.id).limit(config.limits.history_messages)
+            }
+        ).await {
+            Ok(v) => v,
+            Err(e) => {
+                // println!("{:#?}", e);
+                log_to_file(&format!("[WARN] - Can`t seen messages: {:#?}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                return None
+            }
+        };
+
+        // A deleted conversation starts over, without the messages from before the deletion.
+        let deleted_at = match Tombstone::load(thread_id).await {
+            Ok(v) => v.map_or(i64::MIN, |tombstone| tombstone.deleted_at),
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot load tombstone: {}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                return None
+            }
+        };
+
+        let history: Vec<&Message> = history
+            .iter()
+            .rev()
+            .filter(|old_message| snowflake_millis(old_message.id.as_u64().to_owned()) > deleted_at)
+            .collect();
+
+        // The bot creates the threads, so the owner is whoever wrote in it first.
+        let owner_id = history
+            .iter()
+            .find(|old_message| !old_message.author.bot)
+            .map_or(message.author.id, |old_message| old_message.author.id)
+            .as_u64()
+            .to_owned();
+
+        let mut conversation = match message.channel_id.to_channel(ctx).await {
+            Ok(Channel::Guild(v)) => Conversation::new(thread_id, v.guild_id.as_u64().to_owned(), owner_id, &v.name),
+            Ok(Channel::Private(_)) => {
+                // Each user has a single DM channel with the bot, so it holds their personal history.
+                let mut conversation = Conversation::new(
+                    thread_id, 0, message.author.id.as_u64().to_owned(), &format!("DM with {}", message.author.name)
+                );
+                conversation.private = true;
+                conversation
+            },
+            _ => Conversation::new(thread_id, 0, owner_id, "Untitled"),
+        };
+
+        for old_message in history {
+            conversation.push_turn(Turn::from_message(old_message, config.discord.bot_id));
+        }
+
+        if let Err(e) = conversation.save().await {
+            log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                .await.unwrap();
+            self.stats.record_error();
+            return None
+        }
 
-        members.sort_by(|a, b| {
-            a.join_timestamp.cmp(&b.join_timestamp)
-        });
+        Some(conversation)
+    }
+
+    /// Answers the edited `question` again and puts the new text into the message of `answer`
+    async fn regenerate_answer(&self, ctx: &Context, guild_id: Option<GuildId>, thread_id: u64, question: Turn, answer: Turn) {
+        let _request = match self.shutdown.begin_request(thread_id) {
+            Some(v) => v,
+            None => return
+        };
+
+        let conversation = match Conversation::load(thread_id).await {
+            Ok(Some(v)) => v,
+            _ => return
+        };
+
+        let model = match answer.model.as_ref().or(conversation.settings.model.as_ref()) {
+            Some(v) => v.to_owned(),
+            None => model_of_user(question.author_id).await
+        };
+
+        // Edit events carry no member, the roles are fetched for the permission check.
+        let roles = match guild_id {
+            Some(id) => id.member(&ctx.http, question.author_id).await.map(|member| role_ids(&member.roles)).unwrap_or_default(),
+            None => vec![]
+        };
+        let permissions = UserPermissions::load(guild_id.map_or(0, |id| id.0), question.author_id, &roles).await;
+        if let Err(reply) = permissions.authorize_request(question.author_id, &model).await {
+            log_to_file(&format!("[INFO] - Request of user {} refused: {}", question.author_id, reply), &self.messages)
+                .await.unwrap();
+            return
+        }
+
+        let turns = conversation.context_turns();
+        let until = &turns[..turns.partition_point(|t| t.timestamp <= question.timestamp)];
+        let history = conversation_history(
+            &conversation, until, conversation.summary.as_ref(), question.author_id, &question.content, &self.messages
+        ).await;
+
+        let typing = ctx.http
+            .start_typing(thread_id)
+            .expect("Error typing");
+
+        let request = AnswerRequest {
+            guild_id: conversation.guild_id, channel_id: thread_id, user_id: question.author_id, model: &model, permissions: &permissions,
+        };
+        let regenerated = ask(&request, history, &self.messages, &self.stats).await;
+
+        typing.stop();
+
+        // The old answer stays after a failed request, but it no longer fits a question that was edited into a blocked one.
+        if let Answer::Failed = regenerated {
+            return
+        }
+
+        if let Err(e) = ChannelId(thread_id).edit_message(&ctx.http, answer.message_id, |m| {
+            m.content(regenerated.text());
+            attach_images(m, regenerated.images())
+        }).await {
+            log_to_file(&format!("[WARN] - Can`t edit message: {:#?}", e), &self.messages)
+                .await.unwrap();
+            self.stats.record_error();
+            return
+        }
+
+        let res = Conversation::update(thread_id, |conversation| {
+            match regenerated {
+                Answer::Text { text, .. } => if let Some(stored) = conversation.find_turn_mut(answer.message_id) {
+                    stored.content = text;
+                    stored.model = Some(model.trim_matches('"').to_owned());
+                    stored.ratings.clear();
+                },
+                _ => conversation.turns.retain(|t| t.message_id != answer.message_id),
+            }
+        }).await;
+
+        if let Err(e) = res {
+            log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                .await.unwrap();
+        }
+    }
+
+    /// Drops the turns of deleted messages from the conversation store
+    async fn remove_turns(&self, channel_id: ChannelId, message_ids: &[MessageId]) {
+        let thread_id = channel_id.as_u64().to_owned();
+        if is_conversation_channel(thread_id) != Some(true) {
+            return
+        }
+
+        let res = Conversation::update(thread_id, |conversation| {
+            let count = conversation.turns.len();
+            conversation.turns.retain(|t| !message_ids.iter().any(|id| *id == t.message_id));
+            count - conversation.turns.len()
+        }).await;
+
+        match res {
+            Ok(Some(removed)) if removed > 0 => log_to_file(
+                &format!("[INFO] - Removed {} deleted messages from thread {}", removed, thread_id), &self.messages
+            ).await.unwrap(),
+            Ok(_) => {},
+            Err(e) => log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                .await.unwrap(),
+        }
+    }
+
+    /// Replaces the placeholder title of a new chat with one generated from its first exchange
+    async fn update_title(&self, ctx: &Context, thread_id: u64, model: &str) {
+        let conversation = match Conversation::load(thread_id).await {
+            Ok(Some(v)) => v,
+            _ => return
+        };
+
+        let enabled = match GuildSettings::load(conversation.guild_id).await {
+            Ok(settings) => settings.auto_titles,
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot load guild settings: {}", e), &self.messages)
+                    .await.unwrap();
+                return
+            }
+        };
 
-        if members.len() == 0 || members.first().expect("Not members").user_id.unwrap() != bot_id {
+        let title = if enabled {
+            let started_at = Instant::now();
+            match utils::gpt::generate_title(model, conversation.context_turns()).await {
+                Ok(reply) => {
+                    self.stats.record_llm_request(started_at.elapsed(), reply.total_tokens);
+                    Some(reply.text.chars().take(THREAD_NAME_LIMIT).collect::<String>())
+                },
+                Err(e) => {
+                    log_to_file(&format!("[ERROR] - Cannot generate thread title: {:#?}", e), &self.messages)
+                        .await.unwrap();
+                    self.stats.record_error();
+                    None
+                }
+            }.filter(|title| !title.is_empty())
+        } else {
+            None
+        };
+
+        if let Some(title) = &title {
+            if let Err(e) = ChannelId(thread_id).edit_thread(&ctx.http, |thread| thread.name(title)).await {
+                log_to_file(&format!("[WARN] - Cannot rename thread: {:#?}", e), &self.messages)
+                    .await.unwrap();
+                return
+            }
+        }
+
+        // Only the first exchange is used, the placeholder stays if the generation was disabled or failed.
+        let res = Conversation::update(thread_id, |conversation| {
+            conversation.auto_title = false;
+            if let Some(title) = title {
+                conversation.title = title;
+            }
+        }).await;
+
+        if let Err(e) = res {
+            log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                .await.unwrap();
+        }
+    }
+
+    /// Folds the older turns into the rolling summary once the thread exceeds the history window
+    async fn update_summary(&self, thread_id: u64, model: &str) {
+        let config = config();
+
+        let conversation = match Conversation::load(thread_id).await {
+            Ok(Some(v)) => v,
+            _ => return
+        };
+
+        let pending = conversation.unsummarized_turns();
+        if pending.len() as u64 <= config.limits.history_messages {
             return
+        }
+
+        let old_turns = &pending[..pending.len() - config.summaries.keep_messages as usize];
+        let until = match old_turns.last() {
+            Some(v) => v.timestamp,
+            None => return
         };
 
-        log_to_file(&format!("[INFO] - Thread members: {:#?}", members), &self.messages)
+        let started_at = Instant::now();
+        let previous = conversation.summary.as_ref().map(|summary| summary.text.as_str());
+        let text = match utils::gpt::summarize_turns(model, previous, old_turns).await {
+            Ok(reply) => {
+                self.stats.record_llm_request(started_at.elapsed(), reply.total_tokens);
+                reply.text
+            },
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot summarize conversation: {:#?}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                return
+            }
+        };
+
+        let res = Conversation::update(thread_id, |updated| {
+            // A /reset while the summary was generated makes it obsolete.
+            if updated.context_start == conversation.context_start {
+                updated.summary = Some(ConversationSummary { text, until });
+            }
+        }).await;
+
+        match res {
+            Ok(_) => log_to_file(&format!("[INFO] - Updated summary of thread {}", thread_id), &self.messages)
+                .await.unwrap(),
+            Err(e) => log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                .await.unwrap(),
+        }
+    }
+    /// Asks the model for a fact worth remembering in the message, if the author opted in,
+    /// and lets them confirm it with the memory buttons
+    async fn suggest_memory(&self, ctx: &Context, message: &Message, model: &str) {
+        let user_id = message.author.id.as_u64().to_owned();
+
+        let memory = match UserMemory::load(user_id).await {
+            Ok(v) if v.suggestions && v.facts.len() < MAX_FACTS => v,
+            _ => return
+        };
+
+        let known: Vec<String> = memory.facts.iter().map(|fact| fact.text.to_owned()).collect();
+
+        let started_at = Instant::now();
+        let fact = match utils::gpt::suggest_memory_fact(model, &message.content, &known).await {
+            Ok(reply) => {
+                self.stats.record_llm_request(started_at.elapsed(), reply.total_tokens);
+                reply.text
+            },
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot suggest memory fact: {:#?}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                return
+            }
+        };
+
+        if fact.is_empty() || fact.contains(utils::gpt::NO_FACT) || fact.chars().count() > MAX_FACT_CHARS {
+            return
+        }
+
+        let sent = match message.channel_id.send_message(&ctx.http, |m| {
+            m
+                .content(format!("Should I remember this about you?\n> {}", fact))
+                .reference_message(message)
+                .components(|components| components::memory_buttons(components, user_id))
+        }).await {
+            Ok(v) => v,
+            Err(e) => {
+                log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), &self.messages)
+                    .await.unwrap();
+                return
+            }
+        };
+
+        if let Err(e) = UserMemory::update(user_id, |memory| memory.add_pending(sent.id.as_u64().to_owned(), &fact)).await {
+            log_to_file(&format!("[ERROR] - Cannot save memory: {}", e), &self.messages)
+                .await.unwrap();
+        }
+    }
+
+    /// Checks that the author may ask `model` (generate images for `None`) and counts the request,
+    /// or tells them why not. Returns whether the message may be answered.
+    async fn authorize_request(&self, ctx: &Context, message: &Message, permissions: &UserPermissions, model: Option<&str>) -> bool {
+        let user_id = message.author.id.as_u64().to_owned();
+
+        let res = match model {
+            Some(model) => permissions.authorize_request(user_id, model).await,
+            None => permissions.authorize_image_request(user_id).await,
+        };
+        let reply = match res {
+            Ok(()) => return true,
+            Err(v) => v
+        };
+
+        log_to_file(&format!("[INFO] - Request of user {} refused: {}", user_id, reply), &self.messages)
             .await.unwrap();
 
+        if let Err(e) = message.channel_id.send_message(&ctx.http, |m| m.content(reply).reference_message(message)).await {
+            log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), &self.messages)
+                .await.unwrap();
+        }
+
+        false
+    }
+
+    /// Checks the prompt with the moderation policy of the guild and tells the user about a block or a warning.
+    /// Returns whether the prompt may be answered.
+    async fn moderate_prompt(&self, ctx: &Context, message: &Message, guild_id: u64) -> bool {
+        let target = ModerationTarget {
+            guild_id, user_id: message.author.id.as_u64().to_owned(), channel_id: message.channel_id.as_u64().to_owned()
+        };
+
+        self.moderate_prompt_text(ctx, &target, message.id, &message.content).await
+    }
+
+    /// Like `moderate_prompt`, for a prompt known only by its text, like an edited message
+    async fn moderate_prompt_text(&self, ctx: &Context, target: &ModerationTarget, message_id: MessageId, content: &str) -> bool {
+        let config = config();
+
+        let verdict = moderate(target, ContentKind::Prompt, content, &self.messages).await;
+        let reply = match verdict {
+            ModerationVerdict::Allowed => return true,
+            ModerationVerdict::Warned => &config.texts.moderation_warning,
+            ModerationVerdict::Blocked => &config.texts.moderation_blocked,
+        };
+
+        let channel_id = ChannelId(target.channel_id);
+        if let Err(e) = channel_id.send_message(&ctx.http, |m| m.content(reply).reference_message((channel_id, message_id))).await {
+            log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), &self.messages)
+                .await.unwrap();
+        }
+
+        verdict != ModerationVerdict::Blocked
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, _ctx: Context, _new_message: Message) {
+        let config = config();
+        let bot_id = config.discord.bot_id;
+
+        if _new_message.author.id == bot_id || self.shutdown.is_requested() {
+            return
+        };
+
+        // Direct messages are a private conversation with the bot, in guilds only its own threads are answered.
+        if let Some(guild_id) = _new_message.guild_id {
+            if !self.is_bot_thread(&_ctx, _new_message.channel_id).await {
+                if is_addressed_to(&_new_message, bot_id) {
+                    self.answer_mention(&_ctx, &_new_message, guild_id.as_u64().to_owned()).await;
+                }
+                return
+            }
+        }
+
+        let _request = match self.shutdown.begin_request(_new_message.channel_id.as_u64().to_owned()) {
+            Some(v) => v,
+            None => return
+        };
+
+        self.stats.record_message(_new_message.channel_id.as_u64().to_owned());
+
         // println!("{:#?}", _new_message);
         log_to_file(&format!("[INFO] - Get new message from thread: {:#?}", _new_message), &self.messages)
             .await.unwrap();
 
-        let validate_send_image = utils::image::image_submission_check(&_new_message.content, &self.messages)
-            .await
-            .unwrap();
+        let thread_id = _new_message.channel_id.as_u64().to_owned();
+
+        let conversation = match self.load_conversation(&_ctx, &_new_message).await {
+            Some(v) => v,
+            None => return
+        };
+
+        let guild_id = _new_message.guild_id.map_or(0, |id| id.as_u64().to_owned());
+
+        if !self.moderate_prompt(&_ctx, &_new_message, guild_id).await {
+            return
+        }
+
+        let permissions = permissions_of_message(&_new_message, guild_id).await;
+
+        let validate_send_image = if config.images.enabled && permissions.images {
+            utils::image::image_submission_check(&_new_message.content, &self.messages)
+                .await
+                .unwrap()
+        } else {
+            (false, String::new())
+        };
 
         log_to_file(&format!("[INFO] - Check image: {:#?}", validate_send_image), &self.messages)
             .await.unwrap();
 
         if validate_send_image.0 {
+            if !self.authorize_request(&_ctx, &_new_message, &permissions, None).await {
+                return
+            }
+
+            self.stats.record_image_request();
+
             let copied_http_client = Arc::new(&_ctx.http);
 
             let typing = copied_http_client
                 .start_typing(_new_message.channel_id.as_u64().to_owned())
                 .expect("Error typing");
 
-            let images = utils::image::get_images(&_new_message.content, "1024x1024", &4, &self.messages)
-                .await;
+            // The prompt went through `moderate_prompt` already.
+            let images = utils::image::get_images(
+                &_new_message.content, &config.images.size, &config.images.count, None, &self.messages
+            ).await;
             
             typing.stop();
 
             if images.len() == 1 && !&images[0].starts_with("https://") {
+                self.stats.record_error();
+
                  _new_message
                     .channel_id
                     .send_message(
@@ -94,15 +681,12 @@ impl EventHandler for Handler {
                 return
             }
 
-            _new_message
+            let sent = _new_message
                 .channel_id
                 .send_message(
                     &_ctx.http, 
                     |m| {
-                        m.content(
-                            "Вот, что у меня получилось! Можешь попросить меня ещё что-нибудь нарисовать!"
-                                .to_owned()
-                        );
+                        m.content(&config.texts.images_ready);
 
                         for value in images {
                             m.add_file(
@@ -116,40 +700,37 @@ impl EventHandler for Handler {
                     }
                 ).await.unwrap();
 
-            return
-        }
+            let res = Conversation::update(thread_id, |conversation| {
+                conversation.push_turn(Turn::from_message(&_new_message, bot_id));
+                conversation.push_turn(Turn::from_message(&sent, bot_id));
+            }).await;
 
-        // if _new_message.mentions.iter().any(|m| m.id == bot_id) 
-        //   || (_new_message.referenced_message.is_some() && _new_message.referenced_message.unwrap().author.id == bot_id) {
-        let history = match _new_message.channel_id.messages(
-            &_ctx.http, |message| {
-                message.limit(30)
-            }
-        ).await {
-            Ok(v) => v,
-            Err(e) => {
-                // println!("{:#?}", e);
-                log_to_file(&format!("[WARN] - Can`t seen messages: {:#?}", e), &self.messages)
+            if let Err(e) = res {
+                log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
                     .await.unwrap();
-                return
             }
-        };
-        let users = Users::default().await.unwrap();
-        let mut model = "gpt-3.5-turbo";
 
-        let current_user = users.find_user_by_id(_new_message.author.id.as_u64().to_owned());
+            return
+        }
 
-        let mut users = Users::default().await.unwrap();
+        let author_id = _new_message.author.id.as_u64().to_owned();
+        let user_model = Users::update(|users| {
+            match users.find_user_by_id(author_id) {
+                Some(user) => user.model.to_owned(),
+                None => {
+                    users.add_user(User { user_id: author_id, model: config.models.default.to_string(), banned: false });
+                    config.models.default.to_string()
+                }
+            }
+        }).await.unwrap();
+        let mut model = user_model.as_str();
 
-        if current_user.is_none() {
-            let new_user = User {
-                user_id: _new_message.author.id.as_u64().to_owned(),
-                model: "gpt-3.5-turbo".to_string()
-            };
-            users.add_user(new_user);
-            users.write_users_datastorage().await.unwrap();
-        } else {
-            model = &current_user.unwrap().model;
+        if let Some(thread_model) = &conversation.settings.model {
+            model = thread_model;
+        }
+
+        if !self.authorize_request(&_ctx, &_new_message, &permissions, Some(model)).await {
+            return
         }
 
         let copied_http_client = Arc::new(&_ctx.http);
@@ -158,21 +739,47 @@ impl EventHandler for Handler {
             .start_typing(_new_message.channel_id.as_u64().to_owned())
             .expect("Error typing");
 
-        let history = utils::gpt::get_gpt_history_from_messages(history);
-        {
-            let mut messages_guard = self.messages.lock().unwrap();
-            messages_guard.push(format!("[INFO] - History: {:#?}", history));
-        }
-        let text = utils::gpt::send_gpt_message(model, history).await;
-        
+        let conversation = match Conversation::update(thread_id, |conversation| {
+            conversation.push_turn(Turn::from_message(&_new_message, bot_id));
+            conversation.to_owned()
+        }).await {
+            Ok(Some(v)) => v,
+            Ok(None) => return,
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                    .await.unwrap();
+                self.stats.record_error();
+                typing.stop();
+                return
+            }
+        };
+        let history = conversation_history(
+            &conversation, conversation.context_turns(), conversation.summary.as_ref(),
+            _new_message.author.id.as_u64().to_owned(), &_new_message.content, &self.messages
+        ).await;
+        show_in_tui(format!("[INFO] - History: {:#?}", history), &self.messages);
+
+        let request = AnswerRequest {
+            guild_id, channel_id: thread_id, user_id: _new_message.author.id.as_u64().to_owned(), model, permissions: &permissions,
+        };
+        let answer = ask(&request, history, &self.messages, &self.stats).await;
+        let succeeded = matches!(answer, Answer::Text { .. });
+
         typing.stop();
 
-        match _new_message
+        let sent = match _new_message
             .channel_id
             .send_message(
                 &_ctx.http, 
                 |m| {
-                    m.content(text.to_string())
+                    m.content(answer.text());
+
+                    // Error messages can not be regenerated or rated.
+                    if succeeded {
+                        m.components(components::answer_buttons);
+                    }
+
+                    add_images(m, answer.images())
                 }
             )
             .await {
@@ -181,71 +788,161 @@ impl EventHandler for Handler {
                     // println!("Cannot send new message: {}", e);
                     log_to_file(&format!("[WARN] - Can`t send message: {:#?}", e), &self.messages)
                         .await.unwrap();
+                    self.stats.record_error();
                     return
                 }
             };
+
+        // Error messages are not part of the conversation.
+        if succeeded {
+            let mut turn = Turn::from_message(&sent, bot_id);
+            turn.model = Some(model.trim_matches('"').to_owned());
+
+            let auto_title = match Conversation::update(thread_id, |conversation| {
+                conversation.push_turn(turn);
+                conversation.auto_title
+            }).await {
+                Ok(v) => v.unwrap_or(false),
+                Err(e) => {
+                    log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
+                        .await.unwrap();
+                    return
+                }
+            };
+
+            if auto_title {
+                self.update_title(&_ctx, thread_id, model).await;
+            }
+
+            if config.summaries.enabled {
+                self.update_summary(thread_id, model).await;
+            }
+
+            self.suggest_memory(&_ctx, &_new_message, model).await;
+        }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::ApplicationCommand(command) = interaction {
-            // println!("Received command interaction: {:#?}", command);
-            log_to_file(&format!("[INFO] - Received command interaction: {:#?}", command), &self.messages)
-                .await.unwrap();
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                // println!("Received command interaction: {:#?}", command);
+                log_to_file(&format!("[INFO] - Received command interaction: {:#?}", command), &self.messages)
+                    .await.unwrap();
 
-            let content = match command.data.name.as_str() {
-                "ping" => commands::ping::run(&command.data.options),
-                "create_chat" => commands::create_chat::run(&ctx, &self.messages, &command).await,
-                "model" => commands::model::run(&ctx, &command, &self.messages).await,
-                "info" => commands::info::run(&ctx, &command, &self.messages).await,
-                _ => "not implemented :(".to_string(),
-            };
+                let context = CommandContext::new(&ctx, &command, &self.commands, &self.messages, &self.stats);
 
-            if let Err(why) = command
-                .create_interaction_response(&ctx.http, |response| {
-                    response
-                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|message| {
-                            message.ephemeral(true)
-                                .content(content)
-                        })
-                })
-                .await
-            {
-                // println!("Cannot respond to slash command: {}", why);
-                log_to_file(&format!("[ERROR] - Cannot respond to slash command: {}", why), &self.messages)
+                let _request = self.shutdown.begin_request(command.channel_id.as_u64().to_owned());
+
+                let res = match _request {
+                    Some(_) => self.commands.dispatch(&context).await,
+                    None => {
+                        let notice = CommandResponse::ephemeral(&config().texts.shutdown_notice);
+                        commands::respond(&context, notice).await
+                    }
+                };
+
+                if let Err(why) = res {
+                    // println!("Cannot respond to slash command: {}", why);
+                    log_to_file(&format!("[ERROR] - Cannot respond to slash command: {}", why), &self.messages)
+                        .await.unwrap();
+                    self.stats.record_error();
+                }
+            },
+            Interaction::MessageComponent(component) => {
+                log_to_file(
+                    &format!("[INFO] - Received component interaction: {} on {}", component.data.custom_id, component.message.id),
+                    &self.messages
+                ).await.unwrap();
+
+                let _request = match self.shutdown.begin_request(component.channel_id.as_u64().to_owned()) {
+                    Some(v) => v,
+                    None => return
+                };
+
+                if let Err(why) = components::handle_component(&ctx, &component, &self.messages, &self.stats).await {
+                    log_to_file(&format!("[ERROR] - Cannot respond to component interaction: {}", why), &self.messages)
+                        .await.unwrap();
+                    self.stats.record_error();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    async fn message_update(&self, ctx: Context, event: MessageUpdateEvent) {
+        let content = match event.content {
+            Some(v) => v,
+            // Embeds resolved by Discord, not an edit by the user.
+            None => return
+        };
+
+        let thread_id = event.channel_id.as_u64().to_owned();
+        if is_conversation_channel(thread_id) != Some(true) || self.shutdown.is_requested() {
+            return
+        }
+
+        let author_id = match &event.author {
+            Some(author) if author.id != config().discord.bot_id => author.id.as_u64().to_owned(),
+            _ => return
+        };
+
+        // An edit into a blocked prompt is not stored, so it never reaches the model.
+        let target = ModerationTarget { guild_id: event.guild_id.map_or(0, |id| id.0), user_id: author_id, channel_id: thread_id };
+        if !self.moderate_prompt_text(&ctx, &target, event.id, &content).await {
+            return
+        }
+
+        let message_id = event.id.as_u64().to_owned();
+
+        // Stores the new text, and finds the answer to regenerate if the last question was edited.
+        let res = Conversation::update(thread_id, |conversation| {
+            let index = conversation.turns.iter().position(|t| t.message_id == message_id && t.role == TurnRole::User)?;
+            conversation.turns[index].content = content;
+
+            let answer = conversation.turns.get(index + 1).filter(|t| t.role == TurnRole::Assistant)?;
+            let is_latest = index + 2 == conversation.turns.len();
+
+            is_latest.then(|| (conversation.turns[index].to_owned(), answer.to_owned()))
+        }).await;
+
+        let (question, answer) = match res {
+            Ok(Some(Some(v))) => v,
+            Ok(_) => return,
+            Err(e) => {
+                log_to_file(&format!("[ERROR] - Cannot save conversation: {}", e), &self.messages)
                     .await.unwrap();
+                return
             }
-        }
+        };
+
+        log_to_file(&format!("[INFO] - Question {} was edited, regenerating the answer", message_id), &self.messages)
+            .await.unwrap();
+
+        self.regenerate_answer(&ctx, event.guild_id, thread_id, question, answer).await;
+    }
+
+    async fn message_delete(&self, _ctx: Context, channel_id: ChannelId, deleted_message_id: MessageId, _guild_id: Option<GuildId>) {
+        self.remove_turns(channel_id, &[deleted_message_id]).await;
+    }
+
+    async fn message_delete_bulk(&self, _ctx: Context, channel_id: ChannelId, multiple_deleted_messages_ids: Vec<MessageId>, _guild_id: Option<GuildId>) {
+        self.remove_turns(channel_id, &multiple_deleted_messages_ids).await;
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         // println!("{} is connected!", ready.user.name);
         log_to_file(&format!("[INFO] - {} is connected!", ready.user.name), &self.messages)
             .await.unwrap();
+        self.stats.set_guilds(ready.guilds.len());
 
-        let guild_id = GuildId(
-            env::var("GUILD_ID")
-                .expect("Expected GUILD_ID in environment")
-                .parse()
-                .expect("GUILD_ID must be an integer"),
-        );
-        
-        let commands = GuildId::set_application_commands(&guild_id, &ctx.http, |commands| {
-            commands
-                .create_application_command(|command| commands::ping::register(command))
-                .create_application_command(|command| commands::info::register(command))
-                .create_application_command(|command| commands::model::register(command))
-                .create_application_command(|command| commands::create_chat::register(command))
-        })
-        .await;
-
-        // println!("I now have the following guild slash commands: {:#?}", commands);
-        log_to_file(&format!("[INFO] - I now have the following guild slash commands: {:#?}", commands), &self.messages)
-            .await.unwrap();
+        register_commands(&ctx.http, &self.commands, &self.messages).await;
     }
 }
 
-pub async fn start_bot(messages: Arc<Mutex<Vec<String>>>) {
+/// Runs the bot until the client stops or a shutdown is requested through `shutdown`
+pub async fn start_bot(messages: Arc<Mutex<Vec<String>>>, stats: Arc<Stats>, shutdown: Arc<Shutdown>) {
+    shutdown.set_running();
+
     // TODO: Add logging 
 
     // Builder::new()
@@ -262,19 +959,139 @@ pub async fn start_bot(messages: Arc<Mutex<Vec<String>>>) {
     log_to_file("[INFO] - Starting bot...", &messages)
         .await.unwrap();
 
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    match load_conversation_channels().await {
+        Ok(count) => log_to_file(&format!("[INFO] - Loaded {} conversation threads", count), &messages)
+            .await.unwrap(),
+        Err(e) => log_to_file(&format!("[ERROR] - Cannot list conversations: {}", e), &messages)
+            .await.unwrap(),
+    }
+
+    // Configure the client with your Discord bot token from the configuration.
+    let token = config().discord.token.to_owned();
 
     let intents = GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT;
 
+    let registry = Arc::new(CommandRegistry::default());
+
     // Build our client.
     let mut client = Client::builder(&token, intents)
-        .event_handler(Handler { messages })
+        .event_handler(Handler {
+            messages: Arc::clone(&messages),
+            stats: Arc::clone(&stats),
+            shutdown: Arc::clone(&shutdown),
+            commands: Arc::clone(&registry),
+        })
         .await
         .expect("Error creating client");
 
+    set_moderation_http(Arc::clone(&client.cache_and_http.http));
+
+    // Once a shutdown was requested, drain the in-flight requests and close all shards,
+    // this makes `client.start()` return.
+    let shard_manager = Arc::clone(&client.shard_manager);
+    let http = Arc::clone(&client.cache_and_http.http);
+    let shutdown_messages = Arc::clone(&messages);
+    let coordinator = Arc::clone(&shutdown);
+    tokio::spawn(async move {
+        coordinator.wait_requested().await;
+
+        let config = config();
+
+        log_to_file(
+            &format!("[INFO] - Shutdown requested, waiting for {} in-flight requests...", coordinator.in_flight_count()),
+            &shutdown_messages
+        ).await.unwrap();
+
+        for channel_id in coordinator.pending_channels() {
+            if let Err(e) = ChannelId(channel_id).say(&http, &config.texts.shutdown_notice).await {
+                log_to_file(&format!("[WARN] - Can`t post shutdown notice: {:#?}", e), &shutdown_messages)
+                    .await.unwrap();
+            }
+        }
+
+        if !coordinator.wait_drained(Duration::from_secs(config.limits.shutdown_timeout_secs)).await {
+            log_to_file(
+                &format!("[WARN] - Shutdown timed out, abandoning {} requests", coordinator.in_flight_count()),
+                &shutdown_messages
+            ).await.unwrap();
+        }
+
+        flush_datastorage().await;
+
+        log_to_file("[INFO] - Shutting down shards...", &shutdown_messages)
+            .await.unwrap();
+        shard_manager.lock().await.shutdown_all().await;
+    });
+
+    // Register the slash commands again when a config reload changed the model list, the permission rules
+    // or the server, whose old commands are removed.
+    let http = Arc::clone(&client.cache_and_http.http);
+    let reload_messages = Arc::clone(&messages);
+    let registry = Arc::clone(&registry);
+    tokio::spawn(async move {
+        let mut updates = subscribe_config();
+        let mut models = updates.borrow().models.available.to_owned();
+        let mut permissions = updates.borrow().permissions.to_owned();
+        let mut guild_id = updates.borrow().discord.guild_id;
+
+        while updates.changed().await.is_ok() {
+            let new_models = updates.borrow().models.available.to_owned();
+            let new_permissions = updates.borrow().permissions.to_owned();
+            let new_guild_id = updates.borrow().discord.guild_id;
+            if new_guild_id != guild_id {
+                if let Err(e) = GuildId(guild_id).set_application_commands(&http, |commands| commands).await {
+                    log_to_file(&format!("[WARN] - Cannot remove the commands of the old server: {:#?}", e), &reload_messages)
+                        .await.unwrap();
+                }
+            }
+            if new_models != models || new_permissions != permissions || new_guild_id != guild_id {
+                models = new_models;
+                permissions = new_permissions;
+                guild_id = new_guild_id;
+                register_commands(&http, &registry, &reload_messages).await;
+            }
+        }
+    });
+
+    // Delete the conversations older than the configured retention, reloads of the value are picked up.
+    let retention_messages = Arc::clone(&messages);
+    tokio::spawn(async move {
+        loop {
+            let retention_days = config().storage.retention_days;
+
+            if retention_days > 0 {
+                match purge_expired_conversations(retention_days).await {
+                    Ok(0) => {},
+                    Ok(count) => log_to_file(
+                        &format!("[INFO] - Deleted {} conversations older than {} days", count, retention_days), &retention_messages
+                    ).await.unwrap(),
+                    Err(e) => log_to_file(&format!("[ERROR] - Cannot purge old conversations: {}", e), &retention_messages)
+                        .await.unwrap(),
+                }
+            }
+
+            tokio::time::sleep(RETENTION_INTERVAL).await;
+        }
+    });
+
+    // Periodically copy the shard state into the dashboard counters.
+    let shard_manager = Arc::clone(&client.shard_manager);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+
+            let manager = shard_manager.lock().await;
+            let runners = manager.runners.lock().await;
+
+            match runners.values().next() {
+                Some(runner) => stats.set_shard_status(&runner.stage.to_string(), runner.latency),
+                None => stats.set_shard_status("no shards", None),
+            }
+        }
+    });
+
     // Finally, start a single shard, and start listening to events.
     //
     // Shards will automatically attempt to reconnect, and will perform
@@ -282,5 +1099,9 @@ pub async fn start_bot(messages: Arc<Mutex<Vec<String>>>) {
     if let Err(why) = client.start().await {
         panic!("Client error: {:?}", why);
     }
+
+    log_to_file("[INFO] - Bot stopped.", &messages)
+        .await.unwrap();
+    shutdown.set_stopped();
 }
 
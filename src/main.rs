@@ -1,23 +1,111 @@
 use discord_gpt_bot::{
-    ui, utils::{env_load::env_load, datastorage::check_datastorage_exists}
+    start_bot, ui, utils::{
+        config::{reload_config_and_log, set_config, watch_config_file, ConfigSource, DEFAULT_CONFIG_PATH},
+        datastorage::{check_datastorage_exists, set_datastorage_folder},
+        export::{export_all, ExportFormat},
+        log::{log_to_file, set_echo_to_stdout, set_log_level, LogLevel},
+    }
 };
 
+use clap::Parser;
 use crossterm::{
     event::{DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::{error::Error, io};
+use std::{error::Error, io, path::PathBuf, process, sync::Arc};
 use tui::{
     backend::CrosstermBackend, Terminal,
 };
 
+/// Discord bot giving access to ChatGPT and image generation
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Start the bot immediately without the terminal UI, logging to stdout and the log file
+    #[arg(long, visible_alias = "no-tui")]
+    headless: bool,
+
+    /// Path to the TOML or YAML configuration file [default: config.toml, may be missing]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Folder for the datastorage files (overrides storage.data_dir)
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Minimal level of written log messages: error, warn or info (overrides logging.level)
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Validate the configuration, print every problem found and exit
+    #[arg(long)]
+    check_config: bool,
+
+    /// Export all stored conversations into DIR and exit
+    #[arg(long, value_name = "DIR")]
+    export_dir: Option<PathBuf>,
+
+    /// Format of the exported conversations: markdown, json or html
+    #[arg(long, value_name = "FORMAT", default_value = "markdown")]
+    export_format: ExportFormat,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // check and load full enviroment
-    env_load().await;
+    let cli = Cli::parse();
+
+    let source = ConfigSource {
+        required: cli.config.is_some(),
+        path: cli.config.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH)),
+        data_dir: cli.data_dir,
+        log_level: cli.log_level,
+    };
+
+    // load and validate the configuration
+    let config = match source.load() {
+        Ok(v) => v,
+        Err(errors) => {
+            eprintln!("Configuration {} is invalid:", source.path.display());
+            for error in errors {
+                eprintln!("  - {}", error);
+            }
+            process::exit(1);
+        }
+    };
+
+    if cli.check_config {
+        println!("Configuration {} is valid.", source.path.display());
+        return Ok(());
+    }
+
+    set_datastorage_folder(config.storage.data_dir.to_owned());
+    set_log_level(source.log_level(&config));
+    set_config(source, config);
+
     check_datastorage_exists().await;
 
+    if let Some(export_dir) = cli.export_dir {
+        return match export_all(&export_dir, cli.export_format).await {
+            Ok(count) => {
+                println!("Exported {} conversations to {}.", count, export_dir.display());
+                Ok(())
+            },
+            Err(e) => {
+                eprintln!("Cannot export conversations: {}", e);
+                process::exit(1);
+            }
+        };
+    }
+
+    let app = ui::App::default();
+
+    tokio::spawn(watch_config_file(Arc::clone(&app.messages)));
+
+    if cli.headless {
+        return run_headless(app).await;
+    }
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -25,9 +113,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // create app and run it
-    let app = ui::App::default();
-
+    // run the app
     let res = ui::run_app(&mut terminal, app).await;
 
     // restore terminal
@@ -45,3 +131,58 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Runs the bot without the TUI until it stops or the process receives SIGTERM/SIGINT.
+/// SIGHUP reloads the configuration.
+async fn run_headless(app: ui::App) -> Result<(), Box<dyn Error>> {
+    set_echo_to_stdout(true);
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup()).expect("Cannot listen for SIGHUP");
+        let messages = Arc::clone(&app.messages);
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                reload_config_and_log(&messages).await;
+            }
+        });
+    }
+
+    let mut bot = tokio::spawn(start_bot(
+        Arc::clone(&app.messages), Arc::clone(&app.stats), Arc::clone(&app.shutdown)
+    ));
+
+    tokio::select! {
+        res = &mut bot => return res.map_err(|e| e.into()),
+        signal = wait_for_shutdown_signal() => {
+            log_to_file(&format!("[INFO] - Received {}, stopping the bot...", signal), &app.messages)
+                .await?;
+        }
+    }
+
+    app.shutdown.request();
+    bot.await?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen for SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Cannot listen for SIGINT");
+
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() -> &'static str {
+    tokio::signal::ctrl_c().await.expect("Cannot listen for Ctrl+C");
+    "Ctrl+C"
+}
@@ -1,3 +1,3 @@
 pub mod banner;
 pub mod confirm_popup;
-
+pub mod stats_panel;
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Sparkline},
+    Frame,
+};
+
+use crate::utils::stats::Stats;
+
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(v) => format!("{} ms", v.as_millis()),
+        None => "-".to_owned(),
+    }
+}
+
+fn stat_line(name: &str, value: String) -> Spans<'static> {
+    Spans::from(vec![
+        Span::raw(format!("{:<16}", name)),
+        Span::styled(value, Style::default().add_modifier(Modifier::BOLD)),
+    ])
+}
+
+/// Renders the bot statistics panel with a request rate sparkline under it
+pub fn render<B: Backend>(f: &mut Frame<B>, area: Rect, stats: &Stats) {
+    let snapshot = stats.snapshot();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(12), Constraint::Min(3)].as_ref())
+        .split(area);
+
+    let status_color = match snapshot.shard_status.as_str() {
+        "connected" => Color::Green,
+        "not started" => Color::Gray,
+        _ => Color::Yellow,
+    };
+
+    let lines = vec![
+        Spans::from(vec![
+            Span::raw(format!("{:<16}", "Shard")),
+            Span::styled(
+                snapshot.shard_status.to_owned(),
+                Style::default().fg(status_color).add_modifier(Modifier::BOLD),
+            ),
+        ]),
+        stat_line("Gateway ping", format_latency(snapshot.shard_latency)),
+        stat_line("Guilds", snapshot.guilds.to_string()),
+        stat_line("Active threads", snapshot.active_threads.to_string()),
+        stat_line("Messages/min", snapshot.messages_per_minute.to_string()),
+        stat_line("LLM requests", snapshot.llm_requests.to_string()),
+        stat_line("Avg LLM latency", format_latency(snapshot.average_llm_latency)),
+        stat_line("Tokens today", snapshot.tokens_today.to_string()),
+        stat_line("Image requests", snapshot.image_requests.to_string()),
+        stat_line("Errors", snapshot.errors.to_string()),
+    ];
+
+    let stats_paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Stats:"));
+    f.render_widget(stats_paragraph, chunks[0]);
+
+    // Keep the most recent buckets when the panel is narrower than the history.
+    let width = chunks[1].width.saturating_sub(2) as usize;
+    let rate = &snapshot.request_rate[snapshot.request_rate.len().saturating_sub(width)..];
+
+    let sparkline = Sparkline::default()
+        .block(Block::default().borders(Borders::ALL).title("Requests (10s buckets):"))
+        .data(rate)
+        .style(Style::default().fg(Color::Cyan));
+    f.render_widget(sparkline, chunks[1]);
+}
@@ -1,8 +1,10 @@
 mod layout;
 use layout::banner;
 use layout::confirm_popup::centered_rect;
+use layout::stats_panel;
 
 use crate::start_bot;
+use crate::utils::{config::reload_config_and_log, shutdown::Shutdown, stats::Stats};
 
 use tokio::time::Duration;
 
@@ -21,6 +23,8 @@ use tui::{
 pub enum InputMode {
     Normal,
     Updating,
+    /// The bot is draining in-flight requests before exiting
+    Stopping,
 }
 
 /// App holds the state of the application
@@ -32,6 +36,10 @@ pub struct App {
     input_mode: InputMode,
     /// History of recorded messages
     pub messages: Arc<Mutex<Vec<String>>>,
+    /// Counters shown in the stats panel
+    pub stats: Arc<Stats>,
+    /// Coordinates stopping the running bot
+    pub shutdown: Arc<Shutdown>,
 
     show_confirm_popup: bool,
 
@@ -44,6 +52,8 @@ impl Default for App {
             input: String::new(),
             input_mode: InputMode::Normal,
             messages: Arc::new(Mutex::new(Vec::new())),
+            stats: Arc::new(Stats::default()),
+            shutdown: Arc::new(Shutdown::default()),
             show_confirm_popup: false,
             confirm_popup_selection: None
         }
@@ -71,19 +81,19 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io
         })?;
 
         let maybe_key_event = rx.recv_timeout(Duration::from_millis(100));
-        let key_event = if let Ok(event) = maybe_key_event {
-            Some(event)
-        } else {
-            None
-        };
+        let key_event = maybe_key_event.ok();
+
+        if let InputMode::Stopping = app.input_mode {
+            if !app.shutdown.is_running() {
+                return Ok(());
+            }
+        }
 
         match app.input_mode {
             InputMode::Normal => if let Some(Event::Key(event)) = key_event {
                 match event.code {
-                    KeyCode::Esc => {
-                        if app.show_confirm_popup {
-                            app.show_confirm_popup = false;
-                        }
+                    KeyCode::Esc if app.show_confirm_popup => {
+                        app.show_confirm_popup = false;
                     },
                     KeyCode::Char('s') => {            
                         if app.show_confirm_popup {
@@ -93,24 +103,26 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io
                         }
                         app.show_confirm_popup = !app.show_confirm_popup;
                     },
-                    KeyCode::Char('y') => {
-                        if app.show_confirm_popup {
-                            app.confirm_popup_selection = Some(true);
-                            app.show_confirm_popup = false;
-                            app.input_mode = InputMode::Updating;
-                        
-                            {
-                                let messages = Arc::clone(&app.messages);
-                                tokio::spawn(async move { start_bot(messages).await });
-                            }
-                        }   
-                    }
-                    KeyCode::Char('n') => {
-                        if app.show_confirm_popup  {
-                            app.confirm_popup_selection = Some(false);
-                            app.show_confirm_popup = false;
+                    KeyCode::Char('y') if app.show_confirm_popup => {
+                        app.confirm_popup_selection = Some(true);
+                        app.show_confirm_popup = false;
+                        app.input_mode = InputMode::Updating;
+
+                        {
+                            let messages = Arc::clone(&app.messages);
+                            let stats = Arc::clone(&app.stats);
+                            let shutdown = Arc::clone(&app.shutdown);
+                            tokio::spawn(async move { start_bot(messages, stats, shutdown).await });
                         }
                     }
+                    KeyCode::Char('n') if app.show_confirm_popup => {
+                        app.confirm_popup_selection = Some(false);
+                        app.show_confirm_popup = false;
+                    }
+                    KeyCode::Char('r') => {
+                        let messages = Arc::clone(&app.messages);
+                        tokio::spawn(async move { reload_config_and_log(&messages).await });
+                    },
                     KeyCode::Char('q') => {
                         return Ok(());
                     },
@@ -124,7 +136,15 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io
                     //     messages.push("[INFO] - Starting bot...".to_owned());
                     // },
                     KeyCode::Char('q') => {
-                        return Ok(());
+                        if !app.shutdown.is_running() {
+                            return Ok(());
+                        }
+                        app.shutdown.request();
+                        app.input_mode = InputMode::Stopping;
+                    },
+                    KeyCode::Char('r') => {
+                        let messages = Arc::clone(&app.messages);
+                        tokio::spawn(async move { reload_config_and_log(&messages).await });
                     },
                     KeyCode::Char(c) => {
                         app.input.push(c);
@@ -138,6 +158,11 @@ pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io
                     _ => {},
                 }
             },
+            InputMode::Stopping => if let Some(Event::Key(event)) = key_event {
+                if let KeyCode::Char('q') = event.code {
+                    return Ok(());
+                }
+            },
         }
     }
 }
@@ -172,7 +197,9 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
                 Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
                 Span::raw(" to exit, "),
                 Span::styled("s", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to start bot."),
+                Span::raw(" to start bot, "),
+                Span::styled("r", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(" to reload the config."),
             ],
             Style::default().add_modifier(Modifier::RAPID_BLINK),
         ),
@@ -180,10 +207,23 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             vec![
                 Span::raw("Press "),
                 Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit. If you want to restart the bot, you will have to restart the program.")
+                Span::raw(" to exit, "),
+                Span::styled("r", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(" to reload the config. If you want to restart the bot, you will have to restart the program.")
             ],
             Style::default(),
         ),
+        InputMode::Stopping => (
+            vec![
+                Span::raw(format!(
+                    "Stopping the bot, waiting for {} in-flight requests... Press ",
+                    app.shutdown.in_flight_count()
+                )),
+                Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(" again to exit immediately."),
+            ],
+            Style::default().fg(Color::Yellow),
+        ),
     };
     let mut text = Text::from(Spans::from(msg));
     text.patch_style(style);
@@ -202,9 +242,16 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             ListItem::new(content)
         })
         .collect();
+    let body_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(1), Constraint::Length(40)].as_ref())
+        .split(chunks[1]);
+
     let messages =
         List::new(messages).block(Block::default().borders(Borders::ALL).title("Bot logs:"));
-    f.render_widget(messages, chunks[1]);
+    f.render_widget(messages, body_chunks[0]);
+
+    stats_panel::render(f, body_chunks[1], &app.stats);
 
     if app.show_confirm_popup {
         let block = Block::default().borders(Borders::ALL);
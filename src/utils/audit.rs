@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    conversations::now_millis,
+    datastorage::{CappedLog, LogRecord},
+};
+
+/// Actions taken with /admin in a guild
+pub type AuditLog = CappedLog<AuditRecord>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditRecord {
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    pub admin_id: u64,
+    /// Name of the subcommand, e.g. `user ban`
+    pub action: String,
+    /// User the action was taken on
+    pub target_id: Option<u64>,
+    pub details: String,
+}
+
+impl LogRecord for AuditRecord {
+    const FOLDER: &'static str = "audit";
+    const MAX_RECORDS: usize = 1000;
+}
+
+impl AuditRecord {
+    pub fn new(admin_id: u64, action: &str, target_id: Option<u64>, details: &str) -> AuditRecord {
+        AuditRecord { timestamp: now_millis(), admin_id, action: action.to_owned(), target_id, details: details.to_owned() }
+    }
+}
@@ -0,0 +1,725 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use url::Url;
+
+use crate::utils::{
+    log::{log_to_file, set_log_level, LogLevel},
+    moderation::ModerationPolicy,
+    permissions::PermissionRule,
+};
+
+pub static DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// How often the config file modification time is checked
+static WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The active configuration lives in a watch channel, so it can be swapped atomically
+/// and subscribers get notified about reloads.
+static CONFIG: OnceLock<watch::Sender<Arc<Config>>> = OnceLock::new();
+static CONFIG_SOURCE: OnceLock<ConfigSource> = OnceLock::new();
+
+/// Where the configuration is read from, with the command line values taking priority over it
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    /// The path was given explicitly, so a missing file is an error instead of falling back to the defaults
+    pub required: bool,
+    pub data_dir: Option<PathBuf>,
+    pub log_level: Option<LogLevel>,
+}
+
+impl ConfigSource {
+    /// Reads the config file, applies environment and command line overrides and validates the result
+    pub fn load(&self) -> Result<Config, Vec<ConfigError>> {
+        let mut config = Config::read_file(&self.path, self.required)?;
+        let mut errors = config.apply_env();
+
+        if let Some(data_dir) = &self.data_dir {
+            config.storage.data_dir = data_dir.to_owned();
+        }
+
+        errors.extend(config.validate());
+
+        if errors.is_empty() { Ok(config) } else { Err(errors) }
+    }
+
+    pub fn log_level(&self, config: &Config) -> LogLevel {
+        self.log_level.unwrap_or(config.log_level())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub providers: ProvidersConfig,
+    pub models: ModelsConfig,
+    pub images: ImagesConfig,
+    pub storage: StorageConfig,
+    pub logging: LoggingConfig,
+    pub limits: LimitsConfig,
+    pub summaries: SummariesConfig,
+    pub knowledge: KnowledgeConfig,
+    pub moderation: ModerationConfig,
+    pub permissions: PermissionsConfig,
+    pub texts: TextsConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub token: String,
+    pub bot_id: u64,
+    pub guild_id: u64,
+    /// Users who may change the bot-wide data with /admin: models, bans and usage, and see the counters of the bot
+    pub owners: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvidersConfig {
+    /// URL of the /v1/chat/completions endpoint
+    pub api_base: String,
+    /// URL of the /v1/images/generations endpoint
+    pub api_base_image: String,
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelsConfig {
+    /// Model used by users who have not picked one with /model
+    pub default: String,
+    pub temperature: f32,
+    /// Models offered by the /model command
+    pub available: Vec<ModelConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Model name sent to the provider
+    pub name: String,
+    /// Name shown in Discord
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    pub enabled: bool,
+    pub size: String,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub data_dir: PathBuf,
+    /// Conversations without a message for this many days are deleted, 0 keeps them forever
+    pub retention_days: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub path: PathBuf,
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Amount of thread messages sent to the model as context
+    pub history_messages: u64,
+    /// Timeout of a single request to the chat provider
+    pub request_timeout_secs: u64,
+    /// How long a shutdown waits for in-flight requests
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummariesConfig {
+    /// Compress the older turns of a thread into a summary once it exceeds `limits.history_messages`
+    pub enabled: bool,
+    /// Amount of the newest turns kept verbatim when the summary is updated
+    pub keep_messages: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KnowledgeConfig {
+    /// `provider` calls `embeddings_url`, `hashing` embeds locally and works offline
+    pub embedder: String,
+    /// URL of the /v1/embeddings endpoint
+    pub embeddings_url: String,
+    pub embeddings_model: String,
+    /// Length of a document chunk in characters
+    pub chunk_chars: usize,
+    /// Characters shared by neighbouring chunks
+    pub chunk_overlap: usize,
+    /// Amount of chunks added to the system prompt
+    pub top_k: usize,
+    /// Largest file accepted by /knowledge add
+    pub max_file_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// URL of the /v1/moderations endpoint, used by guilds that enable it with /moderation
+    pub endpoint_url: String,
+    /// Policy for direct messages with the bot, which belong to no server
+    pub direct_messages: ModerationPolicy,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionsConfig {
+    /// Applies to everyone, unless a server sets a rule for @everyone with /permissions
+    pub default: PermissionRule,
+    /// Rules for roles and users, added to the default
+    pub rules: Vec<PermissionRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextsConfig {
+    pub chat_error: String,
+    pub images_ready: String,
+    pub images_failed: String,
+    /// Posted to threads with pending requests when the bot stops
+    pub shutdown_notice: String,
+    /// Reply to prompts blocked by the moderation policy of the server
+    pub moderation_blocked: String,
+    /// Reply to prompts flagged by a policy with the `warn` action
+    pub moderation_warning: String,
+    /// Posted instead of answers blocked by the moderation policy
+    pub moderation_answer_blocked: String,
+}
+
+impl Default for ProvidersConfig {
+    fn default() -> Self {
+        ProvidersConfig {
+            api_base: "https://api.openai.com/v1/chat/completions".to_owned(),
+            api_base_image: "https://api.openai.com/v1/images/generations".to_owned(),
+            api_key: String::new(),
+        }
+    }
+}
+
+impl Default for ModelsConfig {
+    fn default() -> Self {
+        ModelsConfig {
+            default: "gpt-3.5-turbo".to_owned(),
+            temperature: 1.0,
+            available: vec![
+                ModelConfig { name: "gpt-3.5-turbo".to_owned(), label: "ChatGPT 3.5-turbo".to_owned() },
+                ModelConfig { name: "gpt-4".to_owned(), label: "ChatGPT 4".to_owned() },
+            ],
+        }
+    }
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig { enabled: true, size: "1024x1024".to_owned(), count: 4 }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig { data_dir: PathBuf::from("data"), retention_days: 0 }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { path: PathBuf::from("bot.log"), level: "info".to_owned() }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { history_messages: 30, request_timeout_secs: 60, shutdown_timeout_secs: 30 }
+    }
+}
+
+impl Default for SummariesConfig {
+    fn default() -> Self {
+        SummariesConfig { enabled: true, keep_messages: 10 }
+    }
+}
+
+impl Default for KnowledgeConfig {
+    fn default() -> Self {
+        KnowledgeConfig {
+            embedder: "provider".to_owned(),
+            embeddings_url: "https://api.openai.com/v1/embeddings".to_owned(),
+            embeddings_model: "text-embedding-ada-002".to_owned(),
+            chunk_chars: 1000,
+            chunk_overlap: 200,
+            top_k: 4,
+            max_file_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            endpoint_url: "https://api.openai.com/v1/moderations".to_owned(),
+            direct_messages: ModerationPolicy::default(),
+        }
+    }
+}
+
+impl Default for TextsConfig {
+    fn default() -> Self {
+        TextsConfig {
+            chat_error: "Error.".to_owned(),
+            images_ready: "Вот, что у меня получилось! Можешь попросить меня ещё что-нибудь нарисовать!".to_owned(),
+            images_failed: "Простите, но у меня не получилось ничего нарисовать. Попробуйте позже(".to_owned(),
+            shutdown_notice: "The bot is restarting, your request will be answered if it finishes in time.".to_owned(),
+            moderation_blocked: "Your message was blocked by the moderation policy of this server.".to_owned(),
+            moderation_warning: "Your message was flagged by the moderation policy of this server, please keep to its rules.".to_owned(),
+            moderation_answer_blocked: "The answer was blocked by the moderation policy of this server.".to_owned(),
+        }
+    }
+}
+
+/// A single problem found in the configuration
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    /// Dotted path of the field, e.g. `discord.token`
+    pub path: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(path: &str, message: impl Into<String>) -> ConfigError {
+        ConfigError { path: path.to_owned(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Config {
+    /// Reads the config file, a missing file gives the defaults unless it is `required`
+    fn read_file(path: &Path, required: bool) -> Result<Config, Vec<ConfigError>> {
+        let file_path = path.display().to_string();
+
+        let content = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(e) => return Err(vec![ConfigError::new(&file_path, format!("cannot read file: {}", e))]),
+        };
+
+        let is_yaml = matches!(
+            path.extension().and_then(|v| v.to_str()),
+            Some("yaml") | Some("yml")
+        );
+
+        let parsed = if is_yaml {
+            serde_yaml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        };
+
+        parsed.map_err(|e| vec![ConfigError::new(&file_path, e.trim_end().to_owned())])
+    }
+
+    /// Environment variables (and the optional .env file) take priority over the file values
+    fn apply_env(&mut self) -> Vec<ConfigError> {
+        dotenv::dotenv().ok();
+
+        let mut errors = vec![];
+
+        fn parse_env<T: FromStr>(name: &str, target: &mut T, errors: &mut Vec<ConfigError>) {
+            if let Ok(value) = env::var(name) {
+                match value.parse() {
+                    Ok(v) => *target = v,
+                    Err(_) => errors.push(ConfigError::new(
+                        &format!("env {}", name), format!("cannot parse `{}`", value)
+                    )),
+                }
+            }
+        }
+
+        parse_env("DISCORD_TOKEN", &mut self.discord.token, &mut errors);
+        parse_env("BOT_ID", &mut self.discord.bot_id, &mut errors);
+        parse_env("GUILD_ID", &mut self.discord.guild_id, &mut errors);
+        parse_env("API_BASE", &mut self.providers.api_base, &mut errors);
+        parse_env("API_BASE_IMAGE", &mut self.providers.api_base_image, &mut errors);
+        parse_env("API_KEY", &mut self.providers.api_key, &mut errors);
+        parse_env("DEFAULT_MODEL", &mut self.models.default, &mut errors);
+        parse_env("DATA_DIR", &mut self.storage.data_dir, &mut errors);
+        parse_env("LOG_PATH", &mut self.logging.path, &mut errors);
+        parse_env("LOG_LEVEL", &mut self.logging.level, &mut errors);
+
+        errors
+    }
+
+    /// Collects every problem instead of stopping at the first one
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = vec![];
+
+        if self.discord.token.trim().is_empty() {
+            errors.push(ConfigError::new("discord.token", "must not be empty"));
+        }
+        if self.discord.bot_id == 0 {
+            errors.push(ConfigError::new("discord.bot_id", "must be set to the bot user id"));
+        }
+        if self.discord.guild_id == 0 {
+            errors.push(ConfigError::new("discord.guild_id", "must be set to the server id"));
+        }
+
+        for (path, value) in [
+            ("providers.api_base", &self.providers.api_base),
+            ("providers.api_base_image", &self.providers.api_base_image),
+        ] {
+            if let Err(e) = Url::parse(value) {
+                errors.push(ConfigError::new(path, format!("`{}` is not a valid URL: {}", value, e)));
+            }
+        }
+        if self.providers.api_key.trim().is_empty() {
+            errors.push(ConfigError::new("providers.api_key", "must not be empty"));
+        }
+
+        if self.models.available.is_empty() {
+            errors.push(ConfigError::new("models.available", "at least one model is required"));
+        }
+        if self.models.available.len() > 25 {
+            errors.push(ConfigError::new("models.available", "Discord allows at most 25 choices"));
+        }
+        for (i, model) in self.models.available.iter().enumerate() {
+            if model.name.trim().is_empty() {
+                errors.push(ConfigError::new(&format!("models.available[{}].name", i), "must not be empty"));
+            }
+            if model.label.trim().is_empty() || model.label.chars().count() > 100 {
+                errors.push(ConfigError::new(
+                    &format!("models.available[{}].label", i), "must be 1-100 characters long"
+                ));
+            }
+        }
+        if !self.models.available.iter().any(|m| m.name == self.models.default) {
+            errors.push(ConfigError::new(
+                "models.default", format!("`{}` is not listed in models.available", self.models.default)
+            ));
+        }
+        if !(0.0..=2.0).contains(&self.models.temperature) {
+            errors.push(ConfigError::new("models.temperature", "must be between 0 and 2"));
+        }
+
+        let size_is_valid = self.images.size
+            .split_once('x')
+            .is_some_and(|(w, h)| w.parse::<u32>().is_ok() && h.parse::<u32>().is_ok());
+        if !size_is_valid {
+            errors.push(ConfigError::new("images.size", format!("`{}` must look like WxH", self.images.size)));
+        }
+        if !(1..=10).contains(&self.images.count) {
+            errors.push(ConfigError::new("images.count", "must be between 1 and 10"));
+        }
+
+        if self.storage.data_dir.as_os_str().is_empty() {
+            errors.push(ConfigError::new("storage.data_dir", "must not be empty"));
+        }
+        if self.storage.retention_days > 36500 {
+            errors.push(ConfigError::new("storage.retention_days", "must be at most 36500 (100 years), 0 keeps the conversations forever"));
+        }
+
+        if self.logging.path.as_os_str().is_empty() {
+            errors.push(ConfigError::new("logging.path", "must not be empty"));
+        }
+        if let Err(e) = LogLevel::from_str(&self.logging.level) {
+            errors.push(ConfigError::new("logging.level", e));
+        }
+
+        if !(1..=100).contains(&self.limits.history_messages) {
+            errors.push(ConfigError::new("limits.history_messages", "must be between 1 and 100"));
+        }
+        if self.limits.request_timeout_secs == 0 {
+            errors.push(ConfigError::new("limits.request_timeout_secs", "must be greater than 0"));
+        }
+
+        if self.summaries.enabled && self.summaries.keep_messages >= self.limits.history_messages {
+            errors.push(ConfigError::new("summaries.keep_messages", "must be less than limits.history_messages"));
+        }
+
+        if !["provider", "hashing"].contains(&self.knowledge.embedder.as_str()) {
+            errors.push(ConfigError::new(
+                "knowledge.embedder", format!("`{}` must be `provider` or `hashing`", self.knowledge.embedder)
+            ));
+        }
+        if let Err(e) = Url::parse(&self.knowledge.embeddings_url) {
+            errors.push(ConfigError::new(
+                "knowledge.embeddings_url", format!("`{}` is not a valid URL: {}", self.knowledge.embeddings_url, e)
+            ));
+        }
+        if self.knowledge.embeddings_model.trim().is_empty() {
+            errors.push(ConfigError::new("knowledge.embeddings_model", "must not be empty"));
+        }
+        if !(100..=8000).contains(&self.knowledge.chunk_chars) {
+            errors.push(ConfigError::new("knowledge.chunk_chars", "must be between 100 and 8000"));
+        }
+        if self.knowledge.chunk_overlap >= self.knowledge.chunk_chars / 2 {
+            errors.push(ConfigError::new("knowledge.chunk_overlap", "must be less than half of knowledge.chunk_chars"));
+        }
+        if !(1..=20).contains(&self.knowledge.top_k) {
+            errors.push(ConfigError::new("knowledge.top_k", "must be between 1 and 20"));
+        }
+        if self.knowledge.max_file_bytes == 0 {
+            errors.push(ConfigError::new("knowledge.max_file_bytes", "must be greater than 0"));
+        }
+
+        if let Err(e) = Url::parse(&self.moderation.endpoint_url) {
+            errors.push(ConfigError::new(
+                "moderation.endpoint_url", format!("`{}` is not a valid URL: {}", self.moderation.endpoint_url, e)
+            ));
+        }
+        for pattern in &self.moderation.direct_messages.blocked_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                errors.push(ConfigError::new(
+                    "moderation.direct_messages.blocked_patterns", format!("`{}` is not a valid pattern: {}", pattern, e)
+                ));
+            }
+        }
+
+        let rules = std::iter::once(("permissions.default".to_owned(), &self.permissions.default))
+            .chain(self.permissions.rules.iter().enumerate().map(|(i, rule)| (format!("permissions.rules[{}]", i), rule)));
+        for (path, rule) in rules {
+            if path != "permissions.default" && rule.roles.is_empty() && rule.users.is_empty() {
+                errors.push(ConfigError::new(&path, "must list at least one role or user"));
+            }
+            for model in rule.models.iter().flatten() {
+                if !self.models.available.iter().any(|m| m.name == *model) {
+                    errors.push(ConfigError::new(
+                        &format!("{}.models", path), format!("`{}` is not listed in models.available", model)
+                    ));
+                }
+            }
+            if rule.daily_requests == Some(0) {
+                errors.push(ConfigError::new(&format!("{}.daily_requests", path), "must be greater than 0"));
+            }
+        }
+
+        for (path, value) in [
+            ("texts.chat_error", &self.texts.chat_error),
+            ("texts.images_ready", &self.texts.images_ready),
+            ("texts.images_failed", &self.texts.images_failed),
+            ("texts.shutdown_notice", &self.texts.shutdown_notice),
+            ("texts.moderation_blocked", &self.texts.moderation_blocked),
+            ("texts.moderation_warning", &self.texts.moderation_warning),
+            ("texts.moderation_answer_blocked", &self.texts.moderation_answer_blocked),
+        ] {
+            if value.is_empty() || value.chars().count() > 2000 {
+                errors.push(ConfigError::new(path, "must be 1-2000 characters long"));
+            }
+        }
+
+        errors
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        LogLevel::from_str(&self.logging.level).unwrap_or(LogLevel::Info)
+    }
+
+    /// Label of the model for display, falls back to the raw name
+    pub fn model_label<'a>(&'a self, name: &'a str) -> &'a str {
+        self.models.available
+            .iter()
+            .find(|m| m.name == name)
+            .map_or(name, |m| m.label.as_str())
+    }
+}
+
+/// Makes the loaded configuration globally available. Can only be called once.
+pub fn set_config(source: ConfigSource, config: Config) {
+    CONFIG_SOURCE.set(source).expect("Configuration is already set");
+    CONFIG.set(watch::channel(Arc::new(config)).0).expect("Configuration is already set");
+}
+
+/// The active configuration, `set_config` has to be called on startup.
+///
+/// Keep the returned `Arc` for the duration of one event, so a reload in the middle of it
+/// does not mix old and new values.
+pub fn config() -> Arc<Config> {
+    Arc::clone(&CONFIG.get().expect("Configuration is not loaded").borrow())
+}
+
+/// Receiver notified every time a new configuration is swapped in
+pub fn subscribe_config() -> watch::Receiver<Arc<Config>> {
+    CONFIG.get().expect("Configuration is not loaded").subscribe()
+}
+
+/// Result of a successful reload
+pub struct ConfigReload {
+    /// The models, permission rules or server changed, so the slash commands have to be registered again
+    pub commands_changed: bool,
+    /// Changed fields that only take effect after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Reads the config file again and swaps it in if it is valid, otherwise the old one stays active
+pub fn reload_config() -> Result<ConfigReload, Vec<ConfigError>> {
+    let source = CONFIG_SOURCE.get().expect("Configuration is not loaded");
+    let mut new_config = source.load()?;
+
+    let old_config = config();
+    let mut restart_required = vec![];
+
+    // The gateway connection and open storage files keep using the startup values.
+    if new_config.discord.token != old_config.discord.token {
+        restart_required.push("discord.token");
+        new_config.discord.token = old_config.discord.token.to_owned();
+    }
+    if new_config.storage.data_dir != old_config.storage.data_dir {
+        restart_required.push("storage.data_dir");
+        new_config.storage.data_dir = old_config.storage.data_dir.to_owned();
+    }
+    set_log_level(source.log_level(&new_config));
+
+    let commands_changed = new_config.models.available != old_config.models.available
+        || new_config.permissions != old_config.permissions
+        || new_config.discord.guild_id != old_config.discord.guild_id;
+
+    CONFIG.get().unwrap().send_replace(Arc::new(new_config));
+
+    Ok(ConfigReload { commands_changed, restart_required })
+}
+
+/// Reloads the configuration and writes the outcome to the bot logs
+pub async fn reload_config_and_log(messages: &Arc<Mutex<Vec<String>>>) {
+    let message = match reload_config() {
+        Ok(reload) => {
+            let mut message = "[INFO] - Configuration reloaded.".to_owned();
+            if reload.commands_changed {
+                message.push_str(" Slash commands will be registered again.");
+            }
+            if !reload.restart_required.is_empty() {
+                message.push_str(&format!(
+                    " Changes of {} need a restart.", reload.restart_required.join(", ")
+                ));
+            }
+            message
+        },
+        Err(errors) => format!(
+            "[ERROR] - Configuration was not reloaded: {}",
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")
+        ),
+    };
+
+    log_to_file(&message, messages).await.unwrap();
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watches the config file on disk and reloads it after every change
+pub async fn watch_config_file(messages: Arc<Mutex<Vec<String>>>) {
+    let path = &CONFIG_SOURCE.get().expect("Configuration is not loaded").path;
+    let mut last_modified = modified_at(path);
+
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+
+        let modified = modified_at(path);
+        if modified != last_modified {
+            last_modified = modified;
+            reload_config_and_log(&messages).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.discord.token = "token".to_owned();
+        config.discord.bot_id = 1;
+        config.discord.guild_id = 2;
+        config.providers.api_key = "key".to_owned();
+        config
+    }
+
+    fn error_paths(config: &Config) -> Vec<String> {
+        config.validate().into_iter().map(|error| error.path).collect()
+    }
+
+    #[test]
+    fn defaults_with_credentials_are_valid() {
+        assert!(error_paths(&valid_config()).is_empty());
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("config.example.toml");
+        assert!(Config::read_file(&path, true).is_ok());
+    }
+
+    #[test]
+    fn missing_credentials_are_reported_together() {
+        assert_eq!(
+            error_paths(&Config::default()),
+            ["discord.token", "discord.bot_id", "discord.guild_id", "providers.api_key"]
+        );
+    }
+
+    #[test]
+    fn default_model_must_be_available() {
+        let mut config = valid_config();
+        config.models.default = "unknown".to_owned();
+
+        assert_eq!(error_paths(&config), ["models.default"]);
+    }
+
+    #[test]
+    fn retention_days_are_bounded() {
+        let mut config = valid_config();
+        config.storage.retention_days = 36500;
+        assert!(error_paths(&config).is_empty());
+
+        config.storage.retention_days = u64::MAX;
+        assert_eq!(error_paths(&config), ["storage.retention_days"]);
+    }
+
+    #[test]
+    fn summaries_must_keep_fewer_messages_than_the_history() {
+        let mut config = valid_config();
+        config.summaries.keep_messages = config.limits.history_messages;
+
+        assert_eq!(error_paths(&config), ["summaries.keep_messages"]);
+    }
+
+    #[test]
+    fn invalid_direct_message_patterns_are_reported() {
+        let mut config = valid_config();
+        config.moderation.direct_messages.blocked_patterns = vec!["(".to_owned()];
+
+        assert_eq!(error_paths(&config), ["moderation.direct_messages.blocked_patterns"]);
+    }
+
+    #[test]
+    fn permission_rules_must_name_someone() {
+        let mut config = valid_config();
+        config.permissions.rules.push(PermissionRule::default());
+
+        assert_eq!(error_paths(&config), ["permissions.rules[0]"]);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use serenity::model::channel::Message;
+
+use crate::utils::datastorage::{
+    list_datastorage_dir, read_datastorage_file, remove_datastorage_file, update_datastorage_file, write_datastorage_file,
+};
+use crate::utils::search::remove_index;
+
+static CONVERSATIONS_FOLDER: &str = "conversations";
+static TOMBSTONES_FOLDER: &str = "tombstones";
+
+/// Whether a channel holds a conversation of the bot, so messages elsewhere are skipped
+/// without asking Discord. A channel never changes its owner, so both answers can be cached.
+static KNOWN_CHANNELS: std::sync::Mutex<Option<HashMap<u64, bool>>> = std::sync::Mutex::new(None);
+
+/// Milliseconds since the Discord epoch are stored in the upper bits of every id
+static DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+
+/// Unix time in milliseconds at which a Discord object was created
+pub fn snowflake_millis(id: u64) -> i64 {
+    (id >> 22) as i64 + DISCORD_EPOCH_MS
+}
+
+pub fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// `Some(true)` for channels with a stored conversation, `None` if the channel was not seen yet
+pub fn is_conversation_channel(channel_id: u64) -> Option<bool> {
+    KNOWN_CHANNELS.lock().unwrap().as_ref().and_then(|channels| channels.get(&channel_id).copied())
+}
+
+pub fn remember_channel(channel_id: u64, is_conversation: bool) {
+    KNOWN_CHANNELS.lock().unwrap().get_or_insert_with(HashMap::new).insert(channel_id, is_conversation);
+}
+
+fn forget_channel(channel_id: u64) {
+    if let Some(channels) = KNOWN_CHANNELS.lock().unwrap().as_mut() {
+        channels.remove(&channel_id);
+    }
+}
+
+/// Fills the channel cache with the stored conversations, returns their amount
+pub async fn load_conversation_channels() -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let ids = Conversation::list_ids().await?;
+
+    for id in &ids {
+        remember_channel(*id, true);
+    }
+
+    Ok(ids.len())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TurnRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// A single message of a conversation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Turn {
+    /// Discord message id, 0 for turns that were never posted to Discord
+    pub message_id: u64,
+    pub author_id: u64,
+    pub role: TurnRole,
+    pub content: String,
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    /// Model that produced an assistant turn
+    #[serde(default)]
+    pub model: Option<String>,
+    /// URLs of attached files and generated images
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// Thumbs up/down given to an assistant turn
+    #[serde(default)]
+    pub ratings: Vec<Rating>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rating {
+    pub user_id: u64,
+    pub positive: bool,
+}
+
+impl Turn {
+    pub fn from_message(message: &Message, bot_id: u64) -> Turn {
+        Turn {
+            message_id: message.id.as_u64().to_owned(),
+            author_id: message.author.id.as_u64().to_owned(),
+            role: if message.author.id == bot_id { TurnRole::Assistant } else { TurnRole::User },
+            content: message.content.to_owned(),
+            timestamp: snowflake_millis(message.id.as_u64().to_owned()),
+            model: None,
+            attachments: message.attachments.iter().map(|a| a.url.to_owned()).collect(),
+            ratings: vec![],
+        }
+    }
+}
+
+/// Per-thread settings, copied by /fork
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConversationSettings {
+    /// Model pinned to the thread, otherwise the owner's /model choice is used
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Add matching excerpts of the server knowledge base to the prompt (set by /knowledge use)
+    #[serde(default)]
+    pub knowledge: bool,
+}
+
+/// Rolling summary of the older turns, sent instead of them once the history gets too long
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConversationSummary {
+    pub text: String,
+    /// Timestamp of the newest turn covered by the summary
+    pub until: i64,
+}
+
+/// A chat thread with everything said in it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Conversation {
+    pub thread_id: u64,
+    pub guild_id: u64,
+    pub owner_id: u64,
+    pub title: String,
+    /// Private thread or direct messages, only visible to the owner
+    #[serde(default)]
+    pub private: bool,
+    /// The thread has a placeholder title that gets replaced after the first answer
+    #[serde(default)]
+    pub auto_title: bool,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    #[serde(default)]
+    pub settings: ConversationSettings,
+    /// Turns older than this moment are ignored by the history builder (set by /reset)
+    #[serde(default)]
+    pub context_start: Option<i64>,
+    #[serde(default)]
+    pub summary: Option<ConversationSummary>,
+    /// Thread this conversation was forked from
+    #[serde(default)]
+    pub forked_from: Option<u64>,
+    #[serde(default)]
+    pub turns: Vec<Turn>,
+}
+
+fn conversation_file(thread_id: u64) -> String {
+    format!("{}/{}.bson", CONVERSATIONS_FOLDER, thread_id)
+}
+
+/// Left behind by a deleted conversation, so the messages before the deletion are not imported again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub thread_id: u64,
+    /// Unix time in milliseconds
+    pub deleted_at: i64,
+}
+
+fn tombstone_file(thread_id: u64) -> String {
+    format!("{}/{}.bson", TOMBSTONES_FOLDER, thread_id)
+}
+
+impl Tombstone {
+    pub async fn load(thread_id: u64) -> Result<Option<Tombstone>, Box<dyn Error + Send + Sync>> {
+        read_datastorage_file(&tombstone_file(thread_id)).await
+    }
+}
+
+impl Conversation {
+    pub fn new(thread_id: u64, guild_id: u64, owner_id: u64, title: &str) -> Conversation {
+        Conversation {
+            thread_id,
+            guild_id,
+            owner_id,
+            title: title.to_owned(),
+            private: false,
+            auto_title: false,
+            created_at: now_millis(),
+            settings: ConversationSettings::default(),
+            context_start: None,
+            summary: None,
+            forked_from: None,
+            turns: vec![],
+        }
+    }
+
+    pub async fn load(thread_id: u64) -> Result<Option<Conversation>, Box<dyn Error + Send + Sync>> {
+        read_datastorage_file(&conversation_file(thread_id)).await
+    }
+
+    pub async fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        write_datastorage_file(&conversation_file(self.thread_id), self).await?;
+        remember_channel(self.thread_id, true);
+        Ok(())
+    }
+
+    pub async fn delete(thread_id: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        remove_datastorage_file(&conversation_file(thread_id)).await?;
+        remove_index(thread_id).await?;
+        write_datastorage_file(&tombstone_file(thread_id), &Tombstone { thread_id, deleted_at: now_millis() }).await?;
+        forget_channel(thread_id);
+        Ok(())
+    }
+
+    /// Ids of all stored threads
+    pub async fn list_ids() -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+        Ok(list_datastorage_dir(CONVERSATIONS_FOLDER)
+            .await?
+            .iter()
+            .filter_map(|name| name.parse().ok())
+            .collect())
+    }
+
+    /// Loads the conversation, applies `f` to it and saves it, all under one lock.
+    /// Returns `None` if the thread has no stored conversation.
+    pub async fn update<F, R>(thread_id: u64, f: F) -> Result<Option<R>, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut Conversation) -> R,
+    {
+        let result = update_datastorage_file(&conversation_file(thread_id), |conversation: &mut Option<Conversation>| {
+            conversation.as_mut().map(f)
+        }).await?;
+
+        if result.is_some() {
+            remember_channel(thread_id, true);
+        }
+
+        Ok(result)
+    }
+
+    /// Turns after the last /reset
+    pub fn context_turns(&self) -> &[Turn] {
+        let start = match self.context_start {
+            Some(start) => self.turns.iter().position(|t| t.timestamp >= start).unwrap_or(self.turns.len()),
+            None => 0,
+        };
+
+        &self.turns[start..]
+    }
+
+    /// Turns after the last /reset that are not covered by the summary yet
+    pub fn unsummarized_turns(&self) -> &[Turn] {
+        let turns = self.context_turns();
+
+        let start = match &self.summary {
+            Some(summary) => turns.partition_point(|t| t.timestamp <= summary.until),
+            None => 0,
+        };
+
+        &turns[start..]
+    }
+
+    pub fn find_turn_mut(&mut self, message_id: u64) -> Option<&mut Turn> {
+        self.turns.iter_mut().find(|t| t.message_id == message_id)
+    }
+
+    /// Adds a turn, keeping the turns ordered by time
+    pub fn push_turn(&mut self, turn: Turn) {
+        let index = self.turns.partition_point(|t| t.timestamp <= turn.timestamp);
+        self.turns.insert(index, turn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(timestamp: i64) -> Turn {
+        Turn {
+            message_id: timestamp as u64,
+            author_id: 1,
+            role: TurnRole::User,
+            content: timestamp.to_string(),
+            timestamp,
+            model: None,
+            attachments: vec![],
+            ratings: vec![],
+        }
+    }
+
+    fn conversation(timestamps: &[i64]) -> Conversation {
+        let mut conversation = Conversation::new(1, 2, 3, "Chat");
+        conversation.turns = timestamps.iter().map(|t| turn(*t)).collect();
+        conversation
+    }
+
+    fn timestamps(turns: &[Turn]) -> Vec<i64> {
+        turns.iter().map(|turn| turn.timestamp).collect()
+    }
+
+    #[test]
+    fn context_turns_without_reset_are_all_turns() {
+        let conversation = conversation(&[10, 20, 30]);
+
+        assert_eq!(timestamps(conversation.context_turns()), [10, 20, 30]);
+    }
+
+    #[test]
+    fn context_turns_start_at_the_reset() {
+        let mut conversation = conversation(&[10, 20, 30]);
+
+        conversation.context_start = Some(20);
+        assert_eq!(timestamps(conversation.context_turns()), [20, 30]);
+
+        conversation.context_start = Some(40);
+        assert!(conversation.context_turns().is_empty());
+    }
+
+    #[test]
+    fn unsummarized_turns_follow_the_summary() {
+        let mut conversation = conversation(&[10, 20, 30]);
+        assert_eq!(timestamps(conversation.unsummarized_turns()), [10, 20, 30]);
+
+        conversation.summary = Some(ConversationSummary { text: "Summary".to_owned(), until: 20 });
+        assert_eq!(timestamps(conversation.unsummarized_turns()), [30]);
+
+        conversation.summary = Some(ConversationSummary { text: "Summary".to_owned(), until: 30 });
+        assert!(conversation.unsummarized_turns().is_empty());
+    }
+
+    #[test]
+    fn unsummarized_turns_start_at_the_reset() {
+        let mut conversation = conversation(&[10, 20, 30, 40]);
+        conversation.summary = Some(ConversationSummary { text: "Summary".to_owned(), until: 20 });
+
+        // A reset after the summary hides the summarized turns and the older ones.
+        conversation.context_start = Some(30);
+        assert_eq!(timestamps(conversation.unsummarized_turns()), [30, 40]);
+
+        // A summary after the reset covers a part of the context.
+        conversation.context_start = Some(10);
+        assert_eq!(timestamps(conversation.unsummarized_turns()), [30, 40]);
+    }
+
+    #[test]
+    fn push_turn_keeps_the_turns_ordered() {
+        let mut conversation = conversation(&[10, 30]);
+
+        conversation.push_turn(turn(20));
+        conversation.push_turn(turn(5));
+        conversation.push_turn(turn(40));
+
+        assert_eq!(timestamps(&conversation.turns), [5, 10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn snowflake_millis_reads_the_creation_time() {
+        assert_eq!(snowflake_millis(0), DISCORD_EPOCH_MS);
+        assert_eq!(snowflake_millis(1000 << 22), DISCORD_EPOCH_MS + 1000);
+    }
+}
@@ -1,18 +1,191 @@
-use std::{fs, error::Error};
+use std::{fs, error::Error, path::PathBuf, sync::OnceLock};
 
 use tokio::fs as tokio_fs;
 use tokio::fs::OpenOptions;
+use tokio::sync::Mutex;
 use tokio::io::AsyncWriteExt;
 
-use bson::{doc, oid::ObjectId, Bson};
-use serde::{Deserialize, Serialize};
+use bson::{doc, Bson};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::utils::config::config;
 
 static DATASTORAGE_FOLDER_NAME: &str = "data";
+static DATASTORAGE_FOLDER: OnceLock<PathBuf> = OnceLock::new();
+/// Folders holding one file per record
+static DATASTORAGE_SUBFOLDERS: &[&str] = &["conversations", "guilds", "knowledge", "search", "memory", "moderation", "usage", "audit", "tombstones"];
+/// Serializes the writes, so concurrent handlers do not overwrite each other's temporary files
+static WRITE_LOCK: Mutex<()> = Mutex::const_new(());
+/// Held for a whole read-modify-write cycle, so concurrent updates of a file are not lost
+static UPDATE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Overrides the folder the datastorage files live in. Has to be called before the first access.
+pub fn set_datastorage_folder(path: PathBuf) {
+    let _ = DATASTORAGE_FOLDER.set(path);
+}
+
+fn datastorage_folder() -> &'static PathBuf {
+    DATASTORAGE_FOLDER.get_or_init(|| PathBuf::from(DATASTORAGE_FOLDER_NAME))
+}
+
+pub(crate) fn datastorage_file(name: &str) -> PathBuf {
+    datastorage_folder().join(name)
+}
+
+/// Reads the datastorage file `name`, returns `None` if it does not exist
+pub(crate) async fn read_datastorage_file<T: DeserializeOwned>(name: &str) -> Result<Option<T>, Box<dyn Error + Send + Sync>> {
+    let bson_bytes = match tokio_fs::read(datastorage_file(name)).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let document = bson::from_slice(&bson_bytes)?;
+    Ok(Some(bson::from_bson(Bson::Document(document))?))
+}
+
+/// Removes the datastorage file `name`, waiting for a running update of it
+pub(crate) async fn remove_datastorage_file(name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let _update_guard = UPDATE_LOCK.lock().await;
+    let _guard = WRITE_LOCK.lock().await;
+
+    match tokio_fs::remove_file(datastorage_file(name)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Names of the `.bson` files in the datastorage subfolder `dir`, without the extension
+pub(crate) async fn list_datastorage_dir(dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut names = vec![];
+
+    let mut entries = match tokio_fs::read_dir(datastorage_file(dir)).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "bson") {
+            if let Some(stem) = path.file_stem().and_then(|v| v.to_str()) {
+                names.push(stem.to_owned());
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Serializes `value` into the datastorage file `name`.
+///
+/// The data is written to a temporary file first and then renamed over the old one,
+/// so an interrupted write never leaves a truncated file behind.
+pub(crate) async fn write_datastorage_file<T: Serialize>(name: &str, value: &T) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let document = bson::to_document(value)?;
+    let bson_bytes = bson::to_vec(&document)?;
+
+    let _guard = WRITE_LOCK.lock().await;
+
+    let tmp_path = datastorage_file(&format!("{}.tmp", name));
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .await?;
+
+    let mut file = tokio::io::BufWriter::new(file);
+
+    file.write_all(&bson_bytes).await?;
+    file.flush().await?;
+    file.get_ref().sync_all().await?;
+
+    tokio_fs::rename(&tmp_path, datastorage_file(name)).await?;
+
+    Ok(())
+}
+
+/// Loads the datastorage file `name` (`None` if it does not exist), applies `f` to it and writes it back
+/// if it is `Some` afterwards, all under one lock
+pub(crate) async fn update_datastorage_file<T, F, R>(name: &str, f: F) -> Result<R, Box<dyn Error + Send + Sync>>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(&mut Option<T>) -> R,
+{
+    let _guard = UPDATE_LOCK.lock().await;
+
+    let mut value = read_datastorage_file(name).await?;
+    let result = f(&mut value);
+
+    if let Some(value) = &value {
+        write_datastorage_file(name, value).await?;
+    }
+
+    Ok(result)
+}
+
+/// Record type of a `CappedLog`
+pub trait LogRecord: Serialize + DeserializeOwned {
+    /// Datastorage subfolder with one log per guild
+    const FOLDER: &'static str;
+    /// Records kept per guild, the oldest ones are dropped
+    const MAX_RECORDS: usize;
+}
+
+/// Newest records of something that happened in a guild, like the moderation and audit logs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CappedLog<T> {
+    pub guild_id: u64,
+    pub records: Vec<T>,
+}
+
+impl<T: LogRecord> CappedLog<T> {
+    fn file(guild_id: u64) -> String {
+        format!("{}/{}.bson", T::FOLDER, guild_id)
+    }
+
+    /// Stored log of the guild, an empty one if it has none
+    pub async fn load(guild_id: u64) -> Result<CappedLog<T>, Box<dyn Error + Send + Sync>> {
+        Ok(read_datastorage_file(&Self::file(guild_id)).await?.unwrap_or(CappedLog { guild_id, records: vec![] }))
+    }
+
+    pub async fn append(guild_id: u64, record: T) -> Result<(), Box<dyn Error + Send + Sync>> {
+        update_datastorage_file(&Self::file(guild_id), |log: &mut Option<CappedLog<T>>| {
+            let log = log.get_or_insert_with(|| CappedLog { guild_id, records: vec![] });
+            log.records.push(record);
+
+            let excess = log.records.len().saturating_sub(T::MAX_RECORDS);
+            log.records.drain(..excess);
+        }).await
+    }
+
+    /// Applies `f` to the records of a stored log
+    pub async fn update_records<F>(guild_id: u64, f: F) -> Result<(), Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut Vec<T>),
+    {
+        update_datastorage_file(&Self::file(guild_id), |log: &mut Option<CappedLog<T>>| {
+            if let Some(log) = log {
+                f(&mut log.records);
+            }
+        }).await
+    }
+}
+
+/// Waits until all pending datastorage writes are finished
+pub async fn flush_datastorage() {
+    let _guard = WRITE_LOCK.lock().await;
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub user_id: u64,
     pub model: String,
+    /// Set with /admin user ban, banned users can't use the bot
+    #[serde(default)]
+    pub banned: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,34 +195,20 @@ pub struct Users {
 
 impl Users {
     pub async fn default() -> Result<Users, Box<dyn Error>> {
-        let bson_bytes = tokio_fs::read(
-            &format!("{}/users.bson", DATASTORAGE_FOLDER_NAME)
-        ).await?;
+        let bson_bytes = tokio_fs::read(datastorage_file("users.bson")).await?;
         let document = bson::from_slice(&bson_bytes)?;
         let users: Users = bson::from_bson(Bson::Document(document))?;
         Ok(users)
     }
 
-    pub async fn write_users_datastorage(&self) -> Result<(), Box<dyn Error>> {
-        let document = bson::to_document(&self)?;
-        let bson_bytes = bson::to_vec(&document)?;
-        
-        // tokio_fs::write(
-        //     &format!("{}/users.bson", DATASTORAGE_FOLDER_NAME), bson_bytes
-        // ).await?;
-
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&format!("{}/users.bson", DATASTORAGE_FOLDER_NAME))
-            .await?;
-        
-        let mut file = tokio::io::BufWriter::new(file);
-
-        file.write_all(&bson_bytes).await?;
-        file.flush().await.unwrap();
-        
-        Ok(())
+    /// Loads the users, applies `f` to them and saves them, all under one lock
+    pub async fn update<F, R>(f: F) -> Result<R, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut Users) -> R,
+    {
+        update_datastorage_file("users.bson", |users: &mut Option<Users>| {
+            f(users.get_or_insert_with(|| Users { users: vec![] }))
+        }).await
     }
 
     pub fn find_user_by_id(&self, user_id: u64) -> Option<&User> {
@@ -69,6 +228,19 @@ impl Users {
         }
     }
 
+    pub fn set_model(&mut self, user_id: u64, model: String) {
+        if !self.update_user(user_id, model.to_owned()) {
+            self.users.push(User { user_id, model, banned: false });
+        }
+    }
+
+    pub fn set_banned(&mut self, user_id: u64, banned: bool) {
+        match self.users.iter_mut().find(|user| user.user_id == user_id) {
+            Some(user) => user.banned = banned,
+            None => self.users.push(User { user_id, model: config().models.default.to_owned(), banned }),
+        }
+    }
+
     pub fn delete_user(&mut self, user_id: u64) -> bool {
         if let Some(index) = self.users.iter().position(|user| user.user_id == user_id) {
             self.users.remove(index);
@@ -79,22 +251,36 @@ impl Users {
     }
 }
 
+/// Model picked by the user with /model, the configured default otherwise
+pub async fn model_of_user(user_id: u64) -> String {
+    match Users::default().await {
+        Ok(users) => users.find_user_by_id(user_id).map(|user| user.model.to_owned()),
+        Err(_) => None
+    }.unwrap_or_else(|| config().models.default.to_owned())
+}
+
 pub async fn check_datastorage_exists() {
-    if let Err(err) = fs::metadata(DATASTORAGE_FOLDER_NAME) {
+    if let Err(err) = fs::metadata(datastorage_folder()) {
         if err.kind() == std::io::ErrorKind::NotFound {
-            match fs::create_dir(DATASTORAGE_FOLDER_NAME) {
+            match fs::create_dir_all(datastorage_folder()) {
                 Ok(()) => {},
                 Err(e) => panic!("Failed to create a folder to store data: {}", e),
             }
         }
     }
 
+    for folder in DATASTORAGE_SUBFOLDERS {
+        if let Err(e) = fs::create_dir_all(datastorage_file(folder)) {
+            panic!("Failed to create a folder to store data: {}", e);
+        }
+    }
+
     check_data_users_file()
         .await.expect("Field check users.bson in datastorage");
 }
 
 async fn check_data_users_file() -> Result<(), Box<dyn Error>> {
-    let file_path = &format!("{}/users.bson", DATASTORAGE_FOLDER_NAME);
+    let file_path = &datastorage_file("users.bson");
 
     if tokio_fs::metadata(file_path).await.is_err() {
         let document = doc! {
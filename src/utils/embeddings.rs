@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use reqwest::header::AUTHORIZATION;
+use serde_json::{json, Value};
+
+use crate::utils::config::config;
+
+/// Dimensions of the vectors made by the hashing embedder
+static HASHING_DIMENSIONS: usize = 512;
+/// Texts sent to the embeddings endpoint in one request
+static PROVIDER_BATCH: usize = 64;
+
+/// Turns texts into vectors. Vectors of different embedders can not be compared,
+/// so every stored vector keeps the `id` of the embedder that made it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Embedder {
+    /// The embeddings endpoint of the provider with the given model
+    Provider(String),
+    /// Feature hashing of words and word pairs, works without any network access
+    Hashing,
+}
+
+impl Embedder {
+    /// The embedder selected in the configuration
+    pub fn from_config() -> Embedder {
+        let config = config();
+
+        match config.knowledge.embedder.as_str() {
+            "hashing" => Embedder::Hashing,
+            _ => Embedder::Provider(config.knowledge.embeddings_model.to_owned()),
+        }
+    }
+
+    pub fn from_id(id: &str) -> Embedder {
+        match id.strip_prefix("provider:") {
+            Some(model) => Embedder::Provider(model.to_owned()),
+            None => Embedder::Hashing,
+        }
+    }
+
+    pub fn id(&self) -> String {
+        match self {
+            Embedder::Provider(model) => format!("provider:{}", model),
+            Embedder::Hashing => format!("hashing:{}", HASHING_DIMENSIONS),
+        }
+    }
+
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        match self {
+            Embedder::Provider(model) => embed_with_provider(model, texts).await,
+            Embedder::Hashing => Ok(texts.iter().map(|text| hashing_embedding(text)).collect()),
+        }
+    }
+}
+
+async fn embed_with_provider(model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+    let config = config();
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.limits.request_timeout_secs))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut vectors = Vec::with_capacity(texts.len());
+
+    for batch in texts.chunks(PROVIDER_BATCH) {
+        let response: Value = client
+            .post(&config.knowledge.embeddings_url)
+            .header(AUTHORIZATION, format!("Bearer {}", config.providers.api_key))
+            .json(&json!({ "model": model, "input": batch }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(error) = response.get("error") {
+            return Err(error["message"].as_str().unwrap_or("unknown error").to_string());
+        }
+
+        let data = response["data"].as_array().ok_or("the response has no data")?;
+
+        if data.len() != batch.len() {
+            return Err(format!("expected {} embeddings, got {}", batch.len(), data.len()));
+        }
+
+        for item in data {
+            let vector = item["embedding"]
+                .as_array()
+                .ok_or("the response has no embedding")?
+                .iter()
+                .map(|v| v.as_f64().unwrap_or(0.0) as f32)
+                .collect();
+
+            vectors.push(vector);
+        }
+    }
+
+    Ok(vectors)
+}
+
+/// Stores vectors as little-endian binary, an array of doubles would take three times the space
+pub(crate) mod vector_bytes {
+    use bson::{spec::BinarySubtype, Binary};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(vector: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+        Binary { subtype: BinarySubtype::Generic, bytes }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+        let binary = Binary::deserialize(deserializer)?;
+        Ok(binary.bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike the hasher of the standard library
+pub(crate) fn fnv1a(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+fn hashing_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0f32; HASHING_DIMENSIONS];
+
+    let lowercase = text.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let mut add = |feature: &str| {
+        let hash = fnv1a(feature);
+        let index = (hash % HASHING_DIMENSIONS as u64) as usize;
+        // The sign bit halves the collisions' bias.
+        vector[index] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+    };
+
+    for word in &words {
+        add(word);
+    }
+    for pair in words.windows(2) {
+        add(&format!("{} {}", pair[0], pair[1]));
+    }
+
+    normalize(&mut vector);
+    vector
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 { 0.0 } else { dot / (norm_a * norm_b) }
+}
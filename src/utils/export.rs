@@ -0,0 +1,345 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+use tokio::fs as tokio_fs;
+
+use crate::utils::conversations::{Conversation, Turn, TurnRole};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(format!("unknown export format `{}` (expected markdown, json or html)", s)),
+        }
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    match Utc.timestamp_millis_opt(timestamp).single() {
+        Some(v) => v.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+fn role_name(role: TurnRole) -> &'static str {
+    match role {
+        TurnRole::System => "System",
+        TurnRole::User => "User",
+        TurnRole::Assistant => "Assistant",
+    }
+}
+
+/// Heading of a turn: role, author or model, and time
+fn turn_heading(turn: &Turn) -> String {
+    let author = match (turn.role, &turn.model) {
+        (TurnRole::Assistant, Some(model)) => model.to_owned(),
+        (TurnRole::User, _) => format!("<@{}>", turn.author_id),
+        _ => String::new(),
+    };
+
+    if author.is_empty() {
+        format!("{} · {}", role_name(turn.role), format_time(turn.timestamp))
+    } else {
+        format!("{} ({}) · {}", role_name(turn.role), author, format_time(turn.timestamp))
+    }
+}
+
+fn is_image(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    [".png", ".jpg", ".jpeg", ".gif", ".webp"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// File name of an attachment URL, without the query string
+fn attachment_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Whether the /reset boundary lies right before `index`
+fn is_context_start(conversation: &Conversation, index: usize) -> bool {
+    match conversation.context_start {
+        Some(start) => {
+            conversation.turns[index].timestamp >= start
+                && (index == 0 || conversation.turns[index - 1].timestamp < start)
+        },
+        None => false,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Only web links are rendered as links, anything else (like `javascript:` from an imported transcript) could run in the browser
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim_start().to_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+fn render_markdown(conversation: &Conversation) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "# {}\n", conversation.title).unwrap();
+    writeln!(out, "- Thread: {}", conversation.thread_id).unwrap();
+    writeln!(out, "- Created: {}", format_time(conversation.created_at)).unwrap();
+    if let Some(source) = conversation.forked_from {
+        writeln!(out, "- Forked from: {}", source).unwrap();
+    }
+    if let Some(model) = &conversation.settings.model {
+        writeln!(out, "- Model: {}", model).unwrap();
+    }
+    if let Some(summary) = &conversation.summary {
+        writeln!(out, "\n> **Summary:** {}", summary.text.replace('\n', "\n> ")).unwrap();
+    }
+
+    for (index, turn) in conversation.turns.iter().enumerate() {
+        if is_context_start(conversation, index) {
+            writeln!(out, "\n---\n\n*Context reset*").unwrap();
+        }
+
+        writeln!(out, "\n### {}\n", turn_heading(turn)).unwrap();
+        writeln!(out, "{}", turn.content).unwrap();
+
+        for url in &turn.attachments {
+            if !is_web_url(url) {
+                writeln!(out, "\n`{}`", url.replace('`', "'")).unwrap();
+            } else if is_image(url) {
+                writeln!(out, "\n![{}]({})", attachment_name(url), url).unwrap();
+            } else {
+                writeln!(out, "\n[{}]({})", attachment_name(url), url).unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+fn render_html(conversation: &Conversation) -> String {
+    let mut out = String::new();
+    let title = escape_html(&conversation.title);
+
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", title).unwrap();
+    out.push_str(
+        "<style>\n\
+        body { font-family: sans-serif; max-width: 50em; margin: 2em auto; padding: 0 1em; color: #222; }\n\
+        .meta { color: #666; }\n\
+        .turn { border-radius: 8px; padding: 0.5em 1em; margin: 1em 0; }\n\
+        .user { background: #eef3ff; }\n\
+        .assistant { background: #f4f4f4; }\n\
+        .system { background: #fff6e0; }\n\
+        .heading { font-size: 0.85em; color: #555; }\n\
+        .content { white-space: pre-wrap; }\n\
+        .reset { text-align: center; color: #999; }\n\
+        img { max-width: 100%; }\n\
+        </style>\n</head>\n<body>\n"
+    );
+
+    writeln!(out, "<h1>{}</h1>", title).unwrap();
+    writeln!(out, "<p class=\"meta\">Thread {} · created {}</p>", conversation.thread_id, format_time(conversation.created_at)).unwrap();
+    if let Some(summary) = &conversation.summary {
+        writeln!(out, "<blockquote><b>Summary:</b> {}</blockquote>", escape_html(&summary.text)).unwrap();
+    }
+
+    for (index, turn) in conversation.turns.iter().enumerate() {
+        if is_context_start(conversation, index) {
+            writeln!(out, "<hr><p class=\"reset\">Context reset</p>").unwrap();
+        }
+
+        writeln!(out, "<div class=\"turn {}\">", role_name(turn.role).to_lowercase()).unwrap();
+        writeln!(out, "<div class=\"heading\">{}</div>", escape_html(&turn_heading(turn))).unwrap();
+        writeln!(out, "<div class=\"content\">{}</div>", escape_html(&turn.content)).unwrap();
+
+        for url in &turn.attachments {
+            let url_escaped = escape_html(url);
+            if !is_web_url(url) {
+                writeln!(out, "<p>{}</p>", url_escaped).unwrap();
+            } else if is_image(url) {
+                writeln!(out, "<p><a href=\"{0}\"><img src=\"{0}\" alt=\"{1}\"></a></p>", url_escaped, escape_html(attachment_name(url))).unwrap();
+            } else {
+                writeln!(out, "<p><a href=\"{}\">{}</a></p>", url_escaped, escape_html(attachment_name(url))).unwrap();
+            }
+        }
+
+        out.push_str("</div>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Renders the whole conversation, including the turns before a /reset
+pub fn render_conversation(conversation: &Conversation, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Markdown => render_markdown(conversation),
+        ExportFormat::Json => serde_json::to_string_pretty(conversation).unwrap(),
+        ExportFormat::Html => render_html(conversation),
+    }
+}
+
+/// File name of the exported conversation
+pub fn export_file_name(conversation: &Conversation, format: ExportFormat) -> String {
+    format!("conversation-{}.{}", conversation.thread_id, format.extension())
+}
+
+/// Writes every stored conversation into `dir`, returns the amount of exported files
+pub async fn export_all(dir: &Path, format: ExportFormat) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    tokio_fs::create_dir_all(dir).await?;
+
+    let mut count = 0;
+
+    for thread_id in Conversation::list_ids().await? {
+        let conversation = match Conversation::load(thread_id).await? {
+            Some(v) => v,
+            None => continue,
+        };
+
+        tokio_fs::write(
+            dir.join(export_file_name(&conversation, format)),
+            render_conversation(&conversation, format),
+        ).await?;
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Conversation {
+        let mut conversation = Conversation::new(10, 20, 30, "Rust <questions>");
+        conversation.created_at = 0;
+        conversation.turns = vec![
+            Turn {
+                message_id: 1,
+                author_id: 30,
+                role: TurnRole::User,
+                content: "What is <b>?".to_owned(),
+                timestamp: 1000,
+                model: None,
+                attachments: vec![
+                    "https://cdn.example.com/cat.png?size=1".to_owned(),
+                    "javascript:alert(1)".to_owned(),
+                ],
+                ratings: vec![],
+            },
+            Turn {
+                message_id: 2,
+                author_id: 1,
+                role: TurnRole::Assistant,
+                content: "A tag.".to_owned(),
+                timestamp: 2000,
+                model: Some("gpt-4".to_owned()),
+                attachments: vec!["https://cdn.example.com/notes.txt".to_owned()],
+                ratings: vec![],
+            },
+        ];
+        conversation
+    }
+
+    #[test]
+    fn escape_html_escapes_markup_and_quotes() {
+        assert_eq!(escape_html(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+        assert_eq!(escape_html("plain text"), "plain text");
+    }
+
+    #[test]
+    fn is_web_url_accepts_only_http_links() {
+        assert!(is_web_url("https://example.com"));
+        assert!(is_web_url(" HTTP://example.com"));
+        assert!(!is_web_url("javascript:alert(1)"));
+        assert!(!is_web_url("data:text/html,hi"));
+        assert!(!is_web_url("example.com"));
+    }
+
+    #[test]
+    fn markdown_links_web_attachments_only() {
+        let markdown = render_markdown(&conversation());
+
+        assert!(markdown.starts_with("# Rust <questions>\n"));
+        assert!(markdown.contains("### User (<@30>) · 1970-01-01 00:00:01 UTC"));
+        assert!(markdown.contains("### Assistant (gpt-4) · 1970-01-01 00:00:02 UTC"));
+        assert!(markdown.contains("![cat.png](https://cdn.example.com/cat.png?size=1)"));
+        assert!(markdown.contains("[notes.txt](https://cdn.example.com/notes.txt)"));
+        assert!(markdown.contains("\n`javascript:alert(1)`\n"));
+    }
+
+    #[test]
+    fn markdown_marks_the_context_reset() {
+        let mut conversation = conversation();
+        conversation.context_start = Some(1500);
+
+        let markdown = render_markdown(&conversation);
+
+        assert_eq!(markdown.matches("*Context reset*").count(), 1);
+        assert!(markdown.find("*Context reset*") > markdown.find("What is <b>?"));
+        assert!(markdown.find("*Context reset*") < markdown.find("A tag."));
+    }
+
+    #[test]
+    fn html_escapes_content_and_links_web_attachments_only() {
+        let html = render_html(&conversation());
+
+        assert!(html.contains("<title>Rust &lt;questions&gt;</title>"));
+        assert!(html.contains("What is &lt;b&gt;?"));
+        assert!(html.contains("<img src=\"https://cdn.example.com/cat.png?size=1\" alt=\"cat.png\">"));
+        assert!(html.contains("<a href=\"https://cdn.example.com/notes.txt\">notes.txt</a>"));
+        assert!(html.contains("<p>javascript:alert(1)</p>"));
+        assert!(!html.contains("href=\"javascript:"));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let json = render_conversation(&conversation(), ExportFormat::Json);
+        let parsed: Conversation = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.thread_id, 10);
+        assert_eq!(parsed.turns.len(), 2);
+    }
+
+    #[test]
+    fn formats_parse_from_their_names() {
+        assert_eq!("MD".parse(), Ok(ExportFormat::Markdown));
+        assert_eq!("html".parse(), Ok(ExportFormat::Html));
+        assert!("pdf".parse::<ExportFormat>().is_err());
+    }
+}
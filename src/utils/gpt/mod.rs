@@ -0,0 +1,284 @@
+pub mod tools;
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chatgpt::{err::Error, prelude::*, types::Role};
+use reqwest::header::AUTHORIZATION;
+use serde_json::{json, Value};
+
+use crate::utils::{config::config, conversations::{ConversationSummary, Turn, TurnRole}, log::log_to_file};
+
+use self::tools::{Tool, ToolContext};
+
+/// Answer of `suggest_memory_fact` when there is nothing to remember
+pub static NO_FACT: &str = "NONE";
+
+/// The default system message of the library, used for requests sent without it
+static SYSTEM_MESSAGE: &str = "You are ChatGPT, an AI model developed by OpenAI. Answer as concisely as possible.";
+/// Model replies that may call tools before the final answer has to be given
+static MAX_TOOL_ROUNDS: usize = 5;
+
+static CUSTOM_ENGINES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+
+/// Maps a model name to the engine, unknown names are interned once and sent as is
+fn engine_for(model: &str) -> ChatGPTEngine {
+    // Older datastorage files keep the model name with JSON quotes around it.
+    let model = model.trim_matches('"');
+
+    match model {
+        "gpt-3.5-turbo" => ChatGPTEngine::Gpt35Turbo,
+        "gpt-4" => ChatGPTEngine::Gpt4,
+        "gpt-4-32k" => ChatGPTEngine::Gpt4_32k,
+        _ => {
+            let mut engines = CUSTOM_ENGINES.lock().unwrap();
+            let engines = engines.get_or_insert_with(HashSet::new);
+
+            let name = match engines.get(model) {
+                Some(v) => *v,
+                None => {
+                    let name: &'static str = Box::leak(model.to_owned().into_boxed_str());
+                    engines.insert(name);
+                    name
+                }
+            };
+
+            ChatGPTEngine::Custom(name)
+        }
+    }
+}
+
+/// Text of the model answer together with the tokens spent on it
+pub struct GptReply {
+    pub text: String,
+    pub total_tokens: u32,
+}
+
+pub async fn send_gpt_message(model: &str, history: Vec<ChatMessage>) -> Result<GptReply> {
+    let config = config();
+
+    let client = ChatGPT::new_with_config(
+        &config.providers.api_key,
+        ModelConfigurationBuilder::default()
+            .api_url(Url::parse(&config.providers.api_base).unwrap())
+            .temperature(config.models.temperature)
+            .timeout(Duration::from_secs(config.limits.request_timeout_secs))
+            .engine(engine_for(model))
+            .build()
+            .unwrap(),
+    )?;
+
+    // Starts with the default system message of the library.
+    let mut conversation = client.new_conversation();
+    conversation.history.extend(history);
+
+    let response = client.send_history(&conversation.history).await?;
+
+    Ok(GptReply {
+        text: response.message().content.to_string(),
+        total_tokens: response.usage.total_tokens,
+    })
+}
+
+/// Like `send_gpt_message`, but lets the model call `tools`. The tool results are sent back
+/// until the model gives a final answer.
+pub async fn send_gpt_message_with_tools(
+    model: &str, history: Vec<ChatMessage>, tools: &[&dyn Tool], context: &ToolContext
+) -> Result<GptReply> {
+    // The library does not know about tools, so these requests are built by hand.
+    if tools.is_empty() {
+        return send_gpt_message(model, history).await;
+    }
+
+    let config = config();
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.limits.request_timeout_secs))
+        .build()?;
+
+    let mut messages = vec![json!({ "role": "system", "content": SYSTEM_MESSAGE })];
+    for message in &history {
+        messages.push(json!({ "role": message.role, "content": message.content }));
+    }
+
+    let definitions: Vec<Value> = tools.iter().map(|tool| tool.definition()).collect();
+    let mut total_tokens = 0;
+
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let response: Value = client
+            .post(&config.providers.api_base)
+            .header(AUTHORIZATION, format!("Bearer {}", config.providers.api_key))
+            .json(&json!({
+                "model": engine_for(model).to_string(),
+                "temperature": config.models.temperature,
+                "messages": messages,
+                "tools": definitions,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            return Err(Error::BackendError {
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+                error_type: error["type"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+
+        total_tokens += response.pointer("/usage/total_tokens").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+
+        let message = response
+            .pointer("/choices/0/message")
+            .cloned()
+            .ok_or_else(|| Error::ParsingError("the response has no message".to_string()))?;
+
+        let calls = message.get("tool_calls").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+
+        if calls.is_empty() {
+            return Ok(GptReply {
+                text: message["content"].as_str().unwrap_or_default().to_string(),
+                total_tokens,
+            });
+        }
+
+        messages.push(message);
+
+        for call in calls {
+            let name = call.pointer("/function/name").and_then(|v| v.as_str()).unwrap_or_default();
+            let arguments = call
+                .pointer("/function/arguments")
+                .and_then(|v| v.as_str())
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or(Value::Null);
+
+            let result = match tools.iter().find(|tool| tool.name() == name) {
+                Some(tool) => tool.call(context, arguments.to_owned()).await,
+                None => Err(format!("unknown tool `{}`", name)),
+            };
+
+            log_to_file(
+                &format!("[INFO] - Tool call {}({}): {:?}", name, arguments, result), &context.messages
+            ).await.unwrap();
+
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": call["id"],
+                "content": result.unwrap_or_else(|e| format!("Error: {}", e)),
+            }));
+        }
+    }
+
+    Err(Error::ParsingError(format!("no answer after {} rounds of tool calls", MAX_TOOL_ROUNDS)))
+}
+
+/// Plain text transcript of the turns for the requests that work on a whole conversation
+fn transcript_of(turns: &[Turn]) -> String {
+    let mut transcript = String::new();
+
+    for turn in turns {
+        let author = match turn.role {
+            TurnRole::System => "System",
+            TurnRole::User => "User",
+            TurnRole::Assistant => "Assistant",
+        };
+        transcript.push_str(&format!("{}: {}\n", author, turn.content));
+    }
+
+    transcript
+}
+
+/// Asks the model to summarize `turns`, folding in the summary of the turns before them
+pub async fn summarize_turns(model: &str, previous: Option<&str>, turns: &[Turn]) -> Result<GptReply> {
+    let mut transcript = String::new();
+
+    if let Some(previous) = previous {
+        transcript.push_str(&format!("Summary of the earlier conversation: {}\n\n", previous));
+    }
+    transcript.push_str(&transcript_of(turns));
+
+    let history = vec![
+        ChatMessage {
+            role: Role::System,
+            content: "Summarize the following conversation. Keep the facts, decisions, open questions \
+                and anything the user asked to remember. Answer in the language of the conversation.".to_string(),
+        },
+        ChatMessage { role: Role::User, content: transcript },
+    ];
+
+    send_gpt_message(model, history).await
+}
+
+/// Asks the model for a short title of the conversation started by `turns`
+pub async fn generate_title(model: &str, turns: &[Turn]) -> Result<GptReply> {
+    let history = vec![
+        ChatMessage {
+            role: Role::System,
+            content: "Come up with a short title (at most 6 words) for the following conversation. \
+                Answer with the title only, without quotes, in the language of the conversation.".to_string(),
+        },
+        ChatMessage { role: Role::User, content: transcript_of(turns) },
+    ];
+
+    let mut reply = send_gpt_message(model, history).await?;
+    reply.text = reply.text.trim().trim_matches(['"', '\'', '«', '»', '.']).trim().to_string();
+
+    Ok(reply)
+}
+
+/// Asks the model whether `message` tells something about the user worth remembering.
+/// The text of the reply is the fact, or `NO_FACT` if there is none.
+pub async fn suggest_memory_fact(model: &str, message: &str, known: &[String]) -> Result<GptReply> {
+    let mut content = format!(
+        "The user wrote a message to an assistant. If it states a lasting fact about the user \
+        (name, job, preferences, ongoing projects) that would help in future conversations, answer with \
+        that fact as one short sentence in the third person, in the language of the message. \
+        Otherwise answer with {} only.",
+        NO_FACT
+    );
+    if !known.is_empty() {
+        content.push_str(&format!(" These facts are already known, do not repeat them:\n{}", known.join("\n")));
+    }
+
+    let history = vec![
+        ChatMessage { role: Role::System, content },
+        ChatMessage { role: Role::User, content: message.to_string() },
+    ];
+
+    let mut reply = send_gpt_message(model, history).await?;
+    reply.text = reply.text.trim().trim_matches('"').trim().to_string();
+
+    Ok(reply)
+}
+
+/// Converts the stored turns (oldest first) into the chat history, keeping the newest `limit` turns.
+/// Turns covered by `summary` are replaced by a system message with the summary.
+pub fn get_gpt_history_from_messages(turns: &[Turn], summary: Option<&ConversationSummary>, limit: usize) -> Vec<ChatMessage> {
+    let mut history = vec![];
+
+    let turns = match summary {
+        Some(summary) => {
+            history.push(ChatMessage {
+                role: Role::System,
+                content: format!("Summary of the earlier conversation: {}", summary.text),
+            });
+
+            &turns[turns.partition_point(|t| t.timestamp <= summary.until)..]
+        },
+        None => turns,
+    };
+
+    for turn in turns.iter().skip(turns.len().saturating_sub(limit)) {
+        let role = match turn.role {
+            TurnRole::System => Role::System,
+            TurnRole::User => Role::User,
+            TurnRole::Assistant => Role::Assistant,
+        };
+
+        history.push(ChatMessage { role, content: turn.content.to_string() });
+    };
+
+    history
+}
+
@@ -0,0 +1,398 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use chrono::prelude::*;
+use serde_json::{json, Value};
+use serenity::async_trait;
+
+use crate::utils::{
+    config::config, conversations::Conversation, image::get_images, moderation::ModerationTarget,
+    permissions::UserPermissions, stats::Stats,
+};
+
+static TOOLS: OnceLock<ToolRegistry> = OnceLock::new();
+
+/// Amount of turns returned by the thread search
+static SEARCH_RESULTS: usize = 5;
+/// Longer turns are cut in the search results
+static SEARCH_EXCERPT_CHARS: usize = 300;
+
+/// What a tool knows about the request it was called for
+pub struct ToolContext {
+    pub guild_id: u64,
+    pub thread_id: u64,
+    pub user_id: u64,
+    /// Permissions of the user, tools doing requests of their own count them against the quota
+    pub permissions: UserPermissions,
+    pub messages: Arc<Mutex<Vec<String>>>,
+    pub stats: Arc<Stats>,
+    /// URLs of the files produced by tools, attached to the answer
+    pub attachments: Mutex<Vec<String>>,
+}
+
+impl ToolContext {
+    pub fn new(
+        guild_id: u64, thread_id: u64, user_id: u64, permissions: &UserPermissions,
+        messages: &Arc<Mutex<Vec<String>>>, stats: &Arc<Stats>
+    ) -> ToolContext {
+        ToolContext {
+            guild_id,
+            thread_id,
+            user_id,
+            permissions: permissions.to_owned(),
+            messages: Arc::clone(messages),
+            stats: Arc::clone(stats),
+            attachments: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn take_attachments(&self) -> Vec<String> {
+        std::mem::take(&mut *self.attachments.lock().unwrap())
+    }
+}
+
+/// A function the chat model may call while answering
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// JSON schema of the arguments object
+    fn parameters(&self) -> Value;
+
+    /// Whether the tool can be offered with the current configuration
+    fn available(&self) -> bool {
+        true
+    }
+
+    /// Runs the tool, the text (or the error) is sent back to the model
+    async fn call(&self, context: &ToolContext, arguments: Value) -> Result<String, String>;
+
+    /// Definition in the format of the chat completions `tools` field
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name(),
+                "description": self.description(),
+                "parameters": self.parameters(),
+            }
+        })
+    }
+}
+
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> ToolRegistry {
+        ToolRegistry {
+            tools: vec![
+                Box::new(CurrentTime),
+                Box::new(Calculator),
+                Box::new(GenerateImage),
+                Box::new(SearchThread),
+            ],
+        }
+    }
+}
+
+impl ToolRegistry {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.tools.iter().map(|t| t.name()).collect()
+    }
+
+    /// Available tools among `names`
+    pub fn enabled(&self, names: &[String]) -> Vec<&dyn Tool> {
+        self.tools
+            .iter()
+            .filter(|t| t.available() && names.iter().any(|name| name == t.name()))
+            .map(|t| t.as_ref())
+            .collect()
+    }
+}
+
+/// The built-in tools
+pub fn tools() -> &'static ToolRegistry {
+    TOOLS.get_or_init(ToolRegistry::default)
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, String> {
+    arguments
+        .get(name)
+        .and_then(|v| v.as_str())
+        .ok_or(format!("the `{}` argument is required", name))
+}
+
+pub struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &'static str {
+        "current_time"
+    }
+
+    fn description(&self) -> &'static str {
+        "Returns the current date and time"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "utc_offset_hours": {
+                    "type": "number",
+                    "description": "Offset of the wanted time zone from UTC in hours, 0 by default"
+                }
+            }
+        })
+    }
+
+    async fn call(&self, _context: &ToolContext, arguments: Value) -> Result<String, String> {
+        let offset_hours = arguments.get("utc_offset_hours").and_then(|v| v.as_f64()).unwrap_or(0.0);
+
+        let offset = FixedOffset::east_opt((offset_hours * 3600.0) as i32)
+            .ok_or(format!("{} is not a valid UTC offset", offset_hours))?;
+
+        Ok(Utc::now().with_timezone(&offset).format("%A, %Y-%m-%d %H:%M:%S %:z").to_string())
+    }
+}
+
+/// Writes the integer literals of `expression` as floats, so `7 / 2` gives 3.5 instead of the integer division
+fn float_literals(expression: &str) -> String {
+    let mut out = String::with_capacity(expression.len() + 8);
+    let mut chars = expression.chars().peekable();
+    let mut previous: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        out.push(c);
+
+        // Digits inside names like `log2` or after a decimal point are left alone.
+        let starts_number = c.is_ascii_digit()
+            && !previous.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '.' || p == ':');
+        previous = Some(c);
+
+        if !starts_number {
+            continue
+        }
+
+        while let Some(&next) = chars.peek() {
+            if !next.is_ascii_digit() {
+                break
+            }
+            out.push(next);
+            previous = Some(next);
+            chars.next();
+        }
+
+        if !chars.peek().is_some_and(|next| *next == '.' || *next == 'e' || *next == 'E') {
+            out.push_str(".0");
+        }
+    }
+
+    out
+}
+
+pub struct Calculator;
+
+#[async_trait]
+impl Tool for Calculator {
+    fn name(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn description(&self) -> &'static str {
+        "Evaluates an arithmetic expression with floating point numbers, e.g. `(2 + 3) * 4 ^ 2`, `7 / 2` or `math::sqrt(2)`"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": { "type": "string", "description": "The expression to evaluate" }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    async fn call(&self, _context: &ToolContext, arguments: Value) -> Result<String, String> {
+        let expression = string_argument(&arguments, "expression")?;
+
+        evalexpr::eval(&float_literals(expression))
+            .map(|value| value.to_string())
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub struct GenerateImage;
+
+#[async_trait]
+impl Tool for GenerateImage {
+    fn name(&self) -> &'static str {
+        "generate_image"
+    }
+
+    fn description(&self) -> &'static str {
+        "Draws an image from a description, the image gets attached to your answer"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "prompt": { "type": "string", "description": "Detailed description of the image in English" }
+            },
+            "required": ["prompt"]
+        })
+    }
+
+    fn available(&self) -> bool {
+        config().images.enabled
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: Value) -> Result<String, String> {
+        let prompt = string_argument(&arguments, "prompt")?;
+
+        context.permissions.authorize_image_request(context.user_id).await?;
+        context.stats.record_image_request();
+
+        let target = ModerationTarget { guild_id: context.guild_id, user_id: context.user_id, channel_id: context.thread_id };
+        let images = get_images(prompt, &config().images.size, &1, Some(&target), &context.messages).await;
+
+        match images.first() {
+            Some(url) if url.starts_with("https://") => {
+                context.attachments.lock().unwrap().push(url.to_owned());
+                Ok("The image was generated and will be attached to your answer.".to_string())
+            },
+            Some(error) => {
+                context.stats.record_error();
+                Err(error.to_owned())
+            },
+            None => Err("no image was generated".to_string()),
+        }
+    }
+}
+
+pub struct SearchThread;
+
+#[async_trait]
+impl Tool for SearchThread {
+    fn name(&self) -> &'static str {
+        "search_thread"
+    }
+
+    fn description(&self) -> &'static str {
+        "Searches the whole conversation of this thread, including messages that are no longer in your context"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "description": "Words to look for" }
+            },
+            "required": ["query"]
+        })
+    }
+
+    async fn call(&self, context: &ToolContext, arguments: Value) -> Result<String, String> {
+        let query = string_argument(&arguments, "query")?.to_lowercase();
+        let words: Vec<&str> = query.split_whitespace().collect();
+
+        let conversation = match Conversation::load(context.thread_id).await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err("this channel has no stored conversation".to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut found: Vec<(usize, usize)> = conversation.turns
+            .iter()
+            .enumerate()
+            .map(|(i, turn)| {
+                let content = turn.content.to_lowercase();
+                (i, words.iter().filter(|word| content.contains(*word)).count())
+            })
+            .filter(|(_, score)| *score > 0)
+            .collect();
+
+        // The best matches first, the newest among equally good ones.
+        found.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+
+        if found.is_empty() {
+            return Ok("Nothing found.".to_string())
+        }
+
+        let results: Vec<String> = found
+            .iter()
+            .take(SEARCH_RESULTS)
+            .map(|(i, _)| {
+                let turn = &conversation.turns[*i];
+                let time = Utc.timestamp_millis_opt(turn.timestamp).single()
+                    .map_or(String::new(), |v| v.format("%Y-%m-%d %H:%M").to_string());
+                let excerpt: String = turn.content.chars().take(SEARCH_EXCERPT_CHARS).collect();
+
+                format!("[{}] {:?}: {}", time, turn.role, excerpt)
+            })
+            .collect();
+
+        Ok(results.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ToolContext {
+        let permissions = UserPermissions {
+            commands: None,
+            models: None,
+            images: true,
+            daily_requests: None,
+            banned: false,
+        };
+        ToolContext::new(1, 2, 3, &permissions, &Arc::new(Mutex::new(vec![])), &Arc::new(Stats::default()))
+    }
+
+    async fn calculate(expression: &str) -> Result<String, String> {
+        Calculator.call(&context(), json!({ "expression": expression })).await
+    }
+
+    #[test]
+    fn float_literals_rewrites_integers() {
+        assert_eq!(float_literals("7 / 2"), "7.0 / 2.0");
+        assert_eq!(float_literals("(10+3)*4^2"), "(10.0+3.0)*4.0^2.0");
+    }
+
+    #[test]
+    fn float_literals_keeps_floats_and_names() {
+        assert_eq!(float_literals("1.5 + 2e3"), "1.5 + 2e3");
+        assert_eq!(float_literals("math::log2(8)"), "math::log2(8.0)");
+        assert_eq!(float_literals("x_1 * 3"), "x_1 * 3.0");
+    }
+
+    #[tokio::test]
+    async fn calculator_divides_as_floats() {
+        assert_eq!(calculate("7 / 2").await.as_deref(), Ok("3.5"));
+        assert_eq!(calculate("(2 + 3) * 4 ^ 2").await.as_deref(), Ok("80"));
+        assert_eq!(calculate("math::sqrt(16)").await.as_deref(), Ok("4"));
+    }
+
+    #[tokio::test]
+    async fn calculator_reports_errors() {
+        assert!(calculate("2 +").await.is_err());
+        assert!(Calculator.call(&context(), json!({})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn current_time_uses_the_offset() {
+        let time = CurrentTime.call(&context(), json!({ "utc_offset_hours": 5.5 })).await.unwrap();
+        assert!(time.ends_with("+05:30"));
+
+        let time = CurrentTime.call(&context(), json!({})).await.unwrap();
+        assert!(time.ends_with("+00:00"));
+
+        assert!(CurrentTime.call(&context(), json!({ "utc_offset_hours": 30 })).await.is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    datastorage::{read_datastorage_file, update_datastorage_file}, gpt::tools::tools, moderation::ModerationPolicy,
+    permissions::{PermissionRule, UserQuota},
+};
+
+static GUILDS_FOLDER: &str = "guilds";
+
+/// Settings changed by the server admins with /settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuildSettings {
+    pub guild_id: u64,
+    /// Rename new chats after the first answer, if they were created without a title
+    #[serde(default = "default_true")]
+    pub auto_titles: bool,
+    /// Answer mentions of the bot and replies to it outside of the chat threads
+    #[serde(default)]
+    pub mentions: bool,
+    /// Channels where mentions are answered, every channel if empty
+    #[serde(default)]
+    pub mention_channels: Vec<u64>,
+    /// Names of the tools the chat model may call
+    #[serde(default = "default_tools")]
+    pub tools: Vec<String>,
+    /// Screening of prompts and answers, changed with /moderation
+    #[serde(default)]
+    pub moderation: ModerationPolicy,
+    /// Rules for roles and users set with /permissions, added to the ones of the configuration
+    #[serde(default)]
+    pub permissions: Vec<PermissionRule>,
+    /// Daily requests of users set with /admin user quota, they replace the ones of the rules
+    #[serde(default)]
+    pub quotas: Vec<UserQuota>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_tools() -> Vec<String> {
+    tools().names().iter().map(|name| name.to_string()).collect()
+}
+
+fn guild_file(guild_id: u64) -> String {
+    format!("{}/{}.bson", GUILDS_FOLDER, guild_id)
+}
+
+impl GuildSettings {
+    pub fn new(guild_id: u64) -> GuildSettings {
+        GuildSettings {
+            guild_id,
+            auto_titles: true,
+            mentions: false,
+            mention_channels: vec![],
+            tools: default_tools(),
+            moderation: ModerationPolicy::default(),
+            permissions: vec![],
+            quotas: vec![],
+        }
+    }
+
+    pub fn mentions_allowed_in(&self, channel_id: u64) -> bool {
+        self.mentions && (self.mention_channels.is_empty() || self.mention_channels.contains(&channel_id))
+    }
+
+    /// Stored settings of the guild, the defaults if it has none
+    pub async fn load(guild_id: u64) -> Result<GuildSettings, Box<dyn Error + Send + Sync>> {
+        Ok(read_datastorage_file(&guild_file(guild_id)).await?.unwrap_or_else(|| GuildSettings::new(guild_id)))
+    }
+
+    pub async fn update<F, R>(guild_id: u64, f: F) -> Result<R, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut GuildSettings) -> R,
+    {
+        update_datastorage_file(&guild_file(guild_id), |settings: &mut Option<GuildSettings>| {
+            f(settings.get_or_insert_with(|| GuildSettings::new(guild_id)))
+        }).await
+    }
+}
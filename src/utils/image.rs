@@ -1,5 +1,3 @@
-use std::env;
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
@@ -12,7 +10,10 @@ use rand::Rng;
 use crypto::digest::Digest;
 use crypto::md5::Md5;
 
-use crate::utils::log::log_to_file;
+use crate::utils::{
+    config::config, log::log_to_file,
+    moderation::{moderate, ContentKind, ModerationTarget, ModerationVerdict},
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct Message {
@@ -37,14 +38,7 @@ fn get_api_key(user_agent: &str) -> String {
                                   + &part1.to_string() 
                                   + "x"))))));
     
-    format!("tryit-{}-{}", part1.to_string(), part2)
-}
-
-fn convert_to_hashmap(value: &Value) -> Option<HashMap<String, String>> {
-    match value {
-        Value::Object(map) => Some(map.iter().map(|(k, v)| (k.clone(), v.to_string())).collect()),
-        _ => None,
-    }
+    format!("tryit-{}-{}", part1, part2)
 }
 
 pub async fn image_submission_check(message: &str, log_messages: &Arc<Mutex<Vec<String>>>) -> Result<(bool, String), (bool, String)> {
@@ -125,12 +119,20 @@ pub async fn image_submission_check(message: &str, log_messages: &Arc<Mutex<Vec<
     Ok((false, "".to_owned()))
 }
 
-pub async fn get_images(text: &str, size: &str, count: &i32, log_messages: &Arc<Mutex<Vec<String>>>) -> Vec<String> {
-    let api_base = env::var("API_BASE_IMAGE") 
-        .expect("API BASE must be not empty in enviroment!");
+/// Generates images for `text`. The prompt is checked by the moderation policy of `moderation`,
+/// `None` if the caller has checked it already.
+pub async fn get_images(
+    text: &str, size: &str, count: &u32, moderation: Option<&ModerationTarget>, log_messages: &Arc<Mutex<Vec<String>>>
+) -> Vec<String> {
+    let config = config();
 
-    let api_key = env::var("API_KEY")
-        .expect("API KEY must be not empty in enviroment!");
+    if let Some(target) = moderation {
+        if moderate(target, ContentKind::ImagePrompt, text, log_messages).await == ModerationVerdict::Blocked {
+            return vec![config.texts.moderation_blocked.to_owned()]
+        }
+    }
+    let api_base = &config.providers.api_base_image;
+    let api_key = &config.providers.api_key;
 
     let client = reqwest::Client::new();
 
@@ -156,7 +158,7 @@ pub async fn get_images(text: &str, size: &str, count: &i32, log_messages: &Arc<
     let empty_map = &serde_json::Map::new();
 
     if !res.as_object().unwrap_or(empty_map).contains_key("data") {
-        return vec!["Простите, но у меня не получилось ничего нарисовать. Попробуйте позже(".to_owned()]
+        return vec![config.texts.images_failed.to_owned()]
     }
 
     let data = &res["data"];
@@ -0,0 +1,276 @@
+use serde_json::Value;
+
+use crate::utils::conversations::{now_millis, Conversation, Turn, TurnRole};
+
+/// Largest transcript file accepted by /create_chat
+pub static MAX_TRANSCRIPT_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Turns read from an uploaded transcript
+pub struct Transcript {
+    pub title: Option<String>,
+    pub turns: Vec<Turn>,
+}
+
+/// Parses a conversation exported by /export (JSON) or the conversations.json of a ChatGPT data export.
+/// User turns are attributed to `user_id`, assistant turns to `bot_id`.
+pub fn parse_transcript(data: &[u8], user_id: u64, bot_id: u64) -> Result<Transcript, String> {
+    let value: Value = serde_json::from_slice(data)
+        .map_err(|e| format!("The file is not valid JSON: {}", e))?;
+
+    let mut transcript = if value.get("thread_id").is_some() && value.get("turns").is_some() {
+        let conversation: Conversation = serde_json::from_value(value)
+            .map_err(|e| format!("The exported conversation is damaged: {}", e))?;
+
+        Transcript { title: Some(conversation.title), turns: conversation.turns }
+    } else {
+        // conversations.json holds every conversation of the account, only the first one is imported.
+        let conversation = match &value {
+            Value::Array(conversations) => conversations.first().ok_or("The file contains no conversations.")?,
+            _ => &value,
+        };
+
+        parse_openai_conversation(conversation)?
+    };
+
+    if transcript.turns.is_empty() {
+        return Err("The file contains no messages.".to_string());
+    }
+
+    for turn in transcript.turns.iter_mut() {
+        // Imported turns were never posted to the new thread.
+        turn.message_id = 0;
+        turn.author_id = match turn.role {
+            TurnRole::User => user_id,
+            _ => bot_id,
+        };
+    }
+
+    fix_timestamps(&mut transcript.turns);
+
+    Ok(transcript)
+}
+
+/// Reads one conversation of the OpenAI export, following the branch that ends in `current_node`
+fn parse_openai_conversation(conversation: &Value) -> Result<Transcript, String> {
+    let mapping = conversation
+        .get("mapping")
+        .and_then(|v| v.as_object())
+        .ok_or("Unknown file format, expected an /export JSON file or conversations.json from ChatGPT.")?;
+
+    let mut node_id = match conversation.get("current_node").and_then(|v| v.as_str()) {
+        Some(v) => v.to_string(),
+        // Without a current node the newest leaf of the tree is used.
+        None => mapping
+            .iter()
+            .filter(|(_, node)| node.get("children").and_then(|v| v.as_array()).is_none_or(|v| v.is_empty()))
+            .max_by_key(|(_, node)| {
+                node.pointer("/message/create_time").and_then(|v| v.as_f64()).map_or(0, |v| (v * 1000.0) as i64)
+            })
+            .map(|(id, _)| id.to_string())
+            .ok_or("The conversation is empty.")?,
+    };
+
+    let mut turns = vec![];
+
+    // Walks from the leaf up to the root, the turns get reversed afterwards.
+    for _ in 0..mapping.len() {
+        let node = match mapping.get(&node_id) {
+            Some(v) => v,
+            None => break,
+        };
+
+        if let Some(turn) = node.get("message").and_then(parse_openai_message) {
+            turns.push(turn);
+        }
+
+        node_id = match node.get("parent").and_then(|v| v.as_str()) {
+            Some(v) => v.to_string(),
+            None => break,
+        };
+    }
+
+    turns.reverse();
+
+    Ok(Transcript {
+        title: conversation.get("title").and_then(|v| v.as_str()).map(|v| v.to_string()),
+        turns,
+    })
+}
+
+fn parse_openai_message(message: &Value) -> Option<Turn> {
+    let role = match message.pointer("/author/role").and_then(|v| v.as_str())? {
+        "user" => TurnRole::User,
+        "assistant" => TurnRole::Assistant,
+        // System prompts and tool calls of the web interface are not part of the dialogue.
+        _ => return None,
+    };
+
+    let content = message
+        .pointer("/content/parts")
+        .and_then(|v| v.as_array())?
+        .iter()
+        .filter_map(|part| part.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if content.trim().is_empty() {
+        return None;
+    }
+
+    let timestamp = message
+        .get("create_time")
+        .and_then(|v| v.as_f64())
+        .map(|seconds| (seconds * 1000.0) as i64)
+        .unwrap_or(0);
+
+    Some(Turn {
+        message_id: 0,
+        author_id: 0,
+        role,
+        content,
+        timestamp,
+        model: message.pointer("/metadata/model_slug").and_then(|v| v.as_str()).map(|v| v.to_string()),
+        attachments: vec![],
+        ratings: vec![],
+    })
+}
+
+/// Makes the timestamps strictly increasing and older than now, so the imported turns
+/// keep their order and come before everything said in the new thread
+fn fix_timestamps(turns: &mut [Turn]) {
+    let earliest = now_millis() - turns.len() as i64;
+    let mut previous = i64::MIN;
+
+    for (i, turn) in turns.iter_mut().enumerate() {
+        let latest = earliest + i as i64;
+
+        // Missing timestamps are read as 0.
+        if turn.timestamp == 0 {
+            turn.timestamp = latest;
+        }
+
+        turn.timestamp = turn.timestamp.clamp(previous.saturating_add(1), latest);
+        previous = turn.timestamp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn turn(role: TurnRole, timestamp: i64) -> Turn {
+        Turn {
+            message_id: 5,
+            author_id: 6,
+            role,
+            content: "Hi".to_owned(),
+            timestamp,
+            model: None,
+            attachments: vec![],
+            ratings: vec![],
+        }
+    }
+
+    fn contents(transcript: &Transcript) -> Vec<&str> {
+        transcript.turns.iter().map(|turn| turn.content.as_str()).collect()
+    }
+
+    fn openai_node(parent: Option<&str>, children: &[&str], role: &str, text: &str, time: f64) -> Value {
+        json!({
+            "parent": parent,
+            "children": children,
+            "message": {
+                "author": { "role": role },
+                "content": { "parts": [text] },
+                "create_time": time,
+            },
+        })
+    }
+
+    /// A question answered twice, the second answer is the current one
+    fn openai_export() -> Value {
+        json!([{
+            "title": "Branches",
+            "current_node": "answer-2",
+            "mapping": {
+                "root": openai_node(None, &["question"], "system", "You are helpful", 1.0),
+                "question": openai_node(Some("root"), &["answer-1", "answer-2"], "user", "Hello?", 2.0),
+                "answer-1": openai_node(Some("question"), &[], "assistant", "First answer", 3.0),
+                "answer-2": openai_node(Some("question"), &[], "assistant", "Second answer", 4.0),
+            },
+        }])
+    }
+
+    #[test]
+    fn exported_conversations_are_attributed_to_the_importer() {
+        let mut conversation = Conversation::new(1, 2, 3, "Exported");
+        conversation.turns = vec![turn(TurnRole::User, 1000), turn(TurnRole::Assistant, 2000)];
+        let data = serde_json::to_vec(&conversation).unwrap();
+
+        let transcript = parse_transcript(&data, 10, 20).unwrap();
+
+        assert_eq!(transcript.title.as_deref(), Some("Exported"));
+        assert_eq!(transcript.turns.iter().map(|turn| turn.author_id).collect::<Vec<_>>(), [10, 20]);
+        assert!(transcript.turns.iter().all(|turn| turn.message_id == 0));
+    }
+
+    #[test]
+    fn openai_exports_follow_the_current_branch() {
+        let data = serde_json::to_vec(&openai_export()).unwrap();
+
+        let transcript = parse_transcript(&data, 10, 20).unwrap();
+
+        assert_eq!(transcript.title.as_deref(), Some("Branches"));
+        assert_eq!(contents(&transcript), ["Hello?", "Second answer"]);
+        assert_eq!(transcript.turns[0].timestamp, 2000);
+        assert_eq!(transcript.turns[1].timestamp, 4000);
+    }
+
+    #[test]
+    fn openai_exports_without_current_node_use_the_newest_leaf() {
+        let mut export = openai_export();
+        export[0].as_object_mut().unwrap().remove("current_node");
+        export[0]["mapping"]["answer-1"]["message"]["create_time"] = json!(5.0);
+        let data = serde_json::to_vec(&export).unwrap();
+
+        let transcript = parse_transcript(&data, 10, 20).unwrap();
+
+        assert_eq!(contents(&transcript), ["Hello?", "First answer"]);
+    }
+
+    #[test]
+    fn unusable_files_are_rejected() {
+        assert!(parse_transcript(b"not json", 10, 20).is_err());
+        assert!(parse_transcript(b"[]", 10, 20).is_err());
+        assert!(parse_transcript(br#"{"hello": "world"}"#, 10, 20).is_err());
+        assert_eq!(
+            parse_transcript(br#"{"mapping": {}, "current_node": "x"}"#, 10, 20).err().as_deref(),
+            Some("The file contains no messages.")
+        );
+    }
+
+    #[test]
+    fn fix_timestamps_fills_missing_and_orders_turns() {
+        let mut turns = vec![turn(TurnRole::User, 5000), turn(TurnRole::Assistant, 0), turn(TurnRole::User, 3000)];
+
+        fix_timestamps(&mut turns);
+
+        assert_eq!(turns[0].timestamp, 5000);
+        assert!(turns[1].timestamp > 5000);
+        assert!(turns[2].timestamp > turns[1].timestamp);
+        assert!(turns[2].timestamp < now_millis());
+    }
+
+    #[test]
+    fn fix_timestamps_moves_future_turns_before_now() {
+        let future = now_millis() + 60_000;
+        let mut turns = vec![turn(TurnRole::User, future), turn(TurnRole::Assistant, future)];
+
+        fix_timestamps(&mut turns);
+
+        assert!(turns[0].timestamp < turns[1].timestamp);
+        assert!(turns[1].timestamp < now_millis());
+    }
+}
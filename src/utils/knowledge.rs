@@ -0,0 +1,244 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    config::config,
+    conversations::now_millis,
+    datastorage::{read_datastorage_file, update_datastorage_file},
+    embeddings::{cosine_similarity, vector_bytes, Embedder},
+};
+
+static KNOWLEDGE_FOLDER: &str = "knowledge";
+
+/// Chunks a single knowledge base may hold, keeps its file well below the BSON document limit
+static MAX_CHUNKS: usize = 2000;
+
+/// Documents added to a server with /knowledge add
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnowledgeBase {
+    pub guild_id: u64,
+    /// `Embedder::id` of the stored vectors, queries are embedded the same way
+    pub embedder: String,
+    pub documents: Vec<KnowledgeDocument>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnowledgeDocument {
+    /// File name of the upload, unique within the knowledge base
+    pub name: String,
+    pub added_by: u64,
+    /// Unix time in milliseconds
+    pub added_at: i64,
+    pub chunks: Vec<KnowledgeChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnowledgeChunk {
+    pub text: String,
+    #[serde(with = "vector_bytes")]
+    pub vector: Vec<f32>,
+}
+
+/// Chunk found for a query
+pub struct KnowledgeMatch {
+    pub document: String,
+    pub text: String,
+    pub score: f32,
+}
+
+fn knowledge_file(guild_id: u64) -> String {
+    format!("{}/{}.bson", KNOWLEDGE_FOLDER, guild_id)
+}
+
+/// Reads the text of an uploaded .txt, .md or .pdf file
+pub fn extract_text(file_name: &str, data: &[u8]) -> Result<String, String> {
+    let extension = file_name.rsplit_once('.').map_or(String::new(), |(_, ext)| ext.to_lowercase());
+
+    let text = match extension.as_str() {
+        "txt" | "md" | "markdown" => String::from_utf8(data.to_vec())
+            .map_err(|_| "The file is not valid UTF-8 text.".to_string())?,
+        "pdf" => {
+            let document = lopdf::Document::load_mem(data)
+                .map_err(|e| format!("Cannot read the PDF: {}", e))?;
+            let pages: Vec<u32> = document.get_pages().keys().copied().collect();
+
+            document.extract_text(&pages).map_err(|e| format!("Cannot extract text from the PDF: {}", e))?
+        },
+        _ => return Err("Only .txt, .md and .pdf files are supported.".to_string()),
+    };
+
+    Ok(text.replace("\r\n", "\n"))
+}
+
+/// Splits `text` into chunks of at most `chunk_chars` characters, neighbours share `overlap` characters.
+/// Chunks end at a line or word break when there is one in their second half.
+pub fn split_into_chunks(text: &str, chunk_chars: usize, overlap: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = (start + chunk_chars).min(chars.len());
+
+        if end < chars.len() {
+            let min_end = start + chunk_chars / 2;
+            let window = &chars[min_end..end];
+
+            if let Some(i) = window.iter().rposition(|c| *c == '\n').or_else(|| window.iter().rposition(|c| c.is_whitespace())) {
+                end = min_end + i + 1;
+            }
+        }
+
+        let chunk: String = chars[start..end].iter().collect();
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk.trim().to_string());
+        }
+
+        if end == chars.len() {
+            break;
+        }
+
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+
+    chunks
+}
+
+impl KnowledgeBase {
+    pub fn new(guild_id: u64) -> KnowledgeBase {
+        KnowledgeBase { guild_id, embedder: Embedder::from_config().id(), documents: vec![] }
+    }
+
+    /// Stored knowledge base of the guild, an empty one if it has none
+    pub async fn load(guild_id: u64) -> Result<KnowledgeBase, Box<dyn Error + Send + Sync>> {
+        Ok(read_datastorage_file(&knowledge_file(guild_id)).await?.unwrap_or_else(|| KnowledgeBase::new(guild_id)))
+    }
+
+    pub async fn update<F, R>(guild_id: u64, f: F) -> Result<R, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut KnowledgeBase) -> R,
+    {
+        update_datastorage_file(&knowledge_file(guild_id), |knowledge: &mut Option<KnowledgeBase>| {
+            f(knowledge.get_or_insert_with(|| KnowledgeBase::new(guild_id)))
+        }).await
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.documents.iter().map(|d| d.chunks.len()).sum()
+    }
+
+    /// Embedder of the stored vectors. An empty knowledge base switches to the configured one.
+    fn embedder(&self) -> Embedder {
+        if self.documents.is_empty() {
+            Embedder::from_config()
+        } else {
+            Embedder::from_id(&self.embedder)
+        }
+    }
+
+    /// Chunks and embeds `text` and stores it as the document `name`, replacing a document with the same name.
+    /// Returns the amount of chunks.
+    pub async fn add_document(
+        guild_id: u64, name: &str, added_by: u64, text: &str
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let knowledge_config = &config().knowledge;
+
+        let texts = split_into_chunks(text, knowledge_config.chunk_chars, knowledge_config.chunk_overlap);
+        if texts.is_empty() {
+            return Err("the file contains no text".into());
+        }
+
+        let embedder = KnowledgeBase::load(guild_id).await?.embedder();
+        let vectors = embedder.embed(&texts).await.map_err(|e| format!("embedding failed: {}", e))?;
+
+        let document = KnowledgeDocument {
+            name: name.to_owned(),
+            added_by,
+            added_at: now_millis(),
+            chunks: texts.into_iter().zip(vectors).map(|(text, vector)| KnowledgeChunk { text, vector }).collect(),
+        };
+        let count = document.chunks.len();
+
+        KnowledgeBase::update(guild_id, |knowledge| {
+            // Another upload may have emptied or filled the knowledge base in the meantime.
+            if knowledge.documents.iter().any(|d| d.name != name) && knowledge.embedder != embedder.id() {
+                return Err("the knowledge base changed its embedder, please try again".to_string());
+            }
+
+            let replaced: usize = knowledge.documents.iter().filter(|d| d.name == name).map(|d| d.chunks.len()).sum();
+
+            if knowledge.chunk_count() - replaced + count > MAX_CHUNKS {
+                return Err(format!("the knowledge base is limited to {} chunks", MAX_CHUNKS));
+            }
+
+            knowledge.documents.retain(|d| d.name != name);
+            knowledge.embedder = embedder.id();
+            knowledge.documents.push(document);
+            Ok(())
+        }).await??;
+
+        Ok(count)
+    }
+
+    /// Removes the document `name`, returns whether it existed
+    pub async fn remove_document(guild_id: u64, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        KnowledgeBase::update(guild_id, |knowledge| {
+            let before = knowledge.documents.len();
+            knowledge.documents.retain(|d| d.name != name);
+            knowledge.documents.len() != before
+        }).await
+    }
+
+    /// The `k` chunks closest to `query`, the best first
+    pub async fn retrieve(guild_id: u64, query: &str, k: usize) -> Result<Vec<KnowledgeMatch>, Box<dyn Error + Send + Sync>> {
+        let knowledge = KnowledgeBase::load(guild_id).await?;
+
+        if knowledge.documents.is_empty() || query.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let query_vector = knowledge.embedder()
+            .embed(&[query.to_owned()])
+            .await
+            .map_err(|e| format!("embedding failed: {}", e))?
+            .pop()
+            .unwrap_or_default();
+
+        let mut matches: Vec<KnowledgeMatch> = knowledge.documents
+            .iter()
+            .flat_map(|document| document.chunks.iter().map(move |chunk| (document, chunk)))
+            .map(|(document, chunk)| KnowledgeMatch {
+                document: document.name.to_owned(),
+                text: chunk.text.to_owned(),
+                score: cosine_similarity(&query_vector, &chunk.vector),
+            })
+            .filter(|m| m.score > 0.0)
+            .collect();
+
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches.truncate(k);
+
+        Ok(matches)
+    }
+
+    /// System prompt with the chunks relevant to `query`, `None` if nothing matched
+    pub async fn prompt_for(guild_id: u64, query: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let matches = KnowledgeBase::retrieve(guild_id, query, config().knowledge.top_k).await?;
+
+        if matches.is_empty() {
+            return Ok(None);
+        }
+
+        let excerpts: Vec<String> = matches
+            .iter()
+            .map(|m| format!("[{}]\n{}", m.document, m.text))
+            .collect();
+
+        Ok(Some(format!(
+            "Excerpts from the knowledge base of this server. Use them when they are relevant \
+            to the question and name the document you took the information from.\n\n{}",
+            excerpts.join("\n\n")
+        )))
+    }
+}
@@ -1,34 +1,102 @@
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 
 use chrono::prelude::*;
 
+use crate::utils::config::config;
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static ECHO_TO_STDOUT: AtomicBool = AtomicBool::new(false);
+/// Lines kept for the log list of the TUI, older ones are dropped
+static MAX_SHOWN_MESSAGES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+}
+
+impl LogLevel {
+    /// Reads the level from the `[LEVEL] - ` prefix used by all log messages
+    fn of_message(message: &str) -> LogLevel {
+        if message.starts_with("[ERROR]") {
+            LogLevel::Error
+        } else if message.starts_with("[WARN]") {
+            LogLevel::Warn
+        } else {
+            LogLevel::Info
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            _ => Err(format!("unknown log level `{}` (expected error, warn or info)", s)),
+        }
+    }
+}
+
+/// Messages less important than `level` are dropped
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Also print every log message to stdout instead of keeping it for the TUI (used when running without it)
+pub fn set_echo_to_stdout(enabled: bool) {
+    ECHO_TO_STDOUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Adds a line to the log list of the TUI only. Nothing is kept when there is no TUI.
+pub fn show_in_tui(message: String, messages: &Arc<Mutex<Vec<String>>>) {
+    if ECHO_TO_STDOUT.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut messages_guard = messages.lock().unwrap();
+    messages_guard.push(message);
+
+    let excess = messages_guard.len().saturating_sub(MAX_SHOWN_MESSAGES);
+    messages_guard.drain(..excess);
+}
+
 pub async fn log_to_file(message: &str, messages: &Arc<Mutex<Vec<String>>>) -> std::io::Result<()> {
+    if LogLevel::of_message(message) as u8 > LOG_LEVEL.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
     let current_time = Local::now();
     let formatted_time = current_time.format("%Y-%m-%dT%H:%M:%S").to_string();
 
     let formatted_message = format!("{} - {}", formatted_time, message);
 
-    {
-        let mut messages_guard = messages.lock().unwrap();
-        messages_guard.push(formatted_message.to_owned());
+    if ECHO_TO_STDOUT.load(Ordering::Relaxed) {
+        println!("{}", formatted_message);
+    } else {
+        show_in_tui(formatted_message.to_owned(), messages);
     }
 
-    let path = "bot.log";
+    let path = config().logging.path.to_owned();
 
     let file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(path)
         .await?;
-        
+
     let mut file = tokio::io::BufWriter::new(file);
 
     file.write_all(formatted_message.as_bytes()).await?;
     file.write_all(b"\n").await?;
     file.flush().await
 }
-
@@ -0,0 +1,116 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    conversations::now_millis,
+    datastorage::{read_datastorage_file, remove_datastorage_file, update_datastorage_file},
+};
+
+static MEMORY_FOLDER: &str = "memory";
+
+/// Facts a user may store, keeps the system message compact
+pub static MAX_FACTS: usize = 30;
+pub static MAX_FACT_CHARS: usize = 200;
+/// Unanswered suggestions kept per user, older ones expire
+static MAX_PENDING: usize = 5;
+
+/// Facts about a user added with /memory, sent to the model in all their conversations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserMemory {
+    pub user_id: u64,
+    pub facts: Vec<MemoryFact>,
+    /// Let the model suggest facts from the user's messages (set by /memory suggestions)
+    #[serde(default)]
+    pub suggestions: bool,
+    /// Suggested facts waiting for confirmation, oldest first
+    #[serde(default)]
+    pub pending: Vec<PendingFact>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemoryFact {
+    pub text: String,
+    /// Unix time in milliseconds
+    pub added_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingFact {
+    /// Message asking the user to confirm the fact
+    pub message_id: u64,
+    pub text: String,
+}
+
+fn memory_file(user_id: u64) -> String {
+    format!("{}/{}.bson", MEMORY_FOLDER, user_id)
+}
+
+impl UserMemory {
+    pub fn new(user_id: u64) -> UserMemory {
+        UserMemory { user_id, facts: vec![], suggestions: false, pending: vec![] }
+    }
+
+    /// Stored memory of the user, an empty one if they have none
+    pub async fn load(user_id: u64) -> Result<UserMemory, Box<dyn Error + Send + Sync>> {
+        Ok(read_datastorage_file(&memory_file(user_id)).await?.unwrap_or_else(|| UserMemory::new(user_id)))
+    }
+
+    pub async fn update<F, R>(user_id: u64, f: F) -> Result<R, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut UserMemory) -> R,
+    {
+        update_datastorage_file(&memory_file(user_id), |memory: &mut Option<UserMemory>| {
+            f(memory.get_or_insert_with(|| UserMemory::new(user_id)))
+        }).await
+    }
+
+    /// Removes everything stored about the user, including pending suggestions
+    pub async fn delete(user_id: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        remove_datastorage_file(&memory_file(user_id)).await
+    }
+
+    /// Adds a fact, the error is the reply to the user
+    pub fn add_fact(&mut self, text: &str) -> Result<(), String> {
+        let text = text.trim();
+
+        if text.is_empty() {
+            return Err("The fact is empty.".to_string());
+        }
+        if text.chars().count() > MAX_FACT_CHARS {
+            return Err(format!("A fact can be at most {} characters long.", MAX_FACT_CHARS));
+        }
+        if self.facts.iter().any(|fact| fact.text.eq_ignore_ascii_case(text)) {
+            return Err("I already remember that.".to_string());
+        }
+        if self.facts.len() >= MAX_FACTS {
+            return Err(format!("You can store at most {} facts, forget some first.", MAX_FACTS));
+        }
+
+        self.facts.push(MemoryFact { text: text.to_owned(), added_at: now_millis() });
+        Ok(())
+    }
+
+    pub fn add_pending(&mut self, message_id: u64, text: &str) {
+        self.pending.push(PendingFact { message_id, text: text.to_owned() });
+
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        self.pending.drain(..excess);
+    }
+
+    pub fn take_pending(&mut self, message_id: u64) -> Option<PendingFact> {
+        let index = self.pending.iter().position(|pending| pending.message_id == message_id)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// System message with the stored facts, `None` if there are none
+    pub fn prompt(&self) -> Option<String> {
+        if self.facts.is_empty() {
+            return None;
+        }
+
+        let facts: Vec<String> = self.facts.iter().map(|fact| format!("- {}", fact.text)).collect();
+
+        Some(format!("Facts the user asked you to remember about them:\n{}", facts.join("\n")))
+    }
+}
@@ -1,6 +1,20 @@
 pub mod gpt;
 pub mod log;
 pub mod image;
-pub mod env_load;
+pub mod config;
 pub mod datastorage;
-
+pub mod conversations;
+pub mod embeddings;
+pub mod knowledge;
+pub mod search;
+pub mod memory;
+pub mod moderation;
+pub mod permissions;
+pub mod usage;
+pub mod audit;
+pub mod privacy;
+pub mod guilds;
+pub mod export;
+pub mod import;
+pub mod stats;
+pub mod shutdown;
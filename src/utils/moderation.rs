@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use regex::Regex;
+use reqwest::header::AUTHORIZATION;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
+
+use crate::utils::{
+    config::config,
+    conversations::now_millis,
+    datastorage::{CappedLog, LogRecord},
+    guilds::GuildSettings,
+    log::log_to_file,
+};
+
+/// Longer texts are cut in the audit log and the mod channel
+static EXCERPT_CHARS: usize = 500;
+
+/// Compiled word lists and patterns per guild, direct messages use 0
+static COMPILED_RULES: Mutex<Option<HashMap<u64, Arc<LocalRules>>>> = Mutex::new(None);
+
+/// Client used to post to the mod channels, set on startup
+static HTTP: OnceLock<Arc<Http>> = OnceLock::new();
+
+pub fn set_moderation_http(http: Arc<Http>) {
+    let _ = HTTP.set(http);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationAction {
+    /// The content is not sent to the model or not posted
+    Block,
+    /// The user is warned, the request goes through
+    Warn,
+    /// Only the audit log and the mod channel learn about it
+    Log,
+}
+
+impl ModerationAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModerationAction::Block => "block",
+            ModerationAction::Warn => "warn",
+            ModerationAction::Log => "log",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ModerationAction> {
+        match name {
+            "block" => Some(ModerationAction::Block),
+            "warn" => Some(ModerationAction::Warn),
+            "log" => Some(ModerationAction::Log),
+            _ => None,
+        }
+    }
+}
+
+/// Per-guild moderation settings, changed with /moderation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationPolicy {
+    pub enabled: bool,
+    /// Ask the moderation endpoint of the provider in addition to the local rules
+    pub endpoint: bool,
+    /// Matched as whole words, case-insensitively
+    pub blocked_words: Vec<String>,
+    /// Regular expressions
+    pub blocked_patterns: Vec<String>,
+    pub action: ModerationAction,
+    /// Check the answers of the model too
+    pub check_answers: bool,
+    /// Channel notified about every flagged content
+    pub mod_channel: Option<u64>,
+}
+
+impl Default for ModerationPolicy {
+    fn default() -> Self {
+        ModerationPolicy {
+            enabled: false,
+            endpoint: false,
+            blocked_words: vec![],
+            blocked_patterns: vec![],
+            action: ModerationAction::Block,
+            check_answers: true,
+            mod_channel: None,
+        }
+    }
+}
+
+/// What was checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Prompt,
+    Answer,
+    ImagePrompt,
+}
+
+impl fmt::Display for ContentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentKind::Prompt => write!(f, "prompt"),
+            ContentKind::Answer => write!(f, "answer"),
+            ContentKind::ImagePrompt => write!(f, "image prompt"),
+        }
+    }
+}
+
+/// Where the checked content comes from
+#[derive(Debug, Clone, Copy)]
+pub struct ModerationTarget {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub channel_id: u64,
+}
+
+/// Action taken for the checked content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationVerdict {
+    Allowed,
+    Warned,
+    Blocked,
+}
+
+/// Flagged content of a guild
+pub type ModerationLog = CappedLog<ModerationRecord>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModerationRecord {
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub kind: ContentKind,
+    pub action: ModerationAction,
+    pub reason: String,
+    pub excerpt: String,
+}
+
+impl LogRecord for ModerationRecord {
+    const FOLDER: &'static str = "moderation";
+    const MAX_RECORDS: usize = 1000;
+}
+
+/// Matches `word` case-insensitively, not as a part of a longer word. A word boundary only
+/// exists next to a letter or digit, so words like `c++` get it on one side only.
+fn word_pattern(word: &str) -> String {
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+    format!(
+        "(?i){}{}{}",
+        if is_word_char(word.chars().next()) { r"\b" } else { "" },
+        regex::escape(word),
+        if is_word_char(word.chars().last()) { r"\b" } else { "" },
+    )
+}
+
+/// The word list and the patterns of a policy, compiled once
+pub struct LocalRules {
+    words: Vec<String>,
+    patterns: Vec<String>,
+    /// Each word or pattern with its regex
+    compiled_words: Vec<(String, Regex)>,
+    compiled_patterns: Vec<(String, Regex)>,
+}
+
+impl LocalRules {
+    pub fn new(policy: &ModerationPolicy) -> LocalRules {
+        // Patterns are validated when they are added, a broken or an empty one is skipped.
+        let compile = |sources: &[String], pattern: fn(&str) -> String| -> Vec<(String, Regex)> {
+            sources
+                .iter()
+                .filter(|source| !source.is_empty())
+                .filter_map(|source| Some((source.to_owned(), Regex::new(&pattern(source)).ok()?)))
+                .collect()
+        };
+
+        LocalRules {
+            words: policy.blocked_words.to_owned(),
+            patterns: policy.blocked_patterns.to_owned(),
+            compiled_words: compile(&policy.blocked_words, word_pattern),
+            compiled_patterns: compile(&policy.blocked_patterns, |pattern| pattern.to_owned()),
+        }
+    }
+
+    fn is_compiled_from(&self, policy: &ModerationPolicy) -> bool {
+        self.words == policy.blocked_words && self.patterns == policy.blocked_patterns
+    }
+
+    /// Rules of the guild policy, compiled again only after the word list or the patterns changed
+    fn of_guild(guild_id: u64, policy: &ModerationPolicy) -> Arc<LocalRules> {
+        let mut cache = COMPILED_RULES.lock().unwrap();
+        let cache = cache.get_or_insert_with(HashMap::new);
+
+        match cache.get(&guild_id) {
+            Some(rules) if rules.is_compiled_from(policy) => Arc::clone(rules),
+            _ => {
+                let rules = Arc::new(LocalRules::new(policy));
+                cache.insert(guild_id, Arc::clone(&rules));
+                rules
+            }
+        }
+    }
+
+    /// Checks `text` against the word list and the patterns, returns the reason of a match
+    pub fn check(&self, text: &str) -> Option<String> {
+        if let Some((word, _)) = self.compiled_words.iter().find(|(_, re)| re.is_match(text)) {
+            return Some(format!("blocked word `{}`", word));
+        }
+
+        if let Some((pattern, _)) = self.compiled_patterns.iter().find(|(_, re)| re.is_match(text)) {
+            return Some(format!("blocked pattern `{}`", pattern));
+        }
+
+        None
+    }
+}
+
+/// Asks the moderation endpoint of the provider, returns the flagged categories
+async fn check_endpoint(text: &str) -> Result<Option<String>, String> {
+    let config = config();
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.limits.request_timeout_secs))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let response: Value = client
+        .post(&config.moderation.endpoint_url)
+        .header(AUTHORIZATION, format!("Bearer {}", config.providers.api_key))
+        .json(&json!({ "input": text }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(error) = response.get("error") {
+        return Err(error["message"].as_str().unwrap_or("unknown error").to_string());
+    }
+
+    let result = response.pointer("/results/0").ok_or("the response has no results")?;
+
+    if !result["flagged"].as_bool().unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let categories: Vec<&str> = result["categories"]
+        .as_object()
+        .map(|categories| {
+            categories.iter().filter(|(_, flagged)| flagged.as_bool() == Some(true)).map(|(name, _)| name.as_str()).collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Some(format!("flagged by the moderation endpoint ({})", categories.join(", "))))
+}
+
+/// Posts the flagged content to the mod channel of the guild
+async fn notify_mod_channel(
+    channel_id: u64, target: &ModerationTarget, record: &ModerationRecord, messages: &Arc<Mutex<Vec<String>>>
+) {
+    let http = match HTTP.get() {
+        Some(v) => v,
+        None => return
+    };
+
+    let res = ChannelId(channel_id).send_message(http, |message| {
+        message.embed(|embed| {
+            embed
+                .title(format!("Moderation: {} ({})", record.kind, record.action.name()))
+                .field("User", format!("<@{}>", target.user_id), true)
+                .field("Channel", format!("<#{}>", target.channel_id), true)
+                .field("Reason", &record.reason, false)
+                .description(&record.excerpt)
+        })
+    }).await;
+
+    if let Err(e) = res {
+        log_to_file(&format!("[WARN] - Cannot post to the mod channel: {:#?}", e), messages)
+            .await.unwrap();
+    }
+}
+
+/// Runs `text` through the moderation policy of the guild (of the configuration for direct messages). Flagged content is recorded in the
+/// audit log and posted to the mod channel. Failures of the endpoint let the content through.
+pub async fn moderate(
+    target: &ModerationTarget, kind: ContentKind, text: &str, messages: &Arc<Mutex<Vec<String>>>
+) -> ModerationVerdict {
+    // Direct messages belong to no server, they use the policy of the configuration.
+    let policy = if target.guild_id == 0 {
+        config().moderation.direct_messages.to_owned()
+    } else {
+        match GuildSettings::load(target.guild_id).await {
+            Ok(settings) => settings.moderation,
+            Err(_) => return ModerationVerdict::Allowed
+        }
+    };
+
+    if !policy.enabled {
+        return ModerationVerdict::Allowed
+    }
+
+    if kind == ContentKind::Answer && !policy.check_answers {
+        return ModerationVerdict::Allowed
+    }
+
+    let mut reason = LocalRules::of_guild(target.guild_id, &policy).check(text);
+
+    if reason.is_none() && policy.endpoint {
+        match check_endpoint(text).await {
+            Ok(v) => reason = v,
+            Err(e) => log_to_file(&format!("[WARN] - Moderation endpoint failed: {}", e), messages)
+                .await.unwrap(),
+        }
+    }
+
+    let reason = match reason {
+        Some(v) => v,
+        None => return ModerationVerdict::Allowed
+    };
+
+    let record = ModerationRecord {
+        timestamp: now_millis(),
+        user_id: target.user_id,
+        channel_id: target.channel_id,
+        kind,
+        action: policy.action,
+        reason,
+        excerpt: text.chars().take(EXCERPT_CHARS).collect(),
+    };
+
+    log_to_file(
+        &format!("[INFO] - Moderation: {} of user {} in guild {}: {} ({})",
+            kind, target.user_id, target.guild_id, record.reason, policy.action.name()),
+        messages
+    ).await.unwrap();
+
+    if let Some(channel_id) = policy.mod_channel {
+        notify_mod_channel(channel_id, target, &record, messages).await;
+    }
+
+    if let Err(e) = ModerationLog::append(target.guild_id, record).await {
+        log_to_file(&format!("[ERROR] - Cannot save moderation record: {}", e), messages)
+            .await.unwrap();
+    }
+
+    match policy.action {
+        ModerationAction::Block => ModerationVerdict::Blocked,
+        ModerationAction::Warn => ModerationVerdict::Warned,
+        ModerationAction::Log => ModerationVerdict::Allowed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(words: &[&str], patterns: &[&str]) -> ModerationPolicy {
+        ModerationPolicy {
+            blocked_words: words.iter().map(|v| v.to_string()).collect(),
+            blocked_patterns: patterns.iter().map(|v| v.to_string()).collect(),
+            ..ModerationPolicy::default()
+        }
+    }
+
+    #[test]
+    fn words_match_whole_words_ignoring_case() {
+        let rules = LocalRules::new(&policy(&["spam"], &[]));
+
+        assert_eq!(rules.check("No SPAM please").as_deref(), Some("blocked word `spam`"));
+        assert_eq!(rules.check("spam!").as_deref(), Some("blocked word `spam`"));
+        assert_eq!(rules.check("spammer"), None);
+    }
+
+    #[test]
+    fn words_are_not_read_as_patterns() {
+        let rules = LocalRules::new(&policy(&["c++"], &[]));
+
+        assert!(rules.check("I write c++ code").is_some());
+        assert_eq!(rules.check("I write ccc code"), None);
+    }
+
+    #[test]
+    fn patterns_are_regular_expressions() {
+        let rules = LocalRules::new(&policy(&[], &[r"\d{4}-\d{4}"]));
+
+        assert_eq!(rules.check("card 1234-5678").as_deref(), Some(r"blocked pattern `\d{4}-\d{4}`"));
+        assert_eq!(rules.check("card 12-34"), None);
+    }
+
+    #[test]
+    fn broken_and_empty_rules_are_skipped() {
+        let rules = LocalRules::new(&policy(&[""], &["(", "", "bad"]));
+
+        assert_eq!(rules.check(""), None);
+        assert_eq!(rules.check("anything"), None);
+        assert!(rules.check("bad").is_some());
+    }
+
+    #[test]
+    fn empty_rules_allow_everything() {
+        let rules = LocalRules::new(&ModerationPolicy::default());
+
+        assert_eq!(rules.check(""), None);
+        assert_eq!(rules.check("text"), None);
+    }
+
+    #[test]
+    fn rules_are_compiled_again_when_the_policy_changes() {
+        let first = LocalRules::of_guild(u64::MAX, &policy(&["one"], &[]));
+        let same = LocalRules::of_guild(u64::MAX, &policy(&["one"], &[]));
+        assert!(Arc::ptr_eq(&first, &same));
+
+        let changed = LocalRules::of_guild(u64::MAX, &policy(&["two"], &[]));
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert!(changed.check("two").is_some());
+    }
+
+    #[test]
+    fn actions_parse_from_their_names() {
+        for action in [ModerationAction::Block, ModerationAction::Warn, ModerationAction::Log] {
+            assert_eq!(ModerationAction::from_name(action.name()), Some(action));
+        }
+        assert_eq!(ModerationAction::from_name("ignore"), None);
+    }
+}
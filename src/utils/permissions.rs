@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::utils::{config::config, datastorage::Users, guilds::GuildSettings, usage::DailyUsage};
+
+/// What a group of members may do. Members get everything allowed by the base rule and by any rule that
+/// applies to them. Fields not set on the base rule allow everything, fields not set on the other rules
+/// grant nothing beyond the base.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionRule {
+    /// Roles the rule applies to, the id of the server is its @everyone role
+    pub roles: Vec<u64>,
+    pub users: Vec<u64>,
+    /// Allowed slash commands
+    pub commands: Option<Vec<String>>,
+    /// Allowed models
+    pub models: Option<Vec<String>>,
+    /// Whether images may be generated
+    pub images: Option<bool>,
+    /// Requests to the chat model per user and day (UTC)
+    pub daily_requests: Option<u64>,
+}
+
+impl PermissionRule {
+    fn applies_to(&self, user_id: u64, roles: &[u64]) -> bool {
+        self.users.contains(&user_id) || self.roles.iter().any(|role| roles.contains(role))
+    }
+
+    /// Whether the rule is the one of the @everyone role of the guild
+    pub fn is_everyone(&self, guild_id: u64) -> bool {
+        self.users.is_empty() && self.roles == [guild_id]
+    }
+}
+
+/// Daily requests of a user set with /admin user quota, replacing the ones of the rules
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserQuota {
+    pub user_id: u64,
+    /// Unlimited if not set
+    pub daily_requests: Option<u64>,
+}
+
+/// Everything a member is allowed, merged from the rules that apply to them
+#[derive(Debug, Clone)]
+pub struct UserPermissions {
+    pub commands: Option<HashSet<String>>,
+    pub models: Option<HashSet<String>>,
+    pub images: bool,
+    pub daily_requests: Option<u64>,
+    /// Banned with /admin user ban, nothing is allowed
+    pub banned: bool,
+}
+
+fn union(a: Option<HashSet<String>>, b: &Option<Vec<String>>) -> Option<HashSet<String>> {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b.iter().cloned());
+            Some(a)
+        },
+        (a, _) => a,
+    }
+}
+
+impl UserPermissions {
+    fn from_rule(rule: &PermissionRule) -> UserPermissions {
+        UserPermissions {
+            commands: rule.commands.as_ref().map(|v| v.iter().cloned().collect()),
+            models: rule.models.as_ref().map(|v| v.iter().cloned().collect()),
+            images: rule.images.unwrap_or(true),
+            daily_requests: rule.daily_requests,
+            banned: false,
+        }
+    }
+
+    fn merge(self, rule: &PermissionRule) -> UserPermissions {
+        UserPermissions {
+            commands: union(self.commands, &rule.commands),
+            models: union(self.models, &rule.models),
+            images: self.images || rule.images == Some(true),
+            daily_requests: match (self.daily_requests, rule.daily_requests) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, _) => a,
+            },
+            banned: self.banned,
+        }
+    }
+
+    /// Permissions of a member with `roles` in the guild (0 for direct messages).
+    ///
+    /// The base is the @everyone rule set with /permissions, or the default of the configuration.
+    /// The rules of the configuration and of the guild that apply to the member are added to it,
+    /// then the quota of the member in the guild replaces their daily requests.
+    pub async fn load(guild_id: u64, user_id: u64, roles: &[u64]) -> UserPermissions {
+        let banned = match Users::default().await {
+            Ok(users) => users.find_user_by_id(user_id).is_some_and(|user| user.banned),
+            Err(_) => false
+        };
+        if banned {
+            return UserPermissions { commands: Some(HashSet::new()), models: Some(HashSet::new()), images: false, daily_requests: Some(0), banned };
+        }
+
+        let settings = settings_of(guild_id).await;
+        let (base, rules) = rules_of(guild_id, settings.as_ref());
+        let quotas = settings.as_ref().map_or(&[][..], |settings| &settings.quotas);
+
+        UserPermissions::merged(&base, &rules, user_id, roles).with_quota(quotas, user_id)
+    }
+
+    /// `base` with the `rules` that apply to the member added
+    fn merged(base: &PermissionRule, rules: &[PermissionRule], user_id: u64, roles: &[u64]) -> UserPermissions {
+        rules
+            .iter()
+            .filter(|rule| rule.applies_to(user_id, roles))
+            .fold(UserPermissions::from_rule(base), |permissions, rule| permissions.merge(rule))
+    }
+
+    /// The quota of the user replaces the daily requests, lower or higher
+    fn with_quota(self, quotas: &[UserQuota], user_id: u64) -> UserPermissions {
+        match quotas.iter().find(|quota| quota.user_id == user_id) {
+            Some(quota) => UserPermissions { daily_requests: quota.daily_requests, ..self },
+            None => self,
+        }
+    }
+
+    /// Permissions of the member who used an interaction
+    pub async fn of_member(guild_id: Option<GuildId>, user_id: UserId, member: Option<&Member>) -> UserPermissions {
+        let roles = member.map(|m| role_ids(&m.roles)).unwrap_or_default();
+        UserPermissions::load(guild_id.map_or(0, |id| id.0), user_id.0, &roles).await
+    }
+
+    pub fn allows_command(&self, name: &str) -> bool {
+        self.commands.as_ref().is_none_or(|commands| commands.contains(name))
+    }
+
+    pub fn allows_model(&self, name: &str) -> bool {
+        self.models.as_ref().is_none_or(|models| models.contains(name.trim_matches('"')))
+    }
+
+    /// Checks that the member may send a request to `model` and counts it against their daily quota.
+    /// The error is the reply to the user.
+    pub async fn authorize_request(&self, user_id: u64, model: &str) -> Result<(), String> {
+        let config = config();
+
+        if self.banned {
+            return Err("You are banned from using the bot.".to_string());
+        }
+        if !self.allows_model(model) {
+            return Err(format!(
+                "You are not allowed to use {}, please pick another model with /model.", config.model_label(model.trim_matches('"'))
+            ));
+        }
+
+        self.count_request(user_id).await
+    }
+
+    /// Checks that the member may generate images and counts the request against their daily quota.
+    /// The error is the reply to the user.
+    pub async fn authorize_image_request(&self, user_id: u64) -> Result<(), String> {
+        if self.banned {
+            return Err("You are banned from using the bot.".to_string());
+        }
+        if !self.images {
+            return Err("You are not allowed to generate images.".to_string());
+        }
+
+        self.count_request(user_id).await
+    }
+
+    async fn count_request(&self, user_id: u64) -> Result<(), String> {
+        match DailyUsage::try_increment(user_id, self.daily_requests).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!(
+                "You have used all {} requests for today, the limit resets at midnight UTC.", self.daily_requests.unwrap_or(0)
+            )),
+            Err(e) => Err(format!("Cannot check your quota: {}", e)),
+        }
+    }
+}
+
+/// Stored settings of the guild, none for direct messages (guild 0) or if they can't be read
+async fn settings_of(guild_id: u64) -> Option<GuildSettings> {
+    match GuildSettings::load(guild_id).await {
+        Ok(settings) if guild_id != 0 => Some(settings),
+        _ => None,
+    }
+}
+
+/// The base rule and the other rules of the configuration and the guild
+fn rules_of(guild_id: u64, settings: Option<&GuildSettings>) -> (PermissionRule, Vec<PermissionRule>) {
+    let config = config();
+    let guild_rules = settings.map_or(&[][..], |settings| &settings.permissions);
+
+    split_rules(guild_id, &config.permissions.default, &config.permissions.rules, guild_rules)
+}
+
+/// The @everyone rule of the guild replaces the default of the configuration as the base,
+/// the rules of the configuration come before the other rules of the guild
+fn split_rules(
+    guild_id: u64, default: &PermissionRule, config_rules: &[PermissionRule], guild_rules: &[PermissionRule]
+) -> (PermissionRule, Vec<PermissionRule>) {
+    let base = guild_rules.iter().find(|rule| rule.is_everyone(guild_id)).unwrap_or(default).to_owned();
+    let rules = config_rules
+        .iter()
+        .chain(guild_rules.iter().filter(|rule| !rule.is_everyone(guild_id)))
+        .cloned()
+        .collect();
+
+    (base, rules)
+}
+
+/// Whether the member has the Manage Server permission in the channel of the interaction
+pub fn can_manage_guild(member: Option<&Member>) -> bool {
+    member.and_then(|member| member.permissions).is_some_and(|permissions| permissions.manage_guild())
+}
+
+pub fn role_ids(roles: &[RoleId]) -> Vec<u64> {
+    roles.iter().map(|role| role.0).collect()
+}
+
+/// Commands no rule of the guild or the configuration allows, they are registered for admins only
+pub async fn hidden_commands(guild_id: u64, names: &[&'static str]) -> Vec<&'static str> {
+    let settings = settings_of(guild_id).await;
+    let (base, rules) = rules_of(guild_id, settings.as_ref());
+
+    unallowed_commands(&base, &rules, names)
+}
+
+fn unallowed_commands(base: &PermissionRule, rules: &[PermissionRule], names: &[&'static str]) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|name| {
+            let allows = |commands: &Vec<String>| commands.iter().any(|c| c == *name);
+            !base.commands.as_ref().is_none_or(allows) && !rules.iter().any(|rule| rule.commands.as_ref().is_some_and(allows))
+        })
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    fn set(values: &[&str]) -> Option<HashSet<String>> {
+        Some(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    fn restricted() -> PermissionRule {
+        PermissionRule {
+            commands: strings(&["ping"]),
+            models: strings(&["small"]),
+            images: Some(false),
+            daily_requests: Some(10),
+            ..PermissionRule::default()
+        }
+    }
+
+    #[test]
+    fn rules_of_other_members_are_ignored() {
+        let rules = [PermissionRule { users: vec![2], commands: strings(&["admin"]), ..PermissionRule::default() }];
+
+        let permissions = UserPermissions::merged(&restricted(), &rules, 1, &[]);
+
+        assert_eq!(permissions.commands, set(&["ping"]));
+        assert_eq!(permissions.models, set(&["small"]));
+        assert!(!permissions.images);
+        assert_eq!(permissions.daily_requests, Some(10));
+    }
+
+    #[test]
+    fn applying_rules_add_to_the_base() {
+        let rules = [
+            PermissionRule { roles: vec![5], commands: strings(&["admin"]), daily_requests: Some(50), images: Some(false), ..restricted() },
+            PermissionRule { users: vec![1], models: strings(&["large"]), daily_requests: Some(20), ..restricted() },
+        ];
+
+        let permissions = UserPermissions::merged(&restricted(), &rules, 1, &[5]);
+
+        assert_eq!(permissions.commands, set(&["ping", "admin"]));
+        assert_eq!(permissions.models, set(&["small", "large"]));
+        assert!(!permissions.images);
+        assert_eq!(permissions.daily_requests, Some(50));
+    }
+
+    #[test]
+    fn unset_fields_of_rules_keep_the_base() {
+        let rules = [PermissionRule { roles: vec![5], daily_requests: Some(20), ..PermissionRule::default() }];
+
+        let permissions = UserPermissions::merged(&restricted(), &rules, 1, &[5]);
+
+        assert_eq!(permissions.commands, set(&["ping"]));
+        assert_eq!(permissions.models, set(&["small"]));
+        assert!(!permissions.images);
+        assert_eq!(permissions.daily_requests, Some(20));
+    }
+
+    #[test]
+    fn quota_replaces_the_daily_requests_only() {
+        let rules = [PermissionRule { users: vec![1], daily_requests: Some(50), ..PermissionRule::default() }];
+        let quotas = [UserQuota { user_id: 2, daily_requests: None }, UserQuota { user_id: 1, daily_requests: Some(3) }];
+
+        let permissions = UserPermissions::merged(&restricted(), &rules, 1, &[]).with_quota(&quotas, 1);
+
+        assert_eq!(permissions.commands, set(&["ping"]));
+        assert_eq!(permissions.models, set(&["small"]));
+        assert!(!permissions.images);
+        assert_eq!(permissions.daily_requests, Some(3));
+
+        let permissions = UserPermissions::merged(&restricted(), &rules, 2, &[]).with_quota(&quotas, 2);
+
+        assert_eq!(permissions.daily_requests, None);
+        assert_eq!(permissions.commands, set(&["ping"]));
+    }
+
+    #[test]
+    fn unset_fields_of_the_base_allow_everything() {
+        let permissions = UserPermissions::merged(&PermissionRule::default(), &[restricted()], 1, &[]);
+
+        assert_eq!(permissions.commands, None);
+        assert_eq!(permissions.models, None);
+        assert!(permissions.images);
+        assert_eq!(permissions.daily_requests, None);
+        assert!(permissions.allows_command("anything"));
+        assert!(permissions.allows_model("\"anything\""));
+    }
+
+    #[test]
+    fn everyone_rule_of_the_guild_replaces_the_default() {
+        let config_rules = [PermissionRule { users: vec![1], ..restricted() }];
+        let everyone = PermissionRule { roles: vec![100], ..restricted() };
+        let guild_rules = [everyone.to_owned(), PermissionRule { roles: vec![5], ..restricted() }];
+
+        let (base, rules) = split_rules(100, &PermissionRule::default(), &config_rules, &guild_rules);
+
+        assert_eq!(base, everyone);
+        assert_eq!(rules, [config_rules[0].to_owned(), guild_rules[1].to_owned()]);
+
+        let (base, rules) = split_rules(100, &PermissionRule::default(), &config_rules, &[]);
+
+        assert_eq!(base, PermissionRule::default());
+        assert_eq!(rules, config_rules);
+    }
+
+    #[test]
+    fn commands_no_rule_allows_are_hidden() {
+        let rules = [PermissionRule { roles: vec![5], commands: strings(&["admin"]), ..PermissionRule::default() }];
+
+        assert_eq!(unallowed_commands(&restricted(), &rules, &["ping", "admin", "moderation"]), ["moderation"]);
+    }
+
+    #[test]
+    fn nothing_is_hidden_when_the_base_allows_every_command() {
+        let rules = [PermissionRule { roles: vec![5], ..restricted() }];
+
+        assert!(unallowed_commands(&PermissionRule::default(), &rules, &["ping", "admin"]).is_empty());
+    }
+
+    #[test]
+    fn rules_without_commands_unhide_nothing() {
+        let rules = [PermissionRule { roles: vec![5], daily_requests: Some(20), ..PermissionRule::default() }];
+
+        assert_eq!(unallowed_commands(&restricted(), &rules, &["ping", "admin"]), ["admin"]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::error::Error;
+
+use serde::Serialize;
+
+use crate::utils::{
+    conversations::{now_millis, Conversation, Turn},
+    datastorage::{list_datastorage_dir, User, Users},
+    memory::UserMemory,
+    moderation::{ModerationLog, ModerationRecord},
+    search::remove_index,
+    usage::DailyUsage,
+};
+
+static DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// Replaces the excerpts of the moderation records of a forgotten user
+static REDACTED_EXCERPT: &str = "[deleted with /forget_me]";
+
+/// Everything stored about a user, exported with /my_data export
+#[derive(Debug, Serialize)]
+pub struct UserData {
+    pub user_id: u64,
+    /// Unix time in milliseconds
+    pub exported_at: i64,
+    pub user: Option<User>,
+    pub usage: DailyUsage,
+    pub memory: UserMemory,
+    /// Conversations the user created
+    pub conversations: Vec<Conversation>,
+    /// Messages of the user in conversations of others
+    pub other_messages: Vec<ThreadMessages>,
+    pub moderation_records: Vec<GuildModerationRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreadMessages {
+    pub thread_id: u64,
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GuildModerationRecord {
+    pub guild_id: u64,
+    #[serde(flatten)]
+    pub record: ModerationRecord,
+}
+
+/// What /forget_me deleted
+pub struct ForgetReport {
+    pub conversations: usize,
+    /// Conversations of others the messages of the user were removed from
+    pub other_threads: usize,
+    /// The ban is kept, only the rest of the user record is reset
+    pub banned: bool,
+}
+
+pub async fn collect_user_data(user_id: u64) -> Result<UserData, Box<dyn Error + Send + Sync>> {
+    let user = match Users::default().await {
+        Ok(users) => users.find_user_by_id(user_id).cloned(),
+        Err(e) => return Err(e.to_string().into())
+    };
+
+    let mut conversations = vec![];
+    let mut other_messages = vec![];
+    for thread_id in Conversation::list_ids().await? {
+        let conversation = match Conversation::load(thread_id).await? {
+            Some(v) => v,
+            None => continue
+        };
+
+        if conversation.owner_id == user_id {
+            conversations.push(conversation);
+            continue;
+        }
+
+        let turns: Vec<Turn> = conversation.turns.into_iter().filter(|turn| turn.author_id == user_id).collect();
+        if !turns.is_empty() {
+            other_messages.push(ThreadMessages { thread_id, turns });
+        }
+    }
+
+    let mut moderation_records = vec![];
+    for name in list_datastorage_dir("moderation").await? {
+        let guild_id = match name.parse() {
+            Ok(v) => v,
+            Err(_) => continue
+        };
+
+        let log = ModerationLog::load(guild_id).await?;
+        moderation_records.extend(
+            log.records
+                .into_iter()
+                .filter(|record| record.user_id == user_id)
+                .map(|record| GuildModerationRecord { guild_id, record })
+        );
+    }
+
+    Ok(UserData {
+        user_id,
+        exported_at: now_millis(),
+        user,
+        usage: DailyUsage::load(user_id).await?,
+        memory: UserMemory::load(user_id).await?,
+        conversations,
+        other_messages,
+        moderation_records,
+    })
+}
+
+/// Deletes the user record, the conversations, the memory and the usage of the user,
+/// removes their messages from the conversations of others and redacts their moderation records
+pub async fn forget_user(user_id: u64) -> Result<ForgetReport, Box<dyn Error + Send + Sync>> {
+    let mut conversations = 0;
+    let mut other_threads = 0;
+    for thread_id in Conversation::list_ids().await? {
+        let conversation = match Conversation::load(thread_id).await? {
+            Some(v) => v,
+            None => continue
+        };
+
+        if conversation.owner_id == user_id {
+            Conversation::delete(thread_id).await?;
+            conversations += 1;
+            continue;
+        }
+
+        let removed = Conversation::update(thread_id, |conversation| {
+            let count = conversation.turns.len();
+            conversation.turns.retain(|turn| turn.author_id != user_id);
+            for turn in &mut conversation.turns {
+                turn.ratings.retain(|rating| rating.user_id != user_id);
+            }
+
+            // The summary may quote the removed messages, it is written again from the rest.
+            let removed = conversation.turns.len() != count;
+            if removed {
+                conversation.summary = None;
+            }
+            removed
+        }).await?;
+
+        if removed == Some(true) {
+            // The index is a cache of the turn embeddings, the next search builds it again.
+            remove_index(thread_id).await?;
+            other_threads += 1;
+        }
+    }
+
+    for name in list_datastorage_dir("moderation").await? {
+        let guild_id = match name.parse() {
+            Ok(v) => v,
+            Err(_) => continue
+        };
+
+        ModerationLog::update_records(guild_id, |records| {
+            for record in records.iter_mut().filter(|record| record.user_id == user_id) {
+                record.excerpt = REDACTED_EXCERPT.to_owned();
+            }
+        }).await?;
+    }
+
+    UserMemory::delete(user_id).await?;
+    DailyUsage::delete(user_id).await?;
+
+    // A ban outlives the deletion, so deleting the data is no way around it.
+    let banned = Users::update(|users| {
+        let banned = users.find_user_by_id(user_id).is_some_and(|user| user.banned);
+        users.delete_user(user_id);
+        if banned {
+            users.set_banned(user_id, true);
+        }
+        banned
+    }).await?;
+
+    Ok(ForgetReport { conversations, other_threads, banned })
+}
+
+/// Deletes the conversations without a message for `retention_days`, returns how many
+pub async fn purge_expired_conversations(retention_days: u64) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    // The configuration bounds the days, a wrong value keeps everything instead of wrapping around.
+    let cutoff = match i64::try_from(retention_days).ok().and_then(|days| days.checked_mul(DAY_MILLIS)) {
+        Some(v) => now_millis() - v,
+        None => return Ok(0)
+    };
+
+    let mut purged = 0;
+    for thread_id in Conversation::list_ids().await? {
+        let last_activity = match Conversation::load(thread_id).await? {
+            Some(v) => v.turns.last().map_or(v.created_at, |turn| turn.timestamp),
+            None => continue
+        };
+
+        if last_activity < cutoff {
+            Conversation::delete(thread_id).await?;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    conversations::{Conversation, Turn, TurnRole},
+    datastorage::{read_datastorage_file, remove_datastorage_file, write_datastorage_file},
+    embeddings::{cosine_similarity, fnv1a, vector_bytes, Embedder},
+};
+
+static SEARCH_FOLDER: &str = "search";
+
+/// Constant of the reciprocal rank fusion, 60 keeps the top of one ranking from outweighing the other
+static RRF_K: f32 = 60.0;
+/// Turns taken from the similarity ranking
+static SIMILARITY_CANDIDATES: usize = 50;
+/// Longer turns are cut in the results
+static EXCERPT_CHARS: usize = 150;
+
+/// Cached embeddings of the turns of one conversation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct SearchIndex {
+    thread_id: u64,
+    /// `Embedder::id` of the vectors, the index is rebuilt when it changes
+    embedder: String,
+    entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct IndexEntry {
+    /// `fnv1a` of the turn content, so edited turns get embedded again
+    hash: i64,
+    #[serde(with = "vector_bytes")]
+    vector: Vec<f32>,
+}
+
+fn index_file(thread_id: u64) -> String {
+    format!("{}/{}.bson", SEARCH_FOLDER, thread_id)
+}
+
+/// Removes the cached embeddings of a deleted conversation
+pub async fn remove_index(thread_id: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    remove_datastorage_file(&index_file(thread_id)).await
+}
+
+/// Conversations a search looks through
+pub enum SearchScope {
+    /// Threads owned by the user
+    Owner(u64),
+    /// Every thread of the guild, except the private threads of other users
+    Guild { guild_id: u64, user_id: u64 },
+}
+
+impl SearchScope {
+    fn includes(&self, conversation: &Conversation) -> bool {
+        match self {
+            SearchScope::Owner(user_id) => conversation.owner_id == *user_id,
+            SearchScope::Guild { guild_id, user_id } => {
+                conversation.guild_id == *guild_id && (!conversation.private || conversation.owner_id == *user_id)
+            },
+        }
+    }
+}
+
+/// A message found by the search
+pub struct SearchHit {
+    pub thread_id: u64,
+    pub guild_id: u64,
+    /// 0 for imported turns that were never posted
+    pub message_id: u64,
+    pub title: String,
+    pub role: TurnRole,
+    pub excerpt: String,
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+}
+
+impl SearchHit {
+    /// Link to the message, or to the thread if the turn has no message
+    pub fn link(&self) -> String {
+        let guild = if self.guild_id == 0 { "@me".to_string() } else { self.guild_id.to_string() };
+
+        if self.message_id == 0 {
+            format!("https://discord.com/channels/{}/{}", guild, self.thread_id)
+        } else {
+            format!("https://discord.com/channels/{}/{}/{}", guild, self.thread_id, self.message_id)
+        }
+    }
+}
+
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    /// Why the results are ranked by the full-text match only
+    pub similarity_error: Option<String>,
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_owned())
+        .collect()
+}
+
+/// Share of the query words found in the content (as prefixes of its words), plus 1 for the whole phrase
+fn text_score(query_words: &[String], phrase: &str, content: &str) -> f32 {
+    if query_words.is_empty() {
+        return 0.0;
+    }
+
+    let content_words: HashSet<String> = words(content).into_iter().collect();
+    let found = query_words
+        .iter()
+        .filter(|word| content_words.iter().any(|content_word| content_word.starts_with(word.as_str())))
+        .count();
+
+    let mut score = found as f32 / query_words.len() as f32;
+    if found > 0 && query_words.len() > 1 && content.to_lowercase().contains(phrase) {
+        score += 1.0;
+    }
+
+    score
+}
+
+fn is_searchable(turn: &Turn) -> bool {
+    turn.role != TurnRole::System && !turn.content.trim().is_empty()
+}
+
+/// Vectors of the searchable turns of the conversation, embedding the turns missing in its index
+async fn turn_vectors(conversation: &Conversation, embedder: &Embedder) -> Result<Vec<Vec<f32>>, Box<dyn Error + Send + Sync>> {
+    let file = index_file(conversation.thread_id);
+
+    let mut index = match read_datastorage_file::<SearchIndex>(&file).await? {
+        Some(v) if v.embedder == embedder.id() => v,
+        _ => SearchIndex { thread_id: conversation.thread_id, embedder: embedder.id(), entries: vec![] },
+    };
+
+    let turns: Vec<&Turn> = conversation.turns.iter().filter(|turn| is_searchable(turn)).collect();
+    let hashes: Vec<i64> = turns.iter().map(|turn| fnv1a(&turn.content) as i64).collect();
+
+    let mut known: HashMap<i64, Vec<f32>> = index.entries.drain(..).map(|entry| (entry.hash, entry.vector)).collect();
+
+    let mut missing_hashes = vec![];
+    let mut missing_texts = vec![];
+    for (turn, hash) in turns.iter().zip(&hashes) {
+        if !known.contains_key(hash) && !missing_hashes.contains(hash) {
+            missing_hashes.push(*hash);
+            missing_texts.push(turn.content.to_owned());
+        }
+    }
+
+    let changed = !missing_texts.is_empty() || known.len() != hashes.iter().collect::<HashSet<_>>().len();
+
+    if !missing_texts.is_empty() {
+        let vectors = embedder.embed(&missing_texts).await?;
+        known.extend(missing_hashes.into_iter().zip(vectors));
+    }
+
+    let vectors: Vec<Vec<f32>> = hashes.iter().map(|hash| known.get(hash).cloned().unwrap_or_default()).collect();
+
+    if changed {
+        // Vectors of edited and deleted turns are dropped.
+        index.entries = hashes
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|hash| known.remove(hash).map(|vector| IndexEntry { hash: *hash, vector }))
+            .collect();
+        write_datastorage_file(&file, &index).await?;
+    }
+
+    Ok(vectors)
+}
+
+/// Searches the stored conversations in `scope`. The full-text and the similarity rankings
+/// are merged with reciprocal rank fusion, the best `limit` turns are returned.
+pub async fn search_conversations(
+    query: &str, scope: &SearchScope, limit: usize
+) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+    let query_words = words(query);
+    let phrase = query.trim().to_lowercase();
+
+    let mut conversations = vec![];
+    for thread_id in Conversation::list_ids().await? {
+        if let Some(conversation) = Conversation::load(thread_id).await? {
+            if scope.includes(&conversation) {
+                conversations.push(conversation);
+            }
+        }
+    }
+
+    // (conversation, turn) of every candidate
+    let candidates: Vec<(usize, usize)> = conversations
+        .iter()
+        .enumerate()
+        .flat_map(|(c, conversation)| {
+            conversation.turns
+                .iter()
+                .enumerate()
+                .filter(|(_, turn)| is_searchable(turn))
+                .map(move |(t, _)| (c, t))
+        })
+        .collect();
+
+    let mut text_ranking: Vec<(usize, f32)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, (c, t))| (i, text_score(&query_words, &phrase, &conversations[*c].turns[*t].content)))
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+    text_ranking.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let embedder = Embedder::from_config();
+    let mut similarity_error = None;
+    let mut similarity_ranking: Vec<(usize, f32)> = vec![];
+
+    match embedder.embed(&[query.to_owned()]).await {
+        Ok(mut query_vector) => {
+            let query_vector = query_vector.pop().unwrap_or_default();
+            let mut i = 0;
+
+            for conversation in &conversations {
+                let vectors = match turn_vectors(conversation, &embedder).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        similarity_error = Some(e.to_string());
+                        similarity_ranking.clear();
+                        break;
+                    }
+                };
+
+                for vector in vectors {
+                    similarity_ranking.push((i, cosine_similarity(&query_vector, &vector)));
+                    i += 1;
+                }
+            }
+        },
+        Err(e) => similarity_error = Some(e),
+    }
+
+    similarity_ranking.retain(|(_, score)| *score > 0.0);
+    similarity_ranking.sort_by(|a, b| b.1.total_cmp(&a.1));
+    similarity_ranking.truncate(SIMILARITY_CANDIDATES);
+
+    let mut fused: HashMap<usize, f32> = HashMap::new();
+    for ranking in [&text_ranking, &similarity_ranking] {
+        for (rank, (i, _)) in ranking.iter().enumerate() {
+            *fused.entry(*i).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+
+    let mut ranked: Vec<(usize, f32)> = fused.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+
+    let hits = ranked
+        .into_iter()
+        .take(limit)
+        .map(|(i, _)| {
+            let (c, t) = candidates[i];
+            let conversation = &conversations[c];
+            let turn = &conversation.turns[t];
+
+            let mut excerpt: String = turn.content.chars().take(EXCERPT_CHARS).collect();
+            if turn.content.chars().count() > EXCERPT_CHARS {
+                excerpt.push('…');
+            }
+
+            SearchHit {
+                thread_id: conversation.thread_id,
+                guild_id: conversation.guild_id,
+                message_id: turn.message_id,
+                title: conversation.title.to_owned(),
+                role: turn.role,
+                excerpt,
+                timestamp: turn.timestamp,
+            }
+        })
+        .collect();
+
+    Ok(SearchResults { hits, similarity_error })
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+/// Coordinates a graceful stop of the bot: new messages are refused,
+/// requests already in flight get time to finish before the shards are closed.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    running: AtomicBool,
+    /// Amount of in-flight requests per channel
+    in_flight: Mutex<HashMap<u64, usize>>,
+    requested_notify: Notify,
+    drained_notify: Notify,
+    stopped_notify: Notify,
+}
+
+/// Marks a request as in flight until dropped
+pub struct InFlightGuard {
+    shutdown: Arc<Shutdown>,
+    channel_id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self.shutdown.in_flight.lock().unwrap();
+
+        if let Some(count) = in_flight.get_mut(&self.channel_id) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.channel_id);
+            }
+        }
+
+        if in_flight.is_empty() {
+            self.shutdown.drained_notify.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Registers a new request in `channel_id`, returns `None` once the shutdown was requested
+    pub fn begin_request(self: &Arc<Self>, channel_id: u64) -> Option<InFlightGuard> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+
+        // Checked under the lock, so `wait_drained` can not miss a request started concurrently.
+        if self.is_requested() {
+            return None;
+        }
+
+        *in_flight.entry(channel_id).or_insert(0) += 1;
+
+        Some(InFlightGuard { shutdown: Arc::clone(self), channel_id })
+    }
+
+    pub fn request(&self) {
+        let _in_flight = self.in_flight.lock().unwrap();
+
+        if !self.requested.swap(true, Ordering::SeqCst) {
+            self.requested_notify.notify_one();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub async fn wait_requested(&self) {
+        if !self.is_requested() {
+            self.requested_notify.notified().await;
+        }
+    }
+
+    /// Channels that still have requests in flight
+    pub fn pending_channels(&self) -> Vec<u64> {
+        self.in_flight.lock().unwrap().keys().copied().collect()
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.lock().unwrap().values().sum()
+    }
+
+    /// Waits for all in-flight requests, returns `false` if `timeout` passed first
+    pub async fn wait_drained(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            loop {
+                let drained = self.drained_notify.notified();
+                if self.in_flight.lock().unwrap().is_empty() {
+                    return;
+                }
+                drained.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    pub fn set_running(&self) {
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn set_stopped(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.stopped_notify.notify_waiters();
+    }
+
+    pub async fn wait_stopped(&self) {
+        let stopped = self.stopped_notify.notified();
+        if self.is_running() {
+            stopped.await;
+        }
+    }
+}
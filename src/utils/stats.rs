@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDate};
+
+/// Width of one sparkline bucket
+static RATE_BUCKET: Duration = Duration::from_secs(10);
+/// Amount of buckets kept for the request rate sparkline
+static RATE_BUCKETS_COUNT: usize = 60;
+/// A thread counts as active if it got a message within this window
+static ACTIVE_THREAD_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Counters shown on the TUI dashboard, updated from the `Handler`
+pub struct Stats {
+    shard_status: Mutex<String>,
+    shard_latency: Mutex<Option<Duration>>,
+    guilds: AtomicUsize,
+    threads: Mutex<HashMap<u64, Instant>>,
+    message_times: Mutex<VecDeque<Instant>>,
+    llm_requests: AtomicU64,
+    llm_latency_total_ms: AtomicU64,
+    tokens_today: Mutex<(NaiveDate, u64)>,
+    image_requests: AtomicU64,
+    errors: AtomicU64,
+    rate_buckets: Mutex<(Instant, VecDeque<u64>)>,
+}
+
+/// Copy of the counters taken at a single moment, used for rendering
+pub struct StatsSnapshot {
+    pub shard_status: String,
+    pub shard_latency: Option<Duration>,
+    pub guilds: usize,
+    pub active_threads: usize,
+    pub messages_per_minute: usize,
+    pub llm_requests: u64,
+    pub average_llm_latency: Option<Duration>,
+    pub tokens_today: u64,
+    pub image_requests: u64,
+    pub errors: u64,
+    pub request_rate: Vec<u64>,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            shard_status: Mutex::new("not started".to_owned()),
+            shard_latency: Mutex::new(None),
+            guilds: AtomicUsize::new(0),
+            threads: Mutex::new(HashMap::new()),
+            message_times: Mutex::new(VecDeque::new()),
+            llm_requests: AtomicU64::new(0),
+            llm_latency_total_ms: AtomicU64::new(0),
+            tokens_today: Mutex::new((Local::now().date_naive(), 0)),
+            image_requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            rate_buckets: Mutex::new((Instant::now(), VecDeque::from(vec![0; RATE_BUCKETS_COUNT]))),
+        }
+    }
+}
+
+impl Stats {
+    pub fn set_shard_status(&self, status: &str, latency: Option<Duration>) {
+        *self.shard_status.lock().unwrap() = status.to_owned();
+        *self.shard_latency.lock().unwrap() = latency;
+    }
+
+    pub fn set_guilds(&self, count: usize) {
+        self.guilds.store(count, Ordering::Relaxed);
+    }
+
+    /// Registers a message handled in a bot thread
+    pub fn record_message(&self, thread_id: u64) {
+        let now = Instant::now();
+
+        self.threads.lock().unwrap().insert(thread_id, now);
+        self.message_times.lock().unwrap().push_back(now);
+
+        let mut buckets = self.rate_buckets.lock().unwrap();
+        Self::rotate_buckets(&mut buckets, now);
+        if let Some(last) = buckets.1.back_mut() {
+            *last += 1;
+        }
+    }
+
+    pub fn record_llm_request(&self, latency: Duration, total_tokens: u32) {
+        self.llm_requests.fetch_add(1, Ordering::Relaxed);
+        self.llm_latency_total_ms.fetch_add(latency.as_millis() as u64, Ordering::Relaxed);
+
+        let today = Local::now().date_naive();
+        let mut tokens = self.tokens_today.lock().unwrap();
+        if tokens.0 != today {
+            *tokens = (today, 0);
+        }
+        tokens.1 += total_tokens as u64;
+    }
+
+    pub fn record_image_request(&self) {
+        self.image_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let now = Instant::now();
+
+        let messages_per_minute = {
+            let mut times = self.message_times.lock().unwrap();
+            while times.front().is_some_and(|t| now.duration_since(*t) > Duration::from_secs(60)) {
+                times.pop_front();
+            }
+            times.len()
+        };
+
+        let active_threads = {
+            let mut threads = self.threads.lock().unwrap();
+            threads.retain(|_, t| now.duration_since(*t) <= ACTIVE_THREAD_WINDOW);
+            threads.len()
+        };
+
+        let request_rate = {
+            let mut buckets = self.rate_buckets.lock().unwrap();
+            Self::rotate_buckets(&mut buckets, now);
+            buckets.1.iter().copied().collect()
+        };
+
+        let llm_requests = self.llm_requests.load(Ordering::Relaxed);
+        let average_llm_latency = match llm_requests {
+            0 => None,
+            n => Some(Duration::from_millis(self.llm_latency_total_ms.load(Ordering::Relaxed) / n)),
+        };
+
+        let tokens_today = {
+            let tokens = self.tokens_today.lock().unwrap();
+            if tokens.0 == Local::now().date_naive() { tokens.1 } else { 0 }
+        };
+
+        StatsSnapshot {
+            shard_status: self.shard_status.lock().unwrap().to_owned(),
+            shard_latency: *self.shard_latency.lock().unwrap(),
+            guilds: self.guilds.load(Ordering::Relaxed),
+            active_threads,
+            messages_per_minute,
+            llm_requests,
+            average_llm_latency,
+            tokens_today,
+            image_requests: self.image_requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            request_rate,
+        }
+    }
+
+    /// Shifts the sparkline buckets so that the last one covers the current moment
+    fn rotate_buckets(buckets: &mut (Instant, VecDeque<u64>), now: Instant) {
+        while now.duration_since(buckets.0) >= RATE_BUCKET {
+            buckets.0 += RATE_BUCKET;
+            buckets.1.pop_front();
+            buckets.1.push_back(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(start: Instant, counts: &[u64]) -> (Instant, VecDeque<u64>) {
+        (start, counts.iter().copied().collect())
+    }
+
+    #[test]
+    fn rotate_buckets_keeps_the_current_bucket() {
+        let start = Instant::now();
+        let mut rate = buckets(start, &[1, 2, 3]);
+
+        Stats::rotate_buckets(&mut rate, start + RATE_BUCKET / 2);
+
+        assert_eq!(rate.0, start);
+        assert_eq!(rate.1, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rotate_buckets_shifts_one_bucket_per_interval() {
+        let start = Instant::now();
+        let mut rate = buckets(start, &[1, 2, 3]);
+
+        Stats::rotate_buckets(&mut rate, start + RATE_BUCKET * 2);
+
+        assert_eq!(rate.0, start + RATE_BUCKET * 2);
+        assert_eq!(rate.1, [3, 0, 0]);
+    }
+
+    #[test]
+    fn rotate_buckets_clears_everything_after_a_long_pause() {
+        let start = Instant::now();
+        let mut rate = buckets(start, &[1, 2, 3]);
+
+        Stats::rotate_buckets(&mut rate, start + RATE_BUCKET * 10 + RATE_BUCKET / 2);
+
+        assert_eq!(rate.0, start + RATE_BUCKET * 10);
+        assert_eq!(rate.1, [0, 0, 0]);
+    }
+
+    #[test]
+    fn snapshot_counts_recorded_messages() {
+        let stats = Stats::default();
+        stats.record_message(1);
+        stats.record_message(1);
+        stats.record_message(2);
+
+        let snapshot = stats.snapshot();
+
+        assert_eq!(snapshot.messages_per_minute, 3);
+        assert_eq!(snapshot.active_threads, 2);
+        assert_eq!(snapshot.request_rate.len(), RATE_BUCKETS_COUNT);
+        assert_eq!(snapshot.request_rate.last(), Some(&3));
+    }
+}
@@ -0,0 +1,60 @@
+use std::error::Error;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::datastorage::{read_datastorage_file, remove_datastorage_file, update_datastorage_file};
+
+static USAGE_FOLDER: &str = "usage";
+
+/// Requests of a user to the chat model on the current day (UTC)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DailyUsage {
+    pub user_id: u64,
+    /// `%Y-%m-%d` of the counted day
+    pub date: String,
+    pub requests: u64,
+}
+
+fn usage_file(user_id: u64) -> String {
+    format!("{}/{}.bson", USAGE_FOLDER, user_id)
+}
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+impl DailyUsage {
+    /// Usage of the user today, counters of earlier days start over
+    pub async fn load(user_id: u64) -> Result<DailyUsage, Box<dyn Error + Send + Sync>> {
+        let today = today();
+
+        Ok(match read_datastorage_file::<DailyUsage>(&usage_file(user_id)).await? {
+            Some(v) if v.date == today => v,
+            _ => DailyUsage { user_id, date: today, requests: 0 },
+        })
+    }
+
+    /// Counts one request if the user stays within `limit`, returns whether it was counted
+    pub async fn try_increment(user_id: u64, limit: Option<u64>) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let today = today();
+
+        update_datastorage_file(&usage_file(user_id), |usage: &mut Option<DailyUsage>| {
+            let usage = match usage {
+                Some(v) if v.date == today => v,
+                _ => usage.insert(DailyUsage { user_id, date: today, requests: 0 }),
+            };
+
+            if limit.is_some_and(|limit| usage.requests >= limit) {
+                return false;
+            }
+
+            usage.requests += 1;
+            true
+        }).await
+    }
+
+    pub async fn delete(user_id: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        remove_datastorage_file(&usage_file(user_id)).await
+    }
+}